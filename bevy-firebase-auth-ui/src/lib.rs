@@ -0,0 +1,808 @@
+use std::collections::HashMap;
+
+use bevy::{
+    ecs::system::EntityCommands,
+    input::{keyboard::KeyboardInput, ButtonState},
+    prelude::*,
+};
+use bevy_firebase_auth::{
+    AuthErrorEvent, AuthState, AuthUrlsEvent, FinalizeSecondFactor, LoginProvider, MfaFactorKind,
+    SecondFactorRequired, SelectedProvider, SignInWithPassword,
+};
+use url::Url;
+
+const SPINNER_FRAMES: [&str; 4] = ["|", "/", "-", "\\"];
+
+/// Colours for one kind of button, by interaction
+#[derive(Clone, Debug)]
+pub struct ButtonColors {
+    pub normal: Color,
+    pub hovered: Color,
+    pub pressed: Color,
+}
+
+/// Bevy `Resource` controlling how the login screen looks
+///
+/// # Examples
+///
+/// Using the game's font, and offering email/password login:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth_ui::*;
+/// fn setup_login_theme(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     commands.insert_resource(AuthUiTheme {
+///         font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+///         title: "My Game".into(),
+///         email_password: true,
+///         ..Default::default()
+///     });
+/// }
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct AuthUiTheme {
+    pub font: Handle<Font>,
+    pub title: String,
+    pub title_size: f32,
+    pub text_size: f32,
+    pub text_color: Color,
+    pub error_color: Color,
+    /// Fills the whole screen behind the login form
+    pub background_color: Color,
+    pub button_colors: ButtonColors,
+    /// Style of every button, including the provider buttons
+    pub button_style: Style,
+    pub input_color: Color,
+    pub placeholder_color: Color,
+    /// Button text for each provider. Defaults to "log in with <provider>"
+    pub provider_labels: HashMap<LoginProvider, String>,
+    /// Show the email/password form. Needs the Email/Password provider
+    /// enabled in the Firebase console
+    pub email_password: bool,
+}
+
+impl Default for AuthUiTheme {
+    fn default() -> Self {
+        AuthUiTheme {
+            font: Default::default(),
+            title: "login".into(),
+            title_size: 40.,
+            text_size: 20.,
+            text_color: Color::rgb(0.9, 0.9, 0.9),
+            error_color: Color::rgb(0.9, 0.3, 0.3),
+            background_color: Color::rgb(0.1, 0.1, 0.1),
+            button_colors: ButtonColors {
+                normal: Color::rgb(0.15, 0.15, 0.15),
+                hovered: Color::rgb(0.25, 0.25, 0.25),
+                pressed: Color::rgb(0.35, 0.75, 0.35),
+            },
+            button_style: Style {
+                width: Val::Px(300.),
+                height: Val::Px(50.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                margin: UiRect::top(Val::Px(10.)),
+                ..default()
+            },
+            input_color: Color::rgb(0.2, 0.2, 0.2),
+            placeholder_color: Color::rgb(0.5, 0.5, 0.5),
+            provider_labels: HashMap::new(),
+            email_password: false,
+        }
+    }
+}
+
+impl AuthUiTheme {
+    fn text(&self, value: impl Into<String>) -> TextBundle {
+        TextBundle::from_section(
+            value,
+            TextStyle {
+                font: self.font.clone(),
+                font_size: self.text_size,
+                color: self.text_color,
+            },
+        )
+    }
+
+    fn button(&self) -> ButtonBundle {
+        ButtonBundle {
+            style: self.button_style.clone(),
+            background_color: self.button_colors.normal.into(),
+            ..default()
+        }
+    }
+
+    fn provider_label(&self, provider: &LoginProvider) -> String {
+        match self.provider_labels.get(provider) {
+            Some(label) => label.clone(),
+            None => format!("log in with {:?}", provider),
+        }
+    }
+}
+
+/// Plugin for a ready made login screen
+///
+/// Shown whenever `AuthState::LogIn` is entered, and removed once logged in.
+/// Builds a button for each provider in `AuthUrlsEvent`, shows a spinner
+/// while the browser login is in progress, asks for an authenticator code on
+/// `SecondFactorRequired`, and displays `AuthErrorEvent` messages. Style it
+/// with the `AuthUiTheme` resource.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth_ui::*;
+/// # let mut app = App::new();
+/// app.add_plugins(AuthUiPlugin);
+/// ```
+pub struct AuthUiPlugin;
+
+impl Plugin for AuthUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AuthUiTheme>()
+            .init_resource::<LoginScreenMode>()
+            .init_resource::<PendingFactor>()
+            .add_systems(OnEnter(AuthState::LogIn), build_login_screen)
+            .add_systems(OnEnter(AuthState::GotAuthCode), show_signing_in)
+            .add_systems(OnEnter(AuthState::LoggedIn), despawn_login_screen)
+            .add_systems(OnEnter(AuthState::LogOut), despawn_login_screen)
+            .add_systems(OnEnter(AuthState::LoggedOut), despawn_login_screen)
+            .add_systems(
+                Update,
+                (
+                    add_provider_buttons,
+                    provider_button_system,
+                    cancel_button_system,
+                    password_button_system,
+                    show_second_factor,
+                    verify_button_system,
+                    show_auth_errors,
+                    apply_screen_mode,
+                    focus_text_input,
+                    text_input_system,
+                    update_text_input_display,
+                    spin_spinner,
+                    button_color_system,
+                ),
+            );
+    }
+}
+
+// Components
+
+/// Root node of the login screen
+#[derive(Component)]
+pub struct LoginScreen;
+
+#[derive(Component)]
+struct ProviderPanel;
+
+#[derive(Component)]
+struct PasswordForm;
+
+#[derive(Component)]
+struct WaitingPanel;
+
+#[derive(Component)]
+struct SecondFactorPanel;
+
+#[derive(Component)]
+struct WaitingText;
+
+#[derive(Component)]
+struct ErrorText;
+
+#[derive(Component)]
+struct Spinner {
+    timer: Timer,
+    frame: usize,
+}
+
+#[derive(Component)]
+struct ThemedButton;
+
+#[derive(Component)]
+struct ProviderButton {
+    provider: LoginProvider,
+    url: Url,
+}
+
+#[derive(Component)]
+struct CancelButton;
+
+#[derive(Component)]
+struct PasswordButton {
+    create_account: bool,
+}
+
+#[derive(Component)]
+struct VerifyButton;
+
+#[derive(Component, Default)]
+struct TextInput {
+    value: String,
+    placeholder: String,
+    masked: bool,
+}
+
+#[derive(Component)]
+struct EmailInput;
+
+#[derive(Component)]
+struct PasswordInput;
+
+#[derive(Component)]
+struct CodeInput;
+
+#[derive(Component)]
+struct Focused;
+
+/// Which part of the login screen is showing
+#[derive(Resource, Default, PartialEq, Eq)]
+enum LoginScreenMode {
+    /// Provider buttons and the email/password form
+    #[default]
+    Choosing,
+    Waiting,
+    /// Asking for a code, for the held enrollment ID
+    SecondFactor(String),
+}
+
+/// Enrollment ID of the factor being asked for, kept while verifying
+#[derive(Resource, Default)]
+struct PendingFactor(Option<String>);
+
+// Building
+
+fn build_login_screen(
+    mut commands: Commands,
+    theme: Res<AuthUiTheme>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut pending_factor: ResMut<PendingFactor>,
+    q_screen: Query<(), With<LoginScreen>>,
+) {
+    *mode = LoginScreenMode::Choosing;
+    pending_factor.0 = None;
+
+    // Back from a failed sign in. The screen stays, with its error, and gets
+    // buttons for the new auth URLs
+    if !q_screen.is_empty() {
+        return;
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            background_color: theme.background_color.into(),
+            z_index: ZIndex::Global(100),
+            ..default()
+        })
+        .insert(LoginScreen)
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                theme.title.clone(),
+                TextStyle {
+                    font: theme.font.clone(),
+                    font_size: theme.title_size,
+                    color: theme.text_color,
+                },
+            ));
+
+            // Filled in by `add_provider_buttons`
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(ProviderPanel);
+
+            if theme.email_password {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            margin: UiRect::top(Val::Px(20.)),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .insert(PasswordForm)
+                    .with_children(|parent| {
+                        spawn_text_input(parent, &theme, "email", false).insert(EmailInput);
+                        spawn_text_input(parent, &theme, "password", true).insert(PasswordInput);
+
+                        spawn_button(parent, &theme, "sign in").insert(PasswordButton {
+                            create_account: false,
+                        });
+                        spawn_button(parent, &theme, "create account").insert(PasswordButton {
+                            create_account: true,
+                        });
+                    });
+            }
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(WaitingPanel)
+                .with_children(|parent| {
+                    parent.spawn(theme.text(SPINNER_FRAMES[0])).insert(Spinner {
+                        timer: Timer::from_seconds(0.15, TimerMode::Repeating),
+                        frame: 0,
+                    });
+                    parent
+                        .spawn(theme.text("waiting for browser..."))
+                        .insert(WaitingText);
+                    spawn_button(parent, &theme, "cancel").insert(CancelButton);
+                });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(SecondFactorPanel)
+                .with_children(|parent| {
+                    parent.spawn(theme.text("enter the code from your authenticator app"));
+                    spawn_text_input(parent, &theme, "code", false).insert(CodeInput);
+                    spawn_button(parent, &theme, "verify").insert(VerifyButton);
+                    spawn_button(parent, &theme, "cancel").insert(CancelButton);
+                });
+
+            parent
+                .spawn(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: theme.font.clone(),
+                        font_size: theme.text_size,
+                        color: theme.error_color,
+                    },
+                ))
+                .insert(ErrorText);
+        });
+}
+
+fn spawn_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    theme: &AuthUiTheme,
+    label: &str,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut button = parent.spawn(theme.button());
+    button.insert(ThemedButton).with_children(|parent| {
+        parent.spawn(theme.text(label));
+    });
+    button
+}
+
+fn spawn_text_input<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    theme: &AuthUiTheme,
+    placeholder: &str,
+    masked: bool,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut input = parent.spawn(ButtonBundle {
+        style: Style {
+            justify_content: JustifyContent::FlexStart,
+            padding: UiRect::horizontal(Val::Px(10.)),
+            ..theme.button_style.clone()
+        },
+        background_color: theme.input_color.into(),
+        ..default()
+    });
+    input
+        .insert(TextInput {
+            placeholder: placeholder.into(),
+            masked,
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(theme.text(placeholder));
+        });
+    input
+}
+
+fn despawn_login_screen(mut commands: Commands, q_screen: Query<Entity, With<LoginScreen>>) {
+    for entity in q_screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Auth events
+
+fn add_provider_buttons(
+    mut commands: Commands,
+    mut er: EventReader<AuthUrlsEvent>,
+    q_panel: Query<Entity, With<ProviderPanel>>,
+    theme: Res<AuthUiTheme>,
+) {
+    // Keep the event until the screen has been built
+    let Ok(panel) = q_panel.get_single() else {
+        return;
+    };
+
+    for e in er.iter() {
+        let mut panel = commands.entity(panel);
+        panel.despawn_descendants();
+
+        // Sorted so the buttons don't shuffle between logins
+        let mut auth_urls: Vec<_> = e.0.iter().collect();
+        auth_urls.sort_by_key(|(provider, _)| format!("{:?}", provider));
+
+        panel.with_children(|parent| {
+            for (provider, url) in auth_urls {
+                spawn_button(parent, &theme, &theme.provider_label(provider)).insert(
+                    ProviderButton {
+                        provider: provider.clone(),
+                        url: url.clone(),
+                    },
+                );
+            }
+        });
+    }
+}
+
+fn show_signing_in(
+    mut mode: ResMut<LoginScreenMode>,
+    mut q_text: Query<&mut Text, With<WaitingText>>,
+) {
+    *mode = LoginScreenMode::Waiting;
+
+    for mut text in q_text.iter_mut() {
+        text.sections[0].value = "signing in...".into();
+    }
+}
+
+fn show_auth_errors(
+    mut er: EventReader<AuthErrorEvent>,
+    mut mode: ResMut<LoginScreenMode>,
+    pending_factor: Res<PendingFactor>,
+    mut q_text: Query<&mut Text, With<ErrorText>>,
+) {
+    for e in er.iter() {
+        // A wrong code can be tried again
+        if *mode == LoginScreenMode::Waiting {
+            *mode = match pending_factor.0.clone() {
+                Some(enrollment_id) => LoginScreenMode::SecondFactor(enrollment_id),
+                None => LoginScreenMode::Choosing,
+            };
+        }
+
+        for mut text in q_text.iter_mut() {
+            text.sections[0].value = e.0.replace('_', " ").to_lowercase();
+        }
+    }
+}
+
+fn apply_screen_mode(
+    mode: Res<LoginScreenMode>,
+    mut q_panels: Query<
+        (
+            &mut Style,
+            Option<&WaitingPanel>,
+            Option<&SecondFactorPanel>,
+        ),
+        Or<(
+            With<ProviderPanel>,
+            With<PasswordForm>,
+            With<WaitingPanel>,
+            With<SecondFactorPanel>,
+        )>,
+    >,
+) {
+    if !mode.is_changed() {
+        return;
+    }
+
+    for (mut style, waiting_panel, second_factor_panel) in q_panels.iter_mut() {
+        let visible = match (waiting_panel, second_factor_panel) {
+            (Some(_), _) => *mode == LoginScreenMode::Waiting,
+            (_, Some(_)) => matches!(*mode, LoginScreenMode::SecondFactor(_)),
+            _ => *mode == LoginScreenMode::Choosing,
+        };
+        style.display = if visible {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+// Buttons
+
+fn provider_button_system(
+    mut commands: Commands,
+    q_interaction: Query<(&Interaction, &ProviderButton), Changed<Interaction>>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut q_text: Query<(&mut Text, Option<&WaitingText>), Or<(With<WaitingText>, With<ErrorText>)>>,
+) {
+    for (interaction, button) in q_interaction.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        commands.insert_resource(SelectedProvider(button.provider.clone()));
+
+        if let Err(err) = open::that(button.url.as_str()) {
+            warn!(target: "bevy_firebase_auth_ui", "Couldn't open browser: {:?}", err);
+        }
+
+        *mode = LoginScreenMode::Waiting;
+
+        for (mut text, waiting_text) in q_text.iter_mut() {
+            text.sections[0].value = match waiting_text {
+                Some(_) => "waiting for browser...".into(),
+                None => String::new(),
+            };
+        }
+    }
+}
+
+fn cancel_button_system(
+    mut commands: Commands,
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<CancelButton>)>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut pending_factor: ResMut<PendingFactor>,
+    auth_state: Res<State<AuthState>>,
+    mut next_state: ResMut<NextState<AuthState>>,
+) {
+    for interaction in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            commands.remove_resource::<SelectedProvider>();
+            pending_factor.0 = None;
+            *mode = LoginScreenMode::Choosing;
+
+            // The redirect server keeps listening until a code arrives. After
+            // that, logging in again starts a new one
+            if *auth_state.get() == AuthState::GotAuthCode {
+                next_state.set(AuthState::LogIn);
+            }
+        }
+    }
+}
+
+fn password_button_system(
+    q_interaction: Query<(&Interaction, &PasswordButton), Changed<Interaction>>,
+    q_email: Query<&TextInput, With<EmailInput>>,
+    q_password: Query<&TextInput, With<PasswordInput>>,
+    mut ew: EventWriter<SignInWithPassword>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut q_text: Query<(&mut Text, Option<&WaitingText>), Or<(With<WaitingText>, With<ErrorText>)>>,
+) {
+    for (interaction, button) in q_interaction.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        let (Ok(email), Ok(password)) = (q_email.get_single(), q_password.get_single()) else {
+            continue;
+        };
+
+        ew.send(SignInWithPassword {
+            email: email.value.clone(),
+            password: password.value.clone(),
+            create_account: button.create_account,
+        });
+
+        *mode = LoginScreenMode::Waiting;
+
+        for (mut text, waiting_text) in q_text.iter_mut() {
+            text.sections[0].value = match waiting_text {
+                Some(_) => "signing in...".into(),
+                None => String::new(),
+            };
+        }
+    }
+}
+
+fn show_second_factor(
+    mut er: EventReader<SecondFactorRequired>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut pending_factor: ResMut<PendingFactor>,
+    mut q_text: Query<&mut Text, With<ErrorText>>,
+) {
+    for e in er.iter() {
+        let totp_factor = e
+            .factors
+            .iter()
+            .find(|factor| factor.kind == MfaFactorKind::Totp);
+
+        let Some(factor) = totp_factor else {
+            *mode = LoginScreenMode::Choosing;
+            for mut text in q_text.iter_mut() {
+                text.sections[0].value = "this account needs a second factor".into();
+            }
+            continue;
+        };
+
+        pending_factor.0 = Some(factor.enrollment_id.clone());
+        *mode = LoginScreenMode::SecondFactor(factor.enrollment_id.clone());
+    }
+}
+
+fn verify_button_system(
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<VerifyButton>)>,
+    mut q_code: Query<&mut TextInput, With<CodeInput>>,
+    mut ew: EventWriter<FinalizeSecondFactor>,
+    mut mode: ResMut<LoginScreenMode>,
+    mut q_text: Query<(&mut Text, Option<&WaitingText>), Or<(With<WaitingText>, With<ErrorText>)>>,
+) {
+    for interaction in q_interaction.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        let LoginScreenMode::SecondFactor(enrollment_id) = &*mode else {
+            continue;
+        };
+        let Ok(mut code) = q_code.get_single_mut() else {
+            continue;
+        };
+
+        ew.send(FinalizeSecondFactor {
+            enrollment_id: enrollment_id.clone(),
+            code: code.value.trim().replace(' ', ""),
+        });
+
+        // Codes only work once
+        code.value.clear();
+        *mode = LoginScreenMode::Waiting;
+
+        for (mut text, waiting_text) in q_text.iter_mut() {
+            text.sections[0].value = match waiting_text {
+                Some(_) => "signing in...".into(),
+                None => String::new(),
+            };
+        }
+    }
+}
+
+fn button_color_system(
+    mut q_interaction: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<ThemedButton>),
+    >,
+    theme: Res<AuthUiTheme>,
+) {
+    for (interaction, mut color) in q_interaction.iter_mut() {
+        *color = match *interaction {
+            Interaction::Pressed => theme.button_colors.pressed,
+            Interaction::Hovered => theme.button_colors.hovered,
+            Interaction::None => theme.button_colors.normal,
+        }
+        .into();
+    }
+}
+
+fn spin_spinner(mut q_spinner: Query<(&mut Text, &mut Spinner)>, time: Res<Time>) {
+    for (mut text, mut spinner) in q_spinner.iter_mut() {
+        spinner.timer.tick(time.delta());
+
+        if spinner.timer.just_finished() {
+            spinner.frame = (spinner.frame + 1) % SPINNER_FRAMES.len();
+            text.sections[0].value = SPINNER_FRAMES[spinner.frame].into();
+        }
+    }
+}
+
+// Text input
+
+fn focus_text_input(
+    mut commands: Commands,
+    q_interaction: Query<(Entity, &Interaction), (Changed<Interaction>, With<TextInput>)>,
+    q_focused: Query<Entity, With<Focused>>,
+    mouse: Res<Input<MouseButton>>,
+) {
+    if mouse.just_pressed(MouseButton::Left) {
+        for entity in q_focused.iter() {
+            commands.entity(entity).remove::<Focused>();
+        }
+    }
+
+    for (entity, interaction) in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            commands.entity(entity).insert(Focused);
+        }
+    }
+}
+
+fn text_input_system(
+    mut commands: Commands,
+    mut er_chars: EventReader<ReceivedCharacter>,
+    mut er_keys: EventReader<KeyboardInput>,
+    mut q_focused: Query<(Entity, &mut TextInput, Option<&EmailInput>), With<Focused>>,
+    q_password: Query<Entity, With<PasswordInput>>,
+) {
+    let Ok((entity, mut input, email_input)) = q_focused.get_single_mut() else {
+        er_chars.clear();
+        er_keys.clear();
+        return;
+    };
+
+    for e in er_chars.iter() {
+        if !e.char.is_control() {
+            input.value.push(e.char);
+        }
+    }
+
+    for e in er_keys.iter() {
+        if e.state != ButtonState::Pressed {
+            continue;
+        }
+
+        match e.key_code {
+            Some(KeyCode::Back) => {
+                input.value.pop();
+            }
+            // Move from email to password, like a web form
+            Some(KeyCode::Tab) | Some(KeyCode::Return) if email_input.is_some() => {
+                commands.entity(entity).remove::<Focused>();
+                if let Ok(password) = q_password.get_single() {
+                    commands.entity(password).insert(Focused);
+                }
+            }
+            Some(KeyCode::Return) => {
+                commands.entity(entity).remove::<Focused>();
+            }
+            _ => (),
+        }
+    }
+}
+
+fn update_text_input_display(
+    q_input: Query<(&TextInput, &Children, Option<&Focused>)>,
+    mut q_text: Query<&mut Text>,
+    theme: Res<AuthUiTheme>,
+    time: Res<Time>,
+) {
+    let caret_visible = time.elapsed_seconds() % 1. < 0.5;
+
+    for (input, children, focused) in q_input.iter() {
+        let Ok(mut text) = q_text.get_mut(children[0]) else {
+            continue;
+        };
+
+        let (mut value, color) = if input.value.is_empty() && focused.is_none() {
+            (input.placeholder.clone(), theme.placeholder_color)
+        } else if input.masked {
+            ("*".repeat(input.value.chars().count()), theme.text_color)
+        } else {
+            (input.value.clone(), theme.text_color)
+        };
+
+        if focused.is_some() && caret_visible {
+            value.push('|');
+        }
+
+        // Avoid marking the text changed every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+        if text.sections[0].style.color != color {
+            text.sections[0].style.color = color;
+        }
+    }
+}
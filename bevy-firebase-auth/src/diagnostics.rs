@@ -0,0 +1,114 @@
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+
+use crate::{AuthErrorEvent, LoginProvider, TokenData};
+
+// Log targets, for filtering with `LogPlugin::filter`
+pub(crate) const LOG_LOGIN: &str = "bevy_firebase_auth::login";
+pub(crate) const LOG_REFRESH: &str = "bevy_firebase_auth::refresh";
+pub(crate) const LOG_PROVIDER: &str = "bevy_firebase_auth::provider";
+pub(crate) const LOG_STORAGE: &str = "bevy_firebase_auth::storage";
+pub(crate) const LOG_MFA: &str = "bevy_firebase_auth::mfa";
+
+/// Bevy `Resource` with the state of the auth session, for debug overlays
+///
+/// Opt in by initializing it. It's kept up to date from then on, and error
+/// counts carry over between logins.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// # let mut app = App::new();
+/// app.init_resource::<AuthDiagnostics>()
+///     .add_systems(Update, auth_overlay);
+///
+/// fn auth_overlay(diagnostics: Res<AuthDiagnostics>) {
+///     if let Some(time_to_expiry) = diagnostics.time_to_expiry() {
+///         // draw "{:?} via {:?}", time_to_expiry, diagnostics.provider
+///     }
+/// }
+/// ```
+#[derive(Resource, Clone, Debug, Default)]
+pub struct AuthDiagnostics {
+    /// When the Firebase token was last refreshed
+    pub last_refresh: Option<SystemTime>,
+    /// When the current Firebase ID token expires
+    pub expires_at: Option<SystemTime>,
+    pub provider: Option<LoginProvider>,
+    /// Sign ins and second factors that failed with an `AuthErrorEvent`
+    pub sign_in_errors: u32,
+    /// Failed Firebase token refreshes, including each offline retry
+    pub refresh_errors: u32,
+    /// Failed provider token refreshes
+    pub provider_errors: u32,
+}
+
+impl AuthDiagnostics {
+    /// Time left before the ID token expires. `None` if logged out or expired
+    pub fn time_to_expiry(&self) -> Option<Duration> {
+        self.expires_at?.duration_since(SystemTime::now()).ok()
+    }
+}
+
+// Updates diagnostics from a background task, if they're enabled
+pub(crate) fn record(world: &mut World, update: impl FnOnce(&mut AuthDiagnostics)) {
+    if let Some(mut diagnostics) = world.get_resource_mut::<AuthDiagnostics>() {
+        update(&mut diagnostics);
+    }
+}
+
+pub(crate) fn logged_in_diagnostics(
+    token_data: Res<TokenData>,
+    diagnostics: Option<ResMut<AuthDiagnostics>>,
+) {
+    let Some(mut diagnostics) = diagnostics else {
+        return;
+    };
+
+    diagnostics.expires_at = token_data
+        .expires_in
+        .parse()
+        .ok()
+        .map(|expires_in| SystemTime::now() + Duration::from_secs(expires_in));
+
+    // Refresh responses don't say which provider was used
+    if let Some(provider) = token_data
+        .provider_id
+        .as_deref()
+        .and_then(LoginProvider::from_provider_id)
+    {
+        diagnostics.provider = Some(provider);
+    }
+}
+
+pub(crate) fn logged_out_diagnostics(diagnostics: Option<ResMut<AuthDiagnostics>>) {
+    if let Some(mut diagnostics) = diagnostics {
+        diagnostics.expires_at = None;
+        diagnostics.provider = None;
+    }
+}
+
+pub(crate) fn count_sign_in_errors(
+    mut er: EventReader<AuthErrorEvent>,
+    diagnostics: Option<ResMut<AuthDiagnostics>>,
+) {
+    let errors = er.iter().count() as u32;
+
+    if let Some(mut diagnostics) = diagnostics {
+        if errors > 0 {
+            diagnostics.sign_in_errors += errors;
+        }
+    }
+}
+
+// Shows whether a secret is set, without showing it
+pub(crate) fn redact(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        "[redacted]"
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt,
     fs::{create_dir_all, remove_file, write, File},
     io::{self, BufRead, BufReader, Write},
     net::TcpListener,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use reqwest::Client;
-use serde::Deserialize;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use url::Url;
 
@@ -16,17 +19,33 @@ use bevy_tokio_tasks::TokioTasksRuntime;
 
 use dirs::cache_dir;
 
-use ron::de::from_reader;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ron::{de::from_reader, ser::PrettyConfig};
+
+mod diagnostics;
+pub use diagnostics::AuthDiagnostics;
+use diagnostics::*;
+
+mod mfa;
+pub use mfa::*;
+
+mod service_account;
+pub use service_account::{AdminToken, ServiceAccount, ServiceAccountKey};
+
+#[cfg(feature = "qr")]
+mod qr;
+#[cfg(feature = "qr")]
+pub use qr::*;
 
 // Sign In Methods
 // app id, client id, application id, and twitter's api key are all client_id
 // app secret, client secret, application secret, and twitter's api secret are all client_secret
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
 pub enum LoginProvider {
     Google,
     Github,
-    // NOT YET IMPLEMENTED
     EmailPassword,
+    // NOT YET IMPLEMENTED
     Apple,
     Phone,
     Anonymous,
@@ -38,6 +57,45 @@ pub enum LoginProvider {
     Yahoo,
 }
 
+impl LoginProvider {
+    /// Maps a Firebase `providerId` (e.g. `google.com`) to a `LoginProvider`
+    pub fn from_provider_id(provider_id: &str) -> Option<Self> {
+        match provider_id {
+            "google.com" => Some(LoginProvider::Google),
+            "github.com" => Some(LoginProvider::Github),
+            "password" => Some(LoginProvider::EmailPassword),
+            "apple.com" => Some(LoginProvider::Apple),
+            "phone" => Some(LoginProvider::Phone),
+            "anonymous" => Some(LoginProvider::Anonymous),
+            "playgames.google.com" => Some(LoginProvider::GooglePlayGames),
+            "gc.apple.com" => Some(LoginProvider::AppleGameCenter),
+            "facebook.com" => Some(LoginProvider::Facebook),
+            "twitter.com" => Some(LoginProvider::Twitter),
+            "microsoft.com" => Some(LoginProvider::Microsoft),
+            "yahoo.com" => Some(LoginProvider::Yahoo),
+            _ => None,
+        }
+    }
+
+    /// The Firebase `providerId` for this provider, the inverse of `from_provider_id`
+    pub fn provider_id(&self) -> &'static str {
+        match self {
+            LoginProvider::Google => "google.com",
+            LoginProvider::Github => "github.com",
+            LoginProvider::EmailPassword => "password",
+            LoginProvider::Apple => "apple.com",
+            LoginProvider::Phone => "phone",
+            LoginProvider::Anonymous => "anonymous",
+            LoginProvider::GooglePlayGames => "playgames.google.com",
+            LoginProvider::AppleGameCenter => "gc.apple.com",
+            LoginProvider::Facebook => "facebook.com",
+            LoginProvider::Twitter => "twitter.com",
+            LoginProvider::Microsoft => "microsoft.com",
+            LoginProvider::Yahoo => "yahoo.com",
+        }
+    }
+}
+
 /// e.g.
 /// ```
 /// # use bevy::prelude::*;
@@ -52,6 +110,156 @@ pub type AuthCodesMap = HashMap<LoginProvider, String>;
 #[derive(Resource)]
 struct LoginKeys(LoginKeysMap);
 
+impl fmt::Debug for LoginKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(provider, keys)| {
+                let keys = keys
+                    .as_ref()
+                    .map(|(client_id, client_secret)| (client_id, redact(client_secret)));
+                (provider, keys)
+            }))
+            .finish()
+    }
+}
+
+/// Scopes and extra authorization parameters for a login provider
+///
+/// Providers without settings use `openid profile email` for Google and
+/// `read:user` for GitHub.
+///
+/// # Examples
+///
+/// Asking Google for offline access, limited to one hosted domain:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// let mut provider_settings = ProviderSettingsMap::new();
+/// provider_settings.insert(
+///     LoginProvider::Google,
+///     ProviderSettings::new(["openid", "profile", "email"])
+///         .with_param("access_type", "offline")
+///         .with_param("prompt", "consent")
+///         .with_param("hd", "example.com"),
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProviderSettings {
+    pub scopes: Vec<String>,
+    /// Extra query parameters for the auth URL, e.g. `prompt`, `login_hint` or `hd`
+    pub auth_params: HashMap<String, String>,
+}
+
+impl ProviderSettings {
+    pub fn new<S: Into<String>>(scopes: impl IntoIterator<Item = S>) -> Self {
+        ProviderSettings {
+            scopes: scopes.into_iter().map(Into::into).collect(),
+            auth_params: HashMap::new(),
+        }
+    }
+
+    pub fn with_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.auth_params.insert(key.into(), value.into());
+        self
+    }
+
+    fn default_for(provider: &LoginProvider) -> Self {
+        match provider {
+            LoginProvider::Google => ProviderSettings::new(["openid", "profile", "email"]),
+            LoginProvider::Github => ProviderSettings::new(["read:user"]),
+            _ => ProviderSettings::default(),
+        }
+    }
+
+    fn auth_url(&self, base_url: &str, client_id: &str, redirect_uri: &str) -> Url {
+        let mut url = Url::parse(base_url).unwrap();
+
+        url.query_pairs_mut()
+            .append_pair("scope", &self.scopes.join(" "))
+            .append_pair("response_type", "code")
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("client_id", client_id)
+            .extend_pairs(self.auth_params.iter());
+
+        url
+    }
+}
+
+pub type ProviderSettingsMap = HashMap<LoginProvider, ProviderSettings>;
+
+#[derive(Resource)]
+struct ProviderConfig(ProviderSettingsMap);
+
+/// A login provider's own OAuth token, for calling its APIs directly
+///
+/// Tokens are redacted in `Debug` output.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProviderCredential {
+    /// `None` when signed in with a provider ID token alone, e.g. from a
+    /// native Google Sign-In SDK
+    pub access_token: Option<String>,
+    /// Only given by providers that support it, e.g. Google with `access_type=offline`
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+    /// Seconds since the unix epoch. `None` if the token doesn't expire
+    pub expires_at: Option<u64>,
+    pub scopes: Vec<String>,
+}
+
+impl fmt::Debug for ProviderCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderCredential")
+            .field("access_token", &self.access_token.as_deref().map(redact))
+            .field("refresh_token", &self.refresh_token.as_deref().map(redact))
+            .field("id_token", &self.id_token.as_deref().map(redact))
+            .field("expires_at", &self.expires_at)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+impl ProviderCredential {
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => unix_now() >= expires_at,
+            None => false,
+        }
+    }
+}
+
+/// Bevy `Resource` holding provider OAuth tokens from the last login
+///
+/// Persisted alongside the refresh token when `RememberLoginFlag` is set, so
+/// it survives a Firebase token refresh and an app restart.
+///
+/// # Examples
+///
+/// Calling the GitHub API:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn list_gists(
+///     credentials: Res<ProviderCredentials>,
+///     mut ew: EventWriter<RefreshProviderCredential>,
+/// ) {
+///     if let Some(github) = credentials.0.get(&LoginProvider::Github) {
+///         if github.is_expired() {
+///             ew.send(RefreshProviderCredential(LoginProvider::Github));
+///             return;
+///         }
+///         if let Some(access_token) = &github.access_token {
+///             // GET https://api.github.com/gists with `Bearer {access_token}`
+///         }
+///     }
+/// }
+/// ```
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProviderCredentials(pub HashMap<LoginProvider, ProviderCredential>);
+
+/// Event to refresh a provider's OAuth token, if it gave a refresh token
+#[derive(Event, Debug, Clone)]
+pub struct RefreshProviderCredential(pub LoginProvider);
+
 /// Event that is sent when an Authorization URL is created
 ///
 /// # Examples
@@ -89,12 +297,111 @@ struct LoginKeys(LoginKeysMap);
 #[derive(Event, Debug)]
 pub struct AuthUrlsEvent(pub AuthUrlsMap);
 
-#[derive(Event, Debug)]
+#[derive(Event)]
 pub struct AuthCodeEvent((LoginProvider, String));
 
+impl fmt::Debug for AuthCodeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AuthCodeEvent")
+            .field(&(&self.0 .0, redact(&self.0 .1)))
+            .finish()
+    }
+}
+
 #[derive(Event, Resource)]
 pub struct SelectedProvider(pub LoginProvider);
 
+/// A credential already held from a provider, for `SignInWithCredential`
+///
+/// Tokens are redacted in `Debug` output.
+#[derive(Clone)]
+pub enum IdpCredential {
+    /// An OpenID Connect ID token, e.g. from Google
+    IdToken(String),
+    /// An OAuth2 access token, e.g. from GitHub
+    AccessToken(String),
+    /// An OAuth1 token and secret, e.g. from Twitter
+    OAuth1 {
+        oauth_token: String,
+        oauth_token_secret: String,
+    },
+}
+
+impl fmt::Debug for IdpCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdpCredential::IdToken(id_token) => {
+                f.debug_tuple("IdToken").field(&redact(id_token)).finish()
+            }
+            IdpCredential::AccessToken(access_token) => f
+                .debug_tuple("AccessToken")
+                .field(&redact(access_token))
+                .finish(),
+            IdpCredential::OAuth1 {
+                oauth_token,
+                oauth_token_secret,
+            } => f
+                .debug_struct("OAuth1")
+                .field("oauth_token", &redact(oauth_token))
+                .field("oauth_token_secret", &redact(oauth_token_secret))
+                .finish(),
+        }
+    }
+}
+
+/// Event to sign in with a credential the app already holds
+///
+/// Skips the browser login and goes straight to Firebase's `signInWithIdp`.
+///
+/// # Examples
+///
+/// Signing in with a launcher's Google ID token:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn launcher_sign_in(mut ew: EventWriter<SignInWithCredential>) {
+///     ew.send(SignInWithCredential {
+///         provider_id: "google.com".into(),
+///         credential: IdpCredential::IdToken("GOOGLE-ID-TOKEN".into()),
+///     });
+/// }
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct SignInWithCredential {
+    /// Firebase provider ID, e.g. `google.com`, `github.com` or `twitter.com`
+    pub provider_id: String,
+    pub credential: IdpCredential,
+}
+
+/// Event to sign in with an email and password
+///
+/// Needs the Email/Password provider enabled in the Firebase console.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn sign_up(mut ew: EventWriter<SignInWithPassword>) {
+///     ew.send(SignInWithPassword {
+///         email: "player@example.com".into(),
+///         password: "hunter22".into(),
+///         create_account: true,
+///     });
+/// }
+/// ```
+#[derive(Event, Clone)]
+pub struct SignInWithPassword {
+    pub email: String,
+    pub password: String,
+    /// Create a new account with `signUp` instead of signing in
+    pub create_account: bool,
+}
+
+/// Event sent when a sign in fails, holding Firebase's error message
+#[derive(Event, Clone, Debug)]
+pub struct AuthErrorEvent(pub String);
+
 #[derive(Resource, Clone)]
 pub struct AuthEmulatorUrl(String);
 
@@ -108,12 +415,19 @@ pub struct ApiKey(String);
 #[derive(Resource)]
 pub struct ProjectId(pub String);
 
+// From plugin
+/// Bevy `Resource` containing the Identity Platform tenant users sign in to
+#[derive(Resource, Clone)]
+pub struct TenantId(pub String);
+
 #[derive(Resource)]
 pub struct RememberLoginFlag(pub bool);
 
 // TODO trim this down?
 /// Holds data from a user access token
-#[derive(Deserialize, Resource, Default, Debug)]
+///
+/// Tokens are redacted in `Debug` output, so it's safe to log.
+#[derive(Deserialize, Resource, Default)]
 pub struct TokenData {
     #[serde(rename = "localId")]
     #[serde(alias = "user_id")]
@@ -140,6 +454,11 @@ pub struct TokenData {
     pub display_name: Option<String>,
     #[serde(rename = "photoUrl")]
     pub photo_url: Option<String>,
+    #[serde(rename = "providerId")]
+    pub provider_id: Option<String>,
+    /// Identity Platform tenant, from the ID token's `firebase.tenant` claim
+    #[serde(rename = "tenantId")]
+    pub tenant_id: Option<String>,
     #[serde(rename = "idToken")]
     #[serde(alias = "id_token")]
     pub id_token: String,
@@ -151,9 +470,161 @@ pub struct TokenData {
     pub expires_in: String,
 }
 
-// Retrieved
-#[derive(Resource)]
-struct GoogleToken(String);
+impl TokenData {
+    // Refresh responses only carry the tokens, so keep the profile we had
+    fn merge_refreshed(self, refreshed: TokenData) -> TokenData {
+        TokenData {
+            local_id: refreshed.local_id,
+            tenant_id: refreshed.tenant_id,
+            id_token: refreshed.id_token,
+            refresh_token: refreshed.refresh_token,
+            expires_in: refreshed.expires_in,
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for TokenData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenData")
+            .field("local_id", &self.local_id)
+            .field("email_verified", &self.email_verified)
+            .field("email", &self.email)
+            .field(
+                "oauth_id_token",
+                &self.oauth_id_token.as_deref().map(redact),
+            )
+            .field(
+                "oauth_access_token",
+                &self.oauth_access_token.as_deref().map(redact),
+            )
+            .field(
+                "oauth_token_secret",
+                &self.oauth_token_secret.as_deref().map(redact),
+            )
+            .field("raw_user_info", &self.raw_user_info)
+            .field("first_name", &self.first_name)
+            .field("last_name", &self.last_name)
+            .field("full_name", &self.full_name)
+            .field("display_name", &self.display_name)
+            .field("photo_url", &self.photo_url)
+            .field("provider_id", &self.provider_id)
+            .field("tenant_id", &self.tenant_id)
+            .field("id_token", &redact(&self.id_token))
+            .field("refresh_token", &redact(&self.refresh_token))
+            .field("expires_in", &self.expires_in)
+            .finish()
+    }
+}
+
+/// An account remembered on this machine
+///
+/// Holds enough to refresh straight into the account with a `SwitchAccount`
+/// event, so no browser login is needed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedAccount {
+    pub uid: String,
+    pub display_name: Option<String>,
+    pub provider: Option<LoginProvider>,
+    refresh_token: String,
+}
+
+impl fmt::Debug for SavedAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SavedAccount")
+            .field("uid", &self.uid)
+            .field("display_name", &self.display_name)
+            .field("provider", &self.provider)
+            .field("refresh_token", &redact(&self.refresh_token))
+            .finish()
+    }
+}
+
+/// Bevy `Resource` listing every remembered account, most recently used first
+///
+/// Accounts are added on login when `RememberLoginFlag` is set, and are kept
+/// through a log out. Remove them with a `ForgetAccount` event.
+///
+/// # Examples
+///
+/// Listing accounts for a profile picker:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn list_profiles(saved_accounts: Res<SavedAccounts>) {
+///     for account in saved_accounts.0.iter() {
+///         println!(
+///             "{} ({:?})",
+///             account.display_name.clone().unwrap_or(account.uid.clone()),
+///             account.provider
+///         );
+///     }
+/// }
+/// ```
+#[derive(Resource, Default, Debug)]
+pub struct SavedAccounts(pub Vec<SavedAccount>);
+
+impl SavedAccounts {
+    fn remember(&mut self, token_data: &TokenData) {
+        let existing = self
+            .0
+            .iter()
+            .position(|account| account.uid == token_data.local_id)
+            .map(|index| self.0.remove(index));
+
+        let provider = token_data
+            .provider_id
+            .as_deref()
+            .and_then(LoginProvider::from_provider_id);
+
+        // Refresh responses don't carry profile info, so keep what we had
+        let (display_name, provider) = match existing {
+            Some(account) => (
+                token_data.display_name.clone().or(account.display_name),
+                provider.or(account.provider),
+            ),
+            None => (token_data.display_name.clone(), provider),
+        };
+
+        self.0.insert(
+            0,
+            SavedAccount {
+                uid: token_data.local_id.clone(),
+                display_name,
+                provider,
+                refresh_token: token_data.refresh_token.clone(),
+            },
+        );
+    }
+
+    // Removes the account and its refresh token
+    fn forget(&mut self, uid: &str) -> Option<SavedAccount> {
+        let index = self.0.iter().position(|account| account.uid == uid)?;
+        Some(self.0.remove(index))
+    }
+}
+
+/// Event to log in to a saved account by uid
+///
+/// Refreshes the account's stored token, so no browser round trip is made.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn pick_profile(mut ew: EventWriter<SwitchAccount>, saved_accounts: Res<SavedAccounts>) {
+///     if let Some(account) = saved_accounts.0.first() {
+///         ew.send(SwitchAccount(account.uid.clone()));
+///     }
+/// }
+/// ```
+#[derive(Event, Debug, Clone)]
+pub struct SwitchAccount(pub String);
+
+/// Event to remove a saved account, and its refresh token, from this machine
+#[derive(Event, Debug, Clone)]
+pub struct ForgetAccount(pub String);
 
 // Generated
 #[derive(Resource)]
@@ -166,9 +637,55 @@ pub enum AuthState {
     LoggedOut,
     LogOut,
     Refreshing,
+    /// Refresh failed on a network error and is being retried. `TokenData`
+    /// keeps the cached uid until the refresh succeeds.
+    Offline,
     LogIn,
     GotAuthCode,
     LoggedIn,
+    /// Tearing down the current account after a `SwitchAccount`, before
+    /// refreshing into the saved one
+    SwitchingAccount,
+}
+
+/// Bevy `Resource` controlling how a token refresh is retried after a network error
+///
+/// Only revocation errors, like `TOKEN_EXPIRED` or `USER_NOT_FOUND`, send the
+/// user back to `AuthState::LogIn`. Anything else is retried with exponential
+/// backoff while in `AuthState::Offline`.
+///
+/// # Examples
+///
+/// Give up on offline play after ten minutes:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// # use std::time::Duration;
+/// # let mut app = App::new();
+/// app.insert_resource(RefreshRetrySettings {
+///     offline_grace_period: Some(Duration::from_secs(600)),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct RefreshRetrySettings {
+    /// Delay before the first retry, doubled after each failure
+    pub initial_delay: Duration,
+    /// Longest delay between retries
+    pub max_delay: Duration,
+    /// How long to stay offline before going to `AuthState::LoggedOut`.
+    /// `None` keeps retrying forever.
+    pub offline_grace_period: Option<Duration>,
+}
+
+impl Default for RefreshRetrySettings {
+    fn default() -> Self {
+        RefreshRetrySettings {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            offline_grace_period: None,
+        }
+    }
 }
 
 /// The Firebase Auth bevy plugin
@@ -191,26 +708,33 @@ pub struct AuthPlugin {
     pub login_keys: LoginKeysMap,
     /// "http://127.0.0.1:9099"
     pub emulator_url: Option<String>,
+    /// Scopes and auth params for each provider
+    pub provider_settings: ProviderSettingsMap,
+    /// Identity Platform tenant to sign users in to. `None` uses the
+    /// project's own users
+    pub tenant_id: Option<String>,
+    /// Log in as a service account instead of a user, for backend tools
+    pub service_account: Option<ServiceAccount>,
 }
 
 impl Default for AuthPlugin {
     fn default() -> Self {
         let keys_path = "keys.ron";
-        let f = File::open(&keys_path);
+        let f = File::open(keys_path);
 
         let login_keys = match f {
             Ok(f) => {
                 let login_keys: LoginKeysMap = match from_reader(f) {
                     Ok(keys) => keys,
                     Err(err) => {
-                        println!("File read error: {:?}", err);
+                        warn!(target: LOG_STORAGE, "Couldn't read {}: {:?}", keys_path, err);
                         HashMap::new()
                     }
                 };
                 login_keys
             }
             Err(err) => {
-                println!("File open error: {:?}", err);
+                warn!(target: LOG_STORAGE, "Couldn't open {}: {:?}", keys_path, err);
                 HashMap::new()
             }
         };
@@ -220,6 +744,9 @@ impl Default for AuthPlugin {
             firebase_project_id: "demo-bevy".into(),
             emulator_url: Some("http://127.0.0.1:9099".into()),
             login_keys,
+            provider_settings: HashMap::new(),
+            tenant_id: None,
+            service_account: None,
         }
     }
 }
@@ -230,31 +757,95 @@ impl Plugin for AuthPlugin {
             .insert_resource(ProjectId(self.firebase_project_id.clone()))
             .insert_resource(TokenData::default())
             .insert_resource(LoginKeys(self.login_keys.clone()))
+            .insert_resource(ProviderConfig(self.provider_settings.clone()))
+            .insert_resource(load_provider_credentials())
             .insert_resource(RememberLoginFlag(false))
+            .init_resource::<RefreshRetrySettings>()
+            .init_resource::<ForgottenAccounts>()
             .add_state::<AuthState>()
             .add_event::<AuthUrlsEvent>()
             .add_event::<AuthCodeEvent>()
-            .add_systems(OnEnter(AuthState::LogIn), init_login)
+            .add_event::<SwitchAccount>()
+            .add_event::<ForgetAccount>()
+            .add_event::<RefreshProviderCredential>()
+            .add_event::<SignInWithCredential>()
+            .add_event::<SignInWithPassword>()
+            .add_event::<AuthErrorEvent>()
+            .add_event::<SecondFactorRequired>()
+            .add_event::<FinalizeSecondFactor>()
+            .add_event::<StartTotpEnrollment>()
+            .add_event::<TotpEnrollmentStarted>()
+            .add_event::<FinalizeTotpEnrollment>()
+            .add_event::<TotpEnrolled>()
+            .add_event::<DeleteAccount>()
+            .add_event::<RefreshIdToken>()
+            .add_systems(
+                OnEnter(AuthState::LogIn),
+                init_login.run_if(not(resource_exists::<ServiceAccount>())),
+            )
             .add_systems(OnEnter(AuthState::GotAuthCode), auth_code_to_firebase_token)
-            .add_systems(OnEnter(AuthState::Refreshing), refresh_login)
-            .add_systems(OnEnter(AuthState::LoggedIn), save_refresh_token)
+            .add_systems(
+                OnEnter(AuthState::Refreshing),
+                refresh_login.run_if(not(resource_exists::<ServiceAccount>())),
+            )
+            // A service account has no refresh token, and isn't an account to switch to
+            .add_systems(
+                OnEnter(AuthState::LoggedIn),
+                save_refresh_token.run_if(not(resource_exists::<ServiceAccount>())),
+            )
             .add_systems(OnEnter(AuthState::LoggedIn), login_clear_resources)
-            .add_systems(OnEnter(AuthState::LogOut), logout_clear_resources);
+            .add_systems(OnEnter(AuthState::LogOut), logout_clear_resources)
+            .add_systems(
+                OnEnter(AuthState::SwitchingAccount),
+                (switch_clear_resources, remove_provider_credentials_file),
+            )
+            .add_systems(OnEnter(AuthState::LoggedIn), logged_in_diagnostics)
+            .add_systems(OnEnter(AuthState::LoggedOut), logged_out_diagnostics)
+            .add_systems(
+                Update,
+                (
+                    switch_account,
+                    forget_account,
+                    sign_in_with_credential,
+                    sign_in_with_password,
+                    refresh_provider_credential,
+                    save_provider_credentials,
+                    mfa::finalize_second_factor,
+                    mfa::start_totp_enrollment,
+                    mfa::finalize_totp_enrollment,
+                    delete_account_event,
+                    count_sign_in_errors,
+                ),
+            )
+            .add_systems(
+                Update,
+                refresh_id_token.run_if(
+                    in_state(AuthState::LoggedIn)
+                        .and_then(not(resource_exists::<ServiceAccount>())),
+                ),
+            );
 
         // check for existing token
 
-        let path = cache_dir()
-            .clone()
-            .unwrap()
-            .join(std::env::var("CARGO_PKG_NAME").unwrap())
-            .join("login")
-            .join("firebase-refresh.key");
+        let path = login_dir().join("firebase-refresh.key");
 
         let token = std::fs::read_to_string(path);
+        let saved_accounts = load_saved_accounts();
 
         match token {
+            // A service account has no user to refresh into
+            Ok(_) if self.service_account.is_some() => {}
             Ok(token) => {
+                // Cache the uid so it's known even if the first refresh is offline
+                let local_id = saved_accounts
+                    .0
+                    .iter()
+                    .find(|account| account.refresh_token == token)
+                    .map(|account| account.uid.clone())
+                    .unwrap_or_default();
+
                 app.insert_resource(TokenData {
+                    local_id,
                     refresh_token: token,
                     ..Default::default()
                 });
@@ -262,9 +853,35 @@ impl Plugin for AuthPlugin {
             Err(_) => {}
         }
 
+        app.insert_resource(saved_accounts);
+
+        #[cfg(feature = "qr")]
+        app.init_resource::<LoginQrSettings>()
+            .add_systems(Update, qr::auth_url_qr_codes);
+
         if self.emulator_url.is_some() {
             app.insert_resource(AuthEmulatorUrl(self.emulator_url.clone().unwrap()));
         }
+
+        if let Some(tenant_id) = &self.tenant_id {
+            app.insert_resource(TenantId(tenant_id.clone()));
+        }
+
+        if let Some(service_account) = &self.service_account {
+            app.insert_resource(service_account.clone())
+                .add_systems(
+                    OnEnter(AuthState::LogIn),
+                    service_account::service_account_login,
+                )
+                .add_systems(
+                    OnEnter(AuthState::Refreshing),
+                    service_account::service_account_login,
+                )
+                .add_systems(
+                    Update,
+                    service_account::refresh_admin_token.run_if(in_state(AuthState::LoggedIn)),
+                );
+        }
     }
 }
 
@@ -333,30 +950,43 @@ pub fn log_out(current_state: Res<State<AuthState>>, mut next_state: ResMut<Next
     next_state.set(AuthState::LogOut);
 }
 
-fn logout_clear_resources(mut commands: Commands, mut next_state: ResMut<NextState<AuthState>>) {
+// Saved accounts are kept, so the user can switch back without a browser login
+fn logout_clear_resources(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<AuthState>>,
+    mut forgotten_accounts: ResMut<ForgottenAccounts>,
+) {
+    // A later login to a forgotten account remembers it again
+    forgotten_accounts.0.clear();
+
     commands.remove_resource::<TokenData>();
+    commands.remove_resource::<AdminToken>();
 
-    let path = cache_dir()
-        .clone()
-        .unwrap()
-        .join(std::env::var("CARGO_PKG_NAME").unwrap())
-        .join("login")
-        .join("firebase-refresh.key");
+    let path = login_dir().join("firebase-refresh.key");
     let _ = remove_file(path);
 
+    commands.insert_resource(ProviderCredentials::default());
+    let _ = remove_file(login_dir().join("provider-credentials.ron"));
+
+    commands.remove_resource::<MfaPendingCredential>();
+    commands.remove_resource::<TotpEnrollmentSession>();
+
     next_state.set(AuthState::LoggedOut);
 
-    println!("Logged out.");
+    info!(target: LOG_LOGIN, "Logged out");
 }
 
 fn login_clear_resources(mut commands: Commands) {
     commands.remove_resource::<RedirectPort>();
-    commands.remove_resource::<GoogleToken>();
+
+    #[cfg(feature = "qr")]
+    commands.remove_resource::<LoginQrCodes>();
 }
 
 fn init_login(
     mut commands: Commands,
     login_keys: Res<LoginKeys>,
+    provider_config: Res<ProviderConfig>,
     mut ew: EventWriter<AuthUrlsEvent>,
     runtime: ResMut<TokioTasksRuntime>,
 ) {
@@ -365,7 +995,8 @@ fn init_login(
 
     match listener.set_nonblocking(true) {
         Ok(_) => {}
-        Err(err) => println!(
+        Err(err) => warn!(
+            target: LOG_LOGIN,
             "Couldn't set nonblocking listener! This may cause an app freeze on exit. {:?}",
             err
         ),
@@ -382,15 +1013,30 @@ fn init_login(
         if let Some(keys) = optional_keys {
             client_id = keys.0.clone();
         }
+        let settings = match provider_config.0.get(provider) {
+            Some(settings) => settings.clone(),
+            None => ProviderSettings::default_for(provider),
+        };
+        let redirect_uri = format!("http://127.0.0.1:{}", port);
         match provider {
             LoginProvider::Google => {
-                let google_url = Url::parse(&format!("https://accounts.google.com/o/oauth2/v2/auth?scope=openid profile email&response_type=code&redirect_uri=http://127.0.0.1:{}&client_id={}",port, client_id)).unwrap();
+                let google_url = settings.auth_url(
+                    "https://accounts.google.com/o/oauth2/v2/auth",
+                    &client_id,
+                    &redirect_uri,
+                );
                 auth_urls.insert(LoginProvider::Google, google_url);
             }
             LoginProvider::Github => {
-                let github_url: Url = Url::parse(&format!("https://github.com/login/oauth/authorize?scope=read:user&redirect_uri=http://127.0.0.1:{}&client_id={}", port, client_id )).unwrap();
+                let github_url = settings.auth_url(
+                    "https://github.com/login/oauth/authorize",
+                    &client_id,
+                    &redirect_uri,
+                );
                 auth_urls.insert(LoginProvider::Github, github_url);
             }
+            // Signed in with `SignInWithPassword`, no browser needed
+            LoginProvider::EmailPassword => {}
             unknown_provider => {
                 panic!("NOT IMPLEMENTED! {:?}", unknown_provider);
             }
@@ -482,7 +1128,10 @@ fn auth_code_to_firebase_token(
     api_key: Res<ApiKey>,
     emulator: Option<Res<AuthEmulatorUrl>>,
     login_keys: Res<LoginKeys>,
+    tenant_id: Option<Res<TenantId>>,
 ) {
+    let tenant_id = tenant_id.map(|tenant_id| tenant_id.0.clone());
+
     let root_url = match emulator {
         Some(url) => format!("{}/identitytoolkit.googleapis.com", url.0.clone()),
         None => "https://identitytoolkit.googleapis.com".into(),
@@ -491,19 +1140,20 @@ fn auth_code_to_firebase_token(
     for auth_code_event in auth_code_event_reader.iter() {
         let (provider, auth_code) = auth_code_event.0.clone();
 
-        if let Some(keys) = login_keys.0.get(&provider) {
-            if let Some((client_id, client_secret)) = keys {
-                let api_key = api_key.0.clone();
-                let port = format!("{}", port.0);
-                let auth_code = auth_code.clone();
-                let root_url = root_url.clone();
-                let client_secret = client_secret.clone();
-                let client_id = client_id.clone();
-                let provider = provider.clone();
-
-                runtime.spawn_background_task(|mut ctx| async move {
+        if let Some(Some((client_id, client_secret))) = login_keys.0.get(&provider) {
+            let api_key = api_key.0.clone();
+            let port = format!("{}", port.0);
+            let auth_code = auth_code.clone();
+            let root_url = root_url.clone();
+            let client_secret = client_secret.clone();
+            let client_id = client_id.clone();
+            let provider = provider.clone();
+            let tenant_id = tenant_id.clone();
+
+            runtime.spawn_background_task(|mut ctx| async move {
                 let client = reqwest::Client::new();
-                let mut body: HashMap<String, Value> = HashMap::new();
+                let mut post_body = String::new();
+                let mut credential = None;
 
                 match provider.clone() {
                     LoginProvider::Google => {
@@ -514,9 +1164,13 @@ fn auth_code_to_firebase_token(
                             .text("redirect_uri", format!("http://127.0.0.1:{port}"))
                             .text("grant_type", "authorization_code");
 
-                        #[derive(Deserialize, Debug)]
+                        #[derive(Deserialize)]
                         struct GoogleTokenResponse {
                             id_token: String,
+                            access_token: String,
+                            refresh_token: Option<String>,
+                            expires_in: Option<u64>,
+                            scope: Option<String>,
                         }
 
                         // Get Google Token
@@ -530,22 +1184,27 @@ fn auth_code_to_firebase_token(
                             .await
                             .unwrap();
 
-                        let id_token = google_token.id_token;
+                        let id_token = google_token.id_token.clone();
 
-                        body.insert(
-                            "postBody".into(),
-                            Value::String(format!(
-                                "id_token={}&providerId={}",
-                                id_token, "google.com"
-                            )),
-                        );
+                        credential = Some(ProviderCredential {
+                            access_token: Some(google_token.access_token),
+                            refresh_token: google_token.refresh_token,
+                            id_token: Some(google_token.id_token),
+                            expires_at: google_token.expires_in.map(|expires_in| unix_now() + expires_in),
+                            scopes: split_scopes(google_token.scope),
+                        });
+
+                        post_body = format!("id_token={}&providerId={}", id_token, "google.com");
                     }
                     LoginProvider::Github => {
                         // TODO no github on emulator
 
-                        #[derive(Deserialize, Debug)]
+                        #[derive(Deserialize)]
                         struct GithubTokenResponse {
-                            access_token: String
+                            access_token: String,
+                            refresh_token: Option<String>,
+                            expires_in: Option<u64>,
+                            scope: Option<String>,
                         }
 
                         let response = client.post(format!("https://github.com/login/oauth/access_token?client_id={}&client_secret={}&code={}",client_id,client_secret,auth_code))
@@ -557,139 +1216,1032 @@ fn auth_code_to_firebase_token(
                         .await
                         .unwrap();
 
-                        let access_token = response.access_token;
+                        let access_token = response.access_token.clone();
 
-                        body.insert(
-                            "postBody".into(), 
-                            Value::String(format!(
-                                "access_token={}&providerId={}",
-                                access_token, "github.com"
-                            ))
-                        );
+                        credential = Some(ProviderCredential {
+                            access_token: Some(response.access_token),
+                            refresh_token: response.refresh_token,
+                            id_token: None,
+                            expires_at: response.expires_in.map(|expires_in| unix_now() + expires_in),
+                            scopes: split_scopes(response.scope),
+                        });
+
+                        post_body =
+                            format!("access_token={}&providerId={}", access_token, "github.com");
                     }
                     _ => (),
                 }
 
-                // Add common params
-                body.insert(
-                    "requestUri".into(),
-                    Value::String(format!("http://127.0.0.1:{port}")),
-                );
-                body.insert("returnIdpCredential".into(), true.into());
-                body.insert("returnSecureToken".into(), true.into());
-
                 // Get Firebase Token
-                let firebase_token = client
-                    .post(format!(
-                        "{}/v1/accounts:signInWithIdp?key={}",
-                        root_url, api_key
-                    ))
-                    .json(&body)
-                    .send()
-                    .await
-                    .unwrap()
-                    .json::<TokenData>()
-                    .await
-                    .unwrap();
-
-                ctx.run_on_main_thread(move |ctx| {
-                    ctx.world.insert_resource(firebase_token);
-
-                    // Set next state
-                    ctx.world
-                        .insert_resource(NextState(Some(AuthState::LoggedIn)));
+                let firebase_token = sign_in_with_idp(
+                    &client,
+                    &root_url,
+                    &api_key,
+                    post_body,
+                    format!("http://127.0.0.1:{port}"),
+                    tenant_id,
+                )
+                .await;
+
+                ctx.run_on_main_thread(move |ctx| match firebase_token {
+                    Ok(firebase_token) => {
+                        if let Some(credential) = credential {
+                            ctx.world
+                                .resource_mut::<ProviderCredentials>()
+                                .0
+                                .insert(provider, credential);
+                        }
+
+                        finish_sign_in(ctx.world, firebase_token);
+                    }
+                    Err(message) => {
+                        warn!(target: LOG_LOGIN, "Sign in failed: {}", message);
+                        ctx.world.send_event(AuthErrorEvent(message));
+
+                        // The redirect listener stopped once the code arrived,
+                        // so start over with a new one and new auth URLs
+                        if *ctx.world.resource::<State<AuthState>>().get()
+                            == AuthState::GotAuthCode
+                        {
+                            ctx.world
+                                .insert_resource(NextState(Some(AuthState::LogIn)));
+                        }
+                    }
                 })
                 .await;
             });
-            }
         }
     }
 }
 
-fn save_refresh_token(token_data: Res<TokenData>, remember_login: Res<RememberLoginFlag>) {
-    if !remember_login.0 {
-        return;
+async fn sign_in_with_idp(
+    client: &Client,
+    root_url: &str,
+    api_key: &str,
+    post_body: String,
+    request_uri: String,
+    tenant_id: Option<String>,
+) -> Result<SignInResult, String> {
+    let mut body: HashMap<String, Value> = HashMap::new();
+    body.insert("postBody".into(), Value::String(post_body));
+    body.insert("requestUri".into(), Value::String(request_uri));
+    body.insert("returnIdpCredential".into(), true.into());
+    body.insert("returnSecureToken".into(), true.into());
+    if let Some(tenant_id) = &tenant_id {
+        body.insert("tenantId".into(), Value::String(tenant_id.clone()));
     }
 
-    let path = cache_dir()
-        .unwrap()
-        .join(std::env::var("CARGO_PKG_NAME").unwrap())
-        .join("login");
+    let response = client
+        .post(format!(
+            "{}/v1/accounts:signInWithIdp?key={}",
+            root_url, api_key
+        ))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    read_sign_in_response(response, tenant_id.as_deref()).await
+}
 
-    let dir_result = create_dir_all(path.clone());
+enum SignInResult {
+    Token(Box<TokenData>),
+    /// The first factor passed, but the account needs a second
+    SecondFactor(MfaPendingCredential, Vec<MfaFactor>),
+}
 
-    match dir_result {
-        Ok(()) => {}
-        Err(err) => println!("Couldn't create login directory: {:?}", err),
+async fn read_sign_in_response(
+    response: reqwest::Response,
+    tenant_id: Option<&str>,
+) -> Result<SignInResult, String> {
+    if !response.status().is_success() {
+        return Err(match response.json::<FirebaseErrorResponse>().await {
+            Ok(body) => body.error.message,
+            Err(err) => err.to_string(),
+        });
     }
 
-    let save_result = write(
-        path.clone().join("firebase-refresh.key"),
-        token_data.refresh_token.as_str(),
-    );
+    let body = response
+        .json::<Value>()
+        .await
+        .map_err(|err| err.to_string())?;
 
-    match save_result {
-        Ok(()) => {}
-        Err(err) => println!("Couldn't save refresh token to {:?}: {:?}", path, err),
+    if let Some(pending_credential) = body["mfaPendingCredential"].as_str() {
+        return Ok(SignInResult::SecondFactor(
+            MfaPendingCredential(pending_credential.into()),
+            MfaFactor::from_sign_in_response(&body),
+        ));
     }
+
+    let mut token_data =
+        serde_json::from_value::<TokenData>(body).map_err(|err| err.to_string())?;
+
+    check_tenant(&mut token_data, tenant_id)?;
+
+    Ok(SignInResult::Token(Box::new(token_data)))
 }
 
-fn refresh_login(
-    token_data: Res<TokenData>,
-    firebase_api_key: Res<ApiKey>,
+// Logs in, or asks for a second factor
+fn finish_sign_in(world: &mut World, result: SignInResult) {
+    match result {
+        SignInResult::Token(token_data) => {
+            world.insert_resource(*token_data);
+            world.insert_resource(NextState(Some(AuthState::LoggedIn)));
+        }
+        SignInResult::SecondFactor(pending_credential, factors) => {
+            world.insert_resource(pending_credential);
+            world.send_event(SecondFactorRequired { factors });
+        }
+    }
+}
+
+// Reads an ID token's claims, without verifying it
+fn token_claims(id_token: &str) -> Option<Value> {
+    let payload = id_token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+
+    serde_json::from_slice(&payload).ok()
+}
+
+// Rejects tokens issued for a different tenant, and stores the tenant in the session
+fn check_tenant(token_data: &mut TokenData, tenant_id: Option<&str>) -> Result<(), String> {
+    let token_tenant = token_claims(&token_data.id_token)
+        .and_then(|claims| claims["firebase"]["tenant"].as_str().map(String::from));
+
+    if token_tenant.as_deref() != tenant_id {
+        return Err(format!(
+            "TENANT_ID_MISMATCH : expected {:?}, token is for {:?}",
+            tenant_id, token_tenant
+        ));
+    }
+
+    token_data.tenant_id = token_tenant;
+    Ok(())
+}
+
+fn sign_in_with_credential(
+    mut er: EventReader<SignInWithCredential>,
     runtime: ResMut<TokioTasksRuntime>,
+    api_key: Res<ApiKey>,
     emulator: Option<Res<AuthEmulatorUrl>>,
+    tenant_id: Option<Res<TenantId>>,
 ) {
-    let refresh_token = token_data.refresh_token.clone();
-    let api_key = firebase_api_key.0.clone();
     let root_url = match emulator {
-        Some(url) => format!("{}/securetoken.googleapis.com", url.0),
-        None => "https://securetoken.googleapis.com".into(),
+        Some(url) => format!("{}/identitytoolkit.googleapis.com", url.0.clone()),
+        None => "https://identitytoolkit.googleapis.com".into(),
     };
 
-    runtime.spawn_background_task(|mut ctx| async move {
-        let client = Client::new();
+    for e in er.iter() {
+        let mut post_body = url::form_urlencoded::Serializer::new(String::new());
+
+        let credential = match &e.credential {
+            IdpCredential::IdToken(id_token) => {
+                post_body.append_pair("id_token", id_token);
+                ProviderCredential {
+                    access_token: None,
+                    refresh_token: None,
+                    id_token: Some(id_token.clone()),
+                    expires_at: None,
+                    scopes: Vec::new(),
+                }
+            }
+            IdpCredential::AccessToken(access_token) => {
+                post_body.append_pair("access_token", access_token);
+                ProviderCredential {
+                    access_token: Some(access_token.clone()),
+                    refresh_token: None,
+                    id_token: None,
+                    expires_at: None,
+                    scopes: Vec::new(),
+                }
+            }
+            IdpCredential::OAuth1 {
+                oauth_token,
+                oauth_token_secret,
+            } => {
+                post_body
+                    .append_pair("access_token", oauth_token)
+                    .append_pair("oauth_token_secret", oauth_token_secret);
+                ProviderCredential {
+                    access_token: Some(oauth_token.clone()),
+                    refresh_token: None,
+                    id_token: None,
+                    expires_at: None,
+                    scopes: Vec::new(),
+                }
+            }
+        };
 
-        let firebase_token = client
-            .post(format!("{}/v1/token?key={}", root_url, api_key))
-            .header("content-type", "application/x-www-form-urlencoded")
-            .body(format!(
-                "grant_type=refresh_token&refresh_token={}",
-                refresh_token
-            ))
-            .send()
-            .await
-            .unwrap()
-            .json::<TokenData>()
+        let post_body = post_body.append_pair("providerId", &e.provider_id).finish();
+        let provider = LoginProvider::from_provider_id(&e.provider_id);
+        let api_key = api_key.0.clone();
+        let root_url = root_url.clone();
+        let tenant_id = tenant_id.as_ref().map(|tenant_id| tenant_id.0.clone());
+
+        runtime.spawn_background_task(move |mut ctx| async move {
+            let client = Client::new();
+
+            let firebase_token = sign_in_with_idp(
+                &client,
+                &root_url,
+                &api_key,
+                post_body,
+                "http://localhost".into(),
+                tenant_id,
+            )
             .await;
 
-        let firebase_token = match firebase_token {
-            Ok(token) => token,
-            Err(_) => {
-                // Set state to logout on failure
-                ctx.run_on_main_thread(|ctx| {
-                    ctx.world.insert_resource(NextState(Some(AuthState::LogIn)))
-                })
-                .await;
-                return;
+            ctx.run_on_main_thread(move |ctx| match firebase_token {
+                Ok(firebase_token) => {
+                    if let Some(provider) = provider {
+                        ctx.world
+                            .resource_mut::<ProviderCredentials>()
+                            .0
+                            .insert(provider, credential);
+                    }
+
+                    finish_sign_in(ctx.world, firebase_token);
+                }
+                Err(message) => {
+                    warn!(target: LOG_LOGIN, "Sign in with credential failed: {}", message);
+                    ctx.world.send_event(AuthErrorEvent(message));
+                }
+            })
+            .await;
+        });
+    }
+}
+
+fn sign_in_with_password(
+    mut er: EventReader<SignInWithPassword>,
+    runtime: ResMut<TokioTasksRuntime>,
+    api_key: Res<ApiKey>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    tenant_id: Option<Res<TenantId>>,
+) {
+    let root_url = match emulator {
+        Some(url) => format!("{}/identitytoolkit.googleapis.com", url.0.clone()),
+        None => "https://identitytoolkit.googleapis.com".into(),
+    };
+
+    for e in er.iter() {
+        let endpoint = if e.create_account {
+            "signUp"
+        } else {
+            "signInWithPassword"
+        };
+        let url = format!("{}/v1/accounts:{}?key={}", root_url, endpoint, api_key.0);
+
+        let mut body: HashMap<String, Value> = HashMap::new();
+        body.insert("email".into(), Value::String(e.email.clone()));
+        body.insert("password".into(), Value::String(e.password.clone()));
+        body.insert("returnSecureToken".into(), true.into());
+
+        let tenant_id = tenant_id.as_ref().map(|tenant_id| tenant_id.0.clone());
+        if let Some(tenant_id) = &tenant_id {
+            body.insert("tenantId".into(), Value::String(tenant_id.clone()));
+        }
+
+        runtime.spawn_background_task(move |mut ctx| async move {
+            let client = Client::new();
+
+            let firebase_token = match client.post(url).json(&body).send().await {
+                Ok(response) => read_sign_in_response(response, tenant_id.as_deref()).await,
+                Err(err) => Err(err.to_string()),
+            };
+
+            ctx.run_on_main_thread(move |ctx| match firebase_token {
+                Ok(mut firebase_token) => {
+                    if let SignInResult::Token(token_data) = &mut firebase_token {
+                        token_data.provider_id = Some("password".into());
+                    }
+                    finish_sign_in(ctx.world, firebase_token);
+                }
+                Err(message) => {
+                    warn!(target: LOG_LOGIN, "Sign in with password failed: {}", message);
+                    ctx.world.send_event(AuthErrorEvent(message));
+                }
+            })
+            .await;
+        });
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Google separates scopes with spaces, GitHub with commas
+fn split_scopes(scope: Option<String>) -> Vec<String> {
+    scope
+        .unwrap_or_default()
+        .split([' ', ','])
+        .filter(|scope| !scope.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn load_provider_credentials() -> ProviderCredentials {
+    let f = File::open(login_dir().join("provider-credentials.ron"));
+
+    match f {
+        Ok(f) => match from_reader(f) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                warn!(target: LOG_STORAGE, "Couldn't read provider credentials: {:?}", err);
+                ProviderCredentials::default()
+            }
+        },
+        Err(_) => ProviderCredentials::default(),
+    }
+}
+
+fn save_provider_credentials(
+    credentials: Res<ProviderCredentials>,
+    remember_login: Res<RememberLoginFlag>,
+) {
+    if !remember_login.0 || !credentials.is_changed() || credentials.0.is_empty() {
+        return;
+    }
+
+    let path = login_dir();
+
+    match create_dir_all(path.clone()) {
+        Ok(()) => {}
+        Err(err) => error!(target: LOG_STORAGE, "Couldn't create login directory: {:?}", err),
+    }
+
+    let serialized = match ron::ser::to_string_pretty(&*credentials, PrettyConfig::default()) {
+        Ok(serialized) => serialized,
+        Err(err) => {
+            error!(target: LOG_STORAGE, "Couldn't serialize provider credentials: {:?}", err);
+            return;
+        }
+    };
+
+    match write(path.join("provider-credentials.ron"), serialized) {
+        Ok(()) => {}
+        Err(err) => error!(
+            target: LOG_STORAGE,
+            "Couldn't save provider credentials to {:?}: {:?}",
+            path, err
+        ),
+    }
+}
+
+fn refresh_provider_credential(
+    mut er: EventReader<RefreshProviderCredential>,
+    credentials: Res<ProviderCredentials>,
+    login_keys: Res<LoginKeys>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for e in er.iter() {
+        let provider = e.0.clone();
+
+        let Some(refresh_token) = credentials
+            .0
+            .get(&provider)
+            .and_then(|credential| credential.refresh_token.clone())
+        else {
+            warn!(target: LOG_PROVIDER, "No refresh token for {:?}, log in again instead", provider);
+            continue;
+        };
+
+        let Some(Some((client_id, client_secret))) = login_keys.0.get(&provider).cloned() else {
+            warn!(target: LOG_PROVIDER, "No login keys for {:?}", provider);
+            continue;
+        };
+
+        let token_url = match provider {
+            LoginProvider::Google => "https://oauth2.googleapis.com/token",
+            LoginProvider::Github => "https://github.com/login/oauth/access_token",
+            _ => {
+                warn!(target: LOG_PROVIDER, "Refreshing {:?} tokens is not supported", provider);
+                continue;
             }
         };
 
-        // Use Firebase Token
+        runtime.spawn_background_task(move |mut ctx| async move {
+            #[derive(Deserialize)]
+            struct RefreshTokenResponse {
+                access_token: String,
+                refresh_token: Option<String>,
+                id_token: Option<String>,
+                expires_in: Option<u64>,
+                scope: Option<String>,
+            }
+
+            let response = Client::new()
+                .post(token_url)
+                .header("Accept", "application/json")
+                .form(&[
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", refresh_token.as_str()),
+                    ("client_id", client_id.as_str()),
+                    ("client_secret", client_secret.as_str()),
+                ])
+                .send()
+                .await;
+
+            let response = match response {
+                Ok(response) => response.json::<RefreshTokenResponse>().await,
+                Err(err) => Err(err),
+            };
+
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => {
+                    warn!(target: LOG_PROVIDER, "Couldn't refresh {:?} token: {}", provider, err);
+                    ctx.run_on_main_thread(|ctx| {
+                        record(ctx.world, |diagnostics| diagnostics.provider_errors += 1);
+                    })
+                    .await;
+                    return;
+                }
+            };
+
+            ctx.run_on_main_thread(move |ctx| {
+                let mut credentials = ctx.world.resource_mut::<ProviderCredentials>();
+
+                if let Some(credential) = credentials.0.get_mut(&provider) {
+                    credential.access_token = Some(response.access_token);
+                    credential.expires_at = response
+                        .expires_in
+                        .map(|expires_in| unix_now() + expires_in);
+
+                    // Providers only send these when they change
+                    if response.refresh_token.is_some() {
+                        credential.refresh_token = response.refresh_token;
+                    }
+                    if response.id_token.is_some() {
+                        credential.id_token = response.id_token;
+                    }
+                    if response.scope.is_some() {
+                        credential.scopes = split_scopes(response.scope);
+                    }
+                }
+            })
+            .await;
+        });
+    }
+}
+
+fn login_dir() -> PathBuf {
+    cache_dir()
+        .unwrap()
+        .join(std::env::var("CARGO_PKG_NAME").unwrap())
+        .join("login")
+}
+
+fn load_saved_accounts() -> SavedAccounts {
+    let f = File::open(login_dir().join("accounts.ron"));
+
+    match f {
+        Ok(f) => match from_reader(f) {
+            Ok(accounts) => SavedAccounts(accounts),
+            Err(err) => {
+                warn!(target: LOG_STORAGE, "Couldn't read saved accounts: {:?}", err);
+                SavedAccounts::default()
+            }
+        },
+        Err(_) => SavedAccounts::default(),
+    }
+}
+
+fn write_saved_accounts(saved_accounts: &SavedAccounts) {
+    let path = login_dir();
+
+    match create_dir_all(path.clone()) {
+        Ok(()) => {}
+        Err(err) => error!(target: LOG_STORAGE, "Couldn't create login directory: {:?}", err),
+    }
+
+    let accounts = match ron::ser::to_string_pretty(&saved_accounts.0, PrettyConfig::default()) {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            error!(target: LOG_STORAGE, "Couldn't serialize saved accounts: {:?}", err);
+            return;
+        }
+    };
+
+    match write(path.join("accounts.ron"), accounts) {
+        Ok(()) => {}
+        Err(err) => error!(target: LOG_STORAGE, "Couldn't save accounts to {:?}: {:?}", path, err),
+    }
+}
+
+// The account a `SwitchAccount` refreshes into, once the current one is torn
+// down
+#[derive(Resource)]
+struct SwitchTo(TokenData);
+
+fn switch_account(
+    mut commands: Commands,
+    mut er: EventReader<SwitchAccount>,
+    saved_accounts: Res<SavedAccounts>,
+    current_state: Res<State<AuthState>>,
+    mut next_state: ResMut<NextState<AuthState>>,
+) {
+    for e in er.iter() {
+        // The loopback listener would log in over the switched account
+        if matches!(
+            current_state.get(),
+            AuthState::LogIn | AuthState::GotAuthCode
+        ) {
+            warn!(
+                target: LOG_LOGIN,
+                "Can't switch to {} while logging in, log out first", e.0
+            );
+            continue;
+        }
+
+        let account = saved_accounts.0.iter().find(|account| account.uid == e.0);
+
+        match account {
+            Some(account) => {
+                commands.insert_resource(SwitchTo(TokenData {
+                    local_id: account.uid.clone(),
+                    display_name: account.display_name.clone(),
+                    provider_id: account
+                        .provider
+                        .as_ref()
+                        .map(|provider| provider.provider_id().into()),
+                    refresh_token: account.refresh_token.clone(),
+                    ..Default::default()
+                }));
+
+                next_state.set(AuthState::SwitchingAccount);
+            }
+            None => warn!(target: LOG_LOGIN, "No saved account with uid {}", e.0),
+        }
+    }
+}
+
+// Drops everything belonging to the account switched away from, like a log
+// out that keeps the refresh token file for the next save to replace
+fn switch_clear_resources(
+    mut commands: Commands,
+    switch_to: Option<ResMut<SwitchTo>>,
+    mut forgotten_accounts: ResMut<ForgottenAccounts>,
+    mut next_state: ResMut<NextState<AuthState>>,
+) {
+    forgotten_accounts.0.clear();
+
+    commands.remove_resource::<AdminToken>();
+    commands.insert_resource(ProviderCredentials::default());
+    commands.remove_resource::<MfaPendingCredential>();
+    commands.remove_resource::<TotpEnrollmentSession>();
+
+    commands.remove_resource::<SwitchTo>();
+    match switch_to {
+        Some(mut switch_to) => {
+            commands.insert_resource(std::mem::take(&mut switch_to.0));
+            next_state.set(AuthState::Refreshing);
+        }
+        None => {
+            commands.remove_resource::<TokenData>();
+            next_state.set(AuthState::LoggedOut);
+        }
+    }
+}
+
+// Empty credentials aren't saved, so the old account's file is removed
+fn remove_provider_credentials_file() {
+    let _ = remove_file(login_dir().join("provider-credentials.ron"));
+}
+
+// Accounts forgotten while logged in, so refreshes don't save them again
+#[derive(Resource, Default)]
+struct ForgottenAccounts(HashSet<String>);
+
+fn forget_account(
+    mut er: EventReader<ForgetAccount>,
+    mut saved_accounts: ResMut<SavedAccounts>,
+    mut forgotten_accounts: ResMut<ForgottenAccounts>,
+    token_data: Option<Res<TokenData>>,
+) {
+    for e in er.iter() {
+        let forgotten = saved_accounts.forget(&e.0);
+        write_saved_accounts(&saved_accounts);
+
+        // Forgetting the account that auto logs in also removes its token,
+        // even when logged out or logged in as someone else
+        let path = login_dir().join("firebase-refresh.key");
+        let stored_token = std::fs::read_to_string(&path).ok();
+
+        let is_current = token_data
+            .as_ref()
+            .is_some_and(|token_data| token_data.local_id == e.0);
+        let is_stored = forgotten
+            .as_ref()
+            .is_some_and(|account| stored_token.as_deref() == Some(account.refresh_token.as_str()));
+
+        if is_current {
+            forgotten_accounts.0.insert(e.0.clone());
+        }
+        if is_current || is_stored {
+            let _ = remove_file(path);
+        }
+    }
+}
+
+fn save_refresh_token(
+    token_data: Res<TokenData>,
+    remember_login: Res<RememberLoginFlag>,
+    mut saved_accounts: ResMut<SavedAccounts>,
+    forgotten_accounts: Res<ForgottenAccounts>,
+) {
+    if !remember_login.0 || forgotten_accounts.0.contains(&token_data.local_id) {
+        return;
+    }
+
+    write_refresh_token(&token_data.refresh_token);
+
+    saved_accounts.remember(&token_data);
+    write_saved_accounts(&saved_accounts);
+}
+
+fn write_refresh_token(refresh_token: &str) {
+    let path = login_dir();
+
+    let dir_result = create_dir_all(path.clone());
+
+    match dir_result {
+        Ok(()) => {}
+        Err(err) => error!(target: LOG_STORAGE, "Couldn't create login directory: {:?}", err),
+    }
+
+    let save_result = write(path.clone().join("firebase-refresh.key"), refresh_token);
+
+    match save_result {
+        Ok(()) => {}
+        Err(err) => {
+            error!(target: LOG_STORAGE, "Couldn't save refresh token to {:?}: {:?}", path, err)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FirebaseErrorResponse {
+    error: FirebaseErrorBody,
+}
+
+#[derive(Deserialize)]
+struct FirebaseErrorBody {
+    message: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum RefreshError {
+    /// Couldn't reach the server, or it had a temporary problem
+    Network(String),
+    /// The request was rejected, e.g. the refresh token was revoked, so the
+    /// user has to log in again
+    Fatal(String),
+}
+
+// Only transport errors, server errors and rate limiting are worth retrying.
+// Any other 4xx, e.g. INVALID_REFRESH_TOKEN or a bad API key, won't go away
+fn classify_refresh_error(status: StatusCode, message: Option<String>) -> RefreshError {
+    let message = message.unwrap_or_else(|| status.to_string());
+
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        RefreshError::Network(message)
+    } else {
+        RefreshError::Fatal(message)
+    }
+}
+
+async fn request_refresh(
+    client: &Client,
+    root_url: &str,
+    api_key: &str,
+    refresh_token: &str,
+    tenant_id: Option<&str>,
+) -> Result<TokenData, RefreshError> {
+    let body = {
+        let mut body = url::form_urlencoded::Serializer::new(String::new());
+        body.append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", refresh_token);
+        if let Some(tenant_id) = tenant_id {
+            body.append_pair("tenantId", tenant_id);
+        }
+        body.finish()
+    };
+
+    let response = client
+        .post(format!("{}/v1/token?key={}", root_url, api_key))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| RefreshError::Network(err.to_string()))?;
+
+    if response.status().is_success() {
+        let mut token_data = response
+            .json::<TokenData>()
+            .await
+            .map_err(|err| RefreshError::Network(err.to_string()))?;
+
+        // A token for another tenant can't be used, so log in again
+        check_tenant(&mut token_data, tenant_id).map_err(RefreshError::Fatal)?;
+
+        return Ok(token_data);
+    }
+
+    let status = response.status();
+
+    let message = response
+        .json::<FirebaseErrorResponse>()
+        .await
+        .ok()
+        .map(|body| body.error.message);
+
+    Err(classify_refresh_error(status, message))
+}
+
+fn refresh_login(
+    token_data: Res<TokenData>,
+    firebase_api_key: Res<ApiKey>,
+    runtime: ResMut<TokioTasksRuntime>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    retry_settings: Res<RefreshRetrySettings>,
+    tenant_id: Option<Res<TenantId>>,
+) {
+    let refresh_token = token_data.refresh_token.clone();
+    let tenant_id = tenant_id.map(|tenant_id| tenant_id.0.clone());
+    let api_key = firebase_api_key.0.clone();
+    let root_url = match emulator {
+        Some(url) => format!("{}/securetoken.googleapis.com", url.0),
+        None => "https://securetoken.googleapis.com".into(),
+    };
+    let retry_settings = retry_settings.clone();
+
+    runtime.spawn_background_task(move |mut ctx| async move {
+        let client = Client::new();
+
+        let mut delay = retry_settings.initial_delay;
+        let mut offline_since: Option<Instant> = None;
+
+        loop {
+            match request_refresh(
+                &client,
+                &root_url,
+                &api_key,
+                &refresh_token,
+                tenant_id.as_deref(),
+            )
+            .await
+            {
+                Ok(firebase_token) => {
+                    let refresh_token = refresh_token.clone();
+
+                    // Use Firebase Token
+                    ctx.run_on_main_thread(move |ctx| {
+                        if !is_refreshing(ctx.world, &refresh_token) {
+                            return;
+                        }
+
+                        let token_data = match ctx.world.remove_resource::<TokenData>() {
+                            Some(token_data) => token_data.merge_refreshed(firebase_token),
+                            None => firebase_token,
+                        };
+                        ctx.world.insert_resource(token_data);
+                        record(ctx.world, |diagnostics| {
+                            diagnostics.last_refresh = Some(SystemTime::now())
+                        });
+
+                        // Set next state
+                        ctx.world
+                            .insert_resource(NextState(Some(AuthState::LoggedIn)));
+                    })
+                    .await;
+                    return;
+                }
+                Err(RefreshError::Fatal(message)) => {
+                    warn!(target: LOG_REFRESH, "Refresh rejected, logging in again: {}", message);
+
+                    let refresh_token = refresh_token.clone();
+
+                    ctx.run_on_main_thread(move |ctx| {
+                        record(ctx.world, |diagnostics| diagnostics.refresh_errors += 1);
+
+                        if is_refreshing(ctx.world, &refresh_token) {
+                            ctx.world.send_event(AuthErrorEvent(message));
+                            ctx.world.insert_resource(NextState(Some(AuthState::LogIn)))
+                        }
+                    })
+                    .await;
+                    return;
+                }
+                Err(RefreshError::Network(message)) => {
+                    warn!(
+                        target: LOG_REFRESH,
+                        "Couldn't refresh login, retrying in {:?}: {}",
+                        delay, message
+                    );
+
+                    let since = *offline_since.get_or_insert_with(Instant::now);
+
+                    let grace_period_over = retry_settings
+                        .offline_grace_period
+                        .is_some_and(|grace_period| since.elapsed() >= grace_period);
+
+                    let refresh_token = refresh_token.clone();
+
+                    let still_refreshing = ctx
+                        .run_on_main_thread(move |ctx| {
+                            record(ctx.world, |diagnostics| diagnostics.refresh_errors += 1);
+
+                            if !is_refreshing(ctx.world, &refresh_token) {
+                                return false;
+                            }
+
+                            let next_state = if grace_period_over {
+                                AuthState::LoggedOut
+                            } else {
+                                AuthState::Offline
+                            };
+
+                            ctx.world.insert_resource(NextState(Some(next_state)));
+
+                            !grace_period_over
+                        })
+                        .await;
+
+                    if !still_refreshing {
+                        return;
+                    }
+
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(retry_settings.max_delay);
+                }
+            }
+        }
+    });
+}
+
+// Stops a refresh task from acting after the user logs out or switches
+// account mid-retry
+fn is_refreshing(world: &World, refresh_token: &str) -> bool {
+    let refreshing = matches!(
+        world.resource::<State<AuthState>>().get(),
+        AuthState::Refreshing | AuthState::Offline
+    );
+
+    refreshing
+        && world
+            .get_resource::<TokenData>()
+            .is_some_and(|token_data| token_data.refresh_token == refresh_token)
+}
+
+/// Event to refresh the ID token without leaving `AuthState::LoggedIn`
+///
+/// `TokenData`, or `AdminToken` for a service account, is replaced in place,
+/// so nothing on `OnEnter(AuthState::LoggedIn)` runs again.
+/// bevy-firebase-firestore sends this shortly before the token expires, and
+/// when a request is rejected as UNAUTHENTICATED.
+#[derive(Event, Clone, Debug)]
+pub struct RefreshIdToken;
+
+// Set while a `RefreshIdToken` is underway, so requests meanwhile share it
+#[derive(Resource)]
+struct IdTokenRefreshing;
+
+#[allow(clippy::too_many_arguments)]
+fn refresh_id_token(
+    mut commands: Commands,
+    mut er: EventReader<RefreshIdToken>,
+    refreshing: Option<Res<IdTokenRefreshing>>,
+    token_data: Res<TokenData>,
+    firebase_api_key: Res<ApiKey>,
+    runtime: ResMut<TokioTasksRuntime>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    tenant_id: Option<Res<TenantId>>,
+) {
+    if er.is_empty() {
+        return;
+    }
+    er.clear();
+
+    if refreshing.is_some() {
+        return;
+    }
+    commands.insert_resource(IdTokenRefreshing);
+
+    let refresh_token = token_data.refresh_token.clone();
+    let tenant_id = tenant_id.map(|tenant_id| tenant_id.0.clone());
+    let api_key = firebase_api_key.0.clone();
+    let root_url = match emulator {
+        Some(url) => format!("{}/securetoken.googleapis.com", url.0),
+        None => "https://securetoken.googleapis.com".into(),
+    };
+
+    runtime.spawn_background_task(move |mut ctx| async move {
+        let result = request_refresh(
+            &Client::new(),
+            &root_url,
+            &api_key,
+            &refresh_token,
+            tenant_id.as_deref(),
+        )
+        .await;
+
         ctx.run_on_main_thread(move |ctx| {
-            ctx.world.insert_resource(firebase_token);
+            ctx.world.remove_resource::<IdTokenRefreshing>();
+
+            // Logged out or switched account while the refresh was underway
+            let logged_in = *ctx.world.resource::<State<AuthState>>().get() == AuthState::LoggedIn;
+            let same_account = ctx
+                .world
+                .get_resource::<TokenData>()
+                .is_some_and(|token_data| token_data.refresh_token == refresh_token);
+            if !logged_in || !same_account {
+                return;
+            }
 
-            // Set next state
-            ctx.world
-                .insert_resource(NextState(Some(AuthState::LoggedIn)));
+            match result {
+                Ok(firebase_token) => {
+                    debug!(target: LOG_REFRESH, "Refreshed ID token");
+
+                    let token_data = ctx
+                        .world
+                        .remove_resource::<TokenData>()
+                        .unwrap_or_default()
+                        .merge_refreshed(firebase_token);
+                    let rotated = token_data.refresh_token != refresh_token;
+                    ctx.world.insert_resource(token_data);
+                    record(ctx.world, |diagnostics| {
+                        diagnostics.last_refresh = Some(SystemTime::now())
+                    });
+
+                    let remember = ctx.world.resource::<RememberLoginFlag>().0
+                        && !ctx
+                            .world
+                            .resource::<ForgottenAccounts>()
+                            .0
+                            .contains(&ctx.world.resource::<TokenData>().local_id);
+                    if rotated && remember {
+                        write_refresh_token(&ctx.world.resource::<TokenData>().refresh_token);
+
+                        ctx.world.resource_scope(
+                            |world, mut saved_accounts: Mut<SavedAccounts>| {
+                                saved_accounts.remember(world.resource::<TokenData>());
+                                write_saved_accounts(&saved_accounts);
+                            },
+                        );
+                    }
+                }
+                Err(RefreshError::Fatal(message)) => {
+                    warn!(target: LOG_REFRESH, "Refresh rejected, logging in again: {}", message);
+                    record(ctx.world, |diagnostics| diagnostics.refresh_errors += 1);
+                    ctx.world.send_event(AuthErrorEvent(message));
+                    ctx.world.insert_resource(NextState(Some(AuthState::LogIn)));
+                }
+                // Hands over to `refresh_login`, which retries with backoff
+                // and goes `Offline` meanwhile
+                Err(RefreshError::Network(message)) => {
+                    warn!(target: LOG_REFRESH, "Couldn't refresh ID token: {}", message);
+                    record(ctx.world, |diagnostics| diagnostics.refresh_errors += 1);
+                    ctx.world
+                        .insert_resource(NextState(Some(AuthState::Refreshing)));
+                }
+            }
         })
         .await;
     });
 }
 
+/// Event to delete the logged in account, like `delete_account`
+///
+/// bevy-firebase-firestore sends this once a user's data is deleted.
+#[derive(Event, Clone, Debug)]
+pub struct DeleteAccount;
+
+fn delete_account_event(
+    mut er: EventReader<DeleteAccount>,
+    token_data: Res<TokenData>,
+    firebase_api_key: Res<ApiKey>,
+    runtime: ResMut<TokioTasksRuntime>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+) {
+    if er.is_empty() {
+        return;
+    }
+    er.clear();
+
+    delete_account(token_data, firebase_api_key, runtime, emulator);
+}
+
 /// Function to delete an account from Firebase
 ///
-/// To be triggered with on state change
+/// To be triggered with on state change. Once deleted, the account is
+/// removed from `SavedAccounts` and logged out. If Firebase refuses, e.g. with
+/// `CREDENTIAL_TOO_OLD_LOGIN_AGAIN`, an `AuthErrorEvent` is sent and the
+/// account stays logged in.
 ///
 /// # Examples
 ///
@@ -713,33 +2265,338 @@ pub fn delete_account(
     emulator: Option<Res<AuthEmulatorUrl>>,
 ) {
     let api_key = firebase_api_key.0.clone();
+    let uid = token_data.local_id.clone();
     let id_token = token_data.id_token.clone();
+    let tenant_id = token_data.tenant_id.clone();
     let root_url = match emulator {
         Some(url) => format!("{}/identitytoolkit.googleapis.com", url.0),
         None => "https://identitytoolkit.googleapis.com".into(),
     };
     runtime.spawn_background_task(|mut ctx| async move {
         let client = Client::new();
-        let mut body = HashMap::new();
-        body.insert("idToken", id_token);
-
-        let _res = client
-            .post(format!("{}/v1/accounts:delete?key={}", root_url, api_key))
-            .header("content-type", "application/json")
-            .json(&body)
-            .send()
-            .await
-            .unwrap()
-            .text()
-            .await;
+        let result =
+            request_delete_account(&client, &root_url, &api_key, id_token, tenant_id).await;
 
-        // TODO handle errors like CREDENTIAL_TOO_OLD
+        ctx.run_on_main_thread(move |ctx| match result {
+            Ok(()) => {
+                info!(target: LOG_LOGIN, "Deleted account {}", uid);
 
-        ctx.run_on_main_thread(move |ctx| {
-            // Set next state
-            ctx.world
-                .insert_resource(NextState(Some(AuthState::LogOut)));
+                let mut saved_accounts = ctx.world.resource_mut::<SavedAccounts>();
+                saved_accounts.forget(&uid);
+                write_saved_accounts(&saved_accounts);
+
+                ctx.world
+                    .insert_resource(NextState(Some(AuthState::LogOut)));
+            }
+            // e.g. CREDENTIAL_TOO_OLD_LOGIN_AGAIN, the account is kept and
+            // stays logged in
+            Err(message) => {
+                warn!(target: LOG_LOGIN, "Couldn't delete account: {}", message);
+                ctx.world.send_event(AuthErrorEvent(message));
+            }
         })
         .await;
     });
 }
+
+async fn request_delete_account(
+    client: &Client,
+    root_url: &str,
+    api_key: &str,
+    id_token: String,
+    tenant_id: Option<String>,
+) -> Result<(), String> {
+    let mut body = HashMap::new();
+    body.insert("idToken", id_token);
+    if let Some(tenant_id) = tenant_id {
+        body.insert("tenantId", tenant_id);
+    }
+
+    let response = client
+        .post(format!("{}/v1/accounts:delete?key={}", root_url, api_key))
+        .header("content-type", "application/json")
+        .json(&body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(match response.json::<FirebaseErrorResponse>().await {
+            Ok(body) => body.error.message,
+            Err(err) => err.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(uid: &str, refresh_token: &str) -> TokenData {
+        TokenData {
+            local_id: uid.into(),
+            refresh_token: refresh_token.into(),
+            ..Default::default()
+        }
+    }
+
+    fn run_system<M>(world: &mut World, system: impl IntoSystemConfigs<M>) {
+        let mut schedule = Schedule::default();
+        schedule.add_systems(system);
+        schedule.run(world);
+    }
+
+    #[test]
+    fn switching_drops_the_old_accounts_resources() {
+        let mut world = World::new();
+        world.init_resource::<NextState<AuthState>>();
+        world.insert_resource(ForgottenAccounts(HashSet::from(["carol".to_string()])));
+        world.insert_resource(TokenData {
+            id_token: "alice-id".into(),
+            ..token("alice", "a1")
+        });
+        world.insert_resource(ProviderCredentials(HashMap::from([(
+            LoginProvider::Google,
+            ProviderCredential {
+                access_token: Some("alice-google".into()),
+                refresh_token: None,
+                id_token: None,
+                expires_at: None,
+                scopes: vec![],
+            },
+        )])));
+        world.insert_resource(MfaPendingCredential("alice-mfa".into()));
+        world.insert_resource(AdminToken {
+            access_token: "admin".into(),
+            expires_at: None,
+        });
+        world.insert_resource(SwitchTo(token("bob", "b1")));
+
+        run_system(&mut world, switch_clear_resources);
+
+        let token_data = world.resource::<TokenData>();
+        assert_eq!(token_data.local_id, "bob");
+        assert_eq!(token_data.refresh_token, "b1");
+        assert!(token_data.id_token.is_empty());
+
+        assert!(world.resource::<ProviderCredentials>().0.is_empty());
+        assert!(world.resource::<ForgottenAccounts>().0.is_empty());
+        assert!(!world.contains_resource::<MfaPendingCredential>());
+        assert!(!world.contains_resource::<AdminToken>());
+        assert!(!world.contains_resource::<SwitchTo>());
+        assert_eq!(
+            world.resource::<NextState<AuthState>>().0,
+            Some(AuthState::Refreshing)
+        );
+    }
+
+    #[test]
+    fn switching_refuses_while_logging_in() {
+        let mut world = World::new();
+        world.insert_resource(State::new(AuthState::GotAuthCode));
+        world.init_resource::<NextState<AuthState>>();
+        world.init_resource::<Events<SwitchAccount>>();
+        world.insert_resource(SavedAccounts(vec![]));
+        world
+            .resource_mut::<SavedAccounts>()
+            .remember(&token("bob", "b1"));
+        world.send_event(SwitchAccount("bob".into()));
+
+        run_system(&mut world, switch_account);
+
+        assert!(!world.contains_resource::<SwitchTo>());
+        assert_eq!(world.resource::<NextState<AuthState>>().0, None);
+
+        world.insert_resource(State::new(AuthState::LoggedIn));
+        world.send_event(SwitchAccount("bob".into()));
+
+        run_system(&mut world, switch_account);
+
+        assert_eq!(world.resource::<SwitchTo>().0.local_id, "bob");
+        assert_eq!(
+            world.resource::<NextState<AuthState>>().0,
+            Some(AuthState::SwitchingAccount)
+        );
+    }
+
+    #[test]
+    fn remember_moves_an_account_to_the_front_once() {
+        let mut saved_accounts = SavedAccounts::default();
+
+        saved_accounts.remember(&token("alice", "a1"));
+        saved_accounts.remember(&token("bob", "b1"));
+        saved_accounts.remember(&token("alice", "a2"));
+
+        let uids: Vec<_> = saved_accounts.0.iter().map(|a| a.uid.as_str()).collect();
+        assert_eq!(uids, ["alice", "bob"]);
+        assert_eq!(saved_accounts.0[0].refresh_token, "a2");
+    }
+
+    #[test]
+    fn remember_keeps_profile_missing_from_a_refresh() {
+        let mut saved_accounts = SavedAccounts::default();
+
+        saved_accounts.remember(&TokenData {
+            display_name: Some("Alice".into()),
+            provider_id: Some("google.com".into()),
+            ..token("alice", "a1")
+        });
+        saved_accounts.remember(&token("alice", "a2"));
+
+        let account = &saved_accounts.0[0];
+        assert_eq!(account.display_name.as_deref(), Some("Alice"));
+        assert_eq!(account.provider, Some(LoginProvider::Google));
+        assert_eq!(account.refresh_token, "a2");
+    }
+
+    #[test]
+    fn forget_removes_only_that_account() {
+        let mut saved_accounts = SavedAccounts::default();
+        saved_accounts.remember(&token("alice", "a1"));
+        saved_accounts.remember(&token("bob", "b1"));
+
+        let forgotten = saved_accounts.forget("alice").unwrap();
+
+        assert_eq!(forgotten.refresh_token, "a1");
+        assert_eq!(saved_accounts.0.len(), 1);
+        assert_eq!(saved_accounts.0[0].uid, "bob");
+        assert!(saved_accounts.forget("alice").is_none());
+    }
+
+    #[test]
+    fn merge_refreshed_keeps_profile() {
+        let current = TokenData {
+            display_name: Some("Alice".into()),
+            provider_id: Some("github.com".into()),
+            id_token: "old".into(),
+            ..token("alice", "a1")
+        };
+        let refreshed = TokenData {
+            id_token: "new".into(),
+            expires_in: "3600".into(),
+            ..token("alice", "a2")
+        };
+
+        let merged = current.merge_refreshed(refreshed);
+
+        assert_eq!(merged.display_name.as_deref(), Some("Alice"));
+        assert_eq!(merged.provider_id.as_deref(), Some("github.com"));
+        assert_eq!(merged.id_token, "new");
+        assert_eq!(merged.refresh_token, "a2");
+        assert_eq!(merged.expires_in, "3600");
+    }
+
+    fn id_token(claims: Value) -> String {
+        let encode = |json: Value| URL_SAFE_NO_PAD.encode(json.to_string());
+        format!(
+            "{}.{}.signature",
+            encode(serde_json::json!({ "alg": "RS256" })),
+            encode(claims)
+        )
+    }
+
+    #[test]
+    fn token_claims_reads_the_payload() {
+        let claims = token_claims(&id_token(serde_json::json!({ "sub": "alice" }))).unwrap();
+        assert_eq!(claims["sub"], "alice");
+
+        // Some encoders pad anyway
+        let padded = format!(
+            "header.{}==.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"sub":"bob"}"#)
+        );
+        assert_eq!(token_claims(&padded).unwrap()["sub"], "bob");
+
+        assert!(token_claims("not-a-jwt").is_none());
+        assert!(token_claims("header.!!!.signature").is_none());
+        assert!(token_claims("header.bm90IGpzb24.signature").is_none());
+    }
+
+    #[test]
+    fn check_tenant_matches_the_firebase_tenant_claim() {
+        let tenant_token = id_token(serde_json::json!({ "firebase": { "tenant": "t-1" } }));
+        let plain_token = id_token(serde_json::json!({ "firebase": {} }));
+
+        let mut token_data = TokenData {
+            id_token: tenant_token.clone(),
+            ..Default::default()
+        };
+        assert!(check_tenant(&mut token_data, Some("t-1")).is_ok());
+        assert_eq!(token_data.tenant_id.as_deref(), Some("t-1"));
+
+        let mut token_data = TokenData {
+            id_token: plain_token.clone(),
+            ..Default::default()
+        };
+        assert!(check_tenant(&mut token_data, None).is_ok());
+        assert_eq!(token_data.tenant_id, None);
+
+        for (id_token, tenant_id) in [
+            (tenant_token.clone(), Some("t-2")),
+            (tenant_token, None),
+            (plain_token, Some("t-1")),
+        ] {
+            let mut token_data = TokenData {
+                id_token,
+                ..Default::default()
+            };
+            let error = check_tenant(&mut token_data, tenant_id).unwrap_err();
+            assert!(error.starts_with("TENANT_ID_MISMATCH"), "{error}");
+        }
+    }
+
+    #[test]
+    fn only_transient_refresh_errors_are_retried() {
+        let cases = [
+            (StatusCode::BAD_REQUEST, "INVALID_REFRESH_TOKEN", false),
+            (StatusCode::BAD_REQUEST, "TOKEN_EXPIRED", false),
+            (StatusCode::BAD_REQUEST, "INVALID_GRANT_TYPE", false),
+            (StatusCode::UNAUTHORIZED, "UNAUTHORIZED", false),
+            (StatusCode::FORBIDDEN, "API_KEY_INVALID", false),
+            (StatusCode::NOT_FOUND, "NOT_FOUND", false),
+            (StatusCode::TOO_MANY_REQUESTS, "QUOTA_EXCEEDED", true),
+            (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL", true),
+            (StatusCode::BAD_GATEWAY, "BAD_GATEWAY", true),
+            (StatusCode::SERVICE_UNAVAILABLE, "UNAVAILABLE", true),
+        ];
+
+        for (status, message, retried) in cases {
+            let error = classify_refresh_error(status, Some(message.into()));
+            let expected = if retried {
+                RefreshError::Network(message.into())
+            } else {
+                RefreshError::Fatal(message.into())
+            };
+            assert_eq!(error, expected, "{status}");
+        }
+    }
+
+    #[test]
+    fn refresh_errors_without_a_body_use_the_status() {
+        assert_eq!(
+            classify_refresh_error(StatusCode::BAD_REQUEST, None),
+            RefreshError::Fatal("400 Bad Request".into())
+        );
+        assert_eq!(
+            classify_refresh_error(StatusCode::GATEWAY_TIMEOUT, None),
+            RefreshError::Network("504 Gateway Timeout".into())
+        );
+    }
+
+    #[test]
+    fn provider_ids_round_trip() {
+        for provider in [
+            LoginProvider::Google,
+            LoginProvider::Github,
+            LoginProvider::EmailPassword,
+            LoginProvider::Yahoo,
+        ] {
+            assert_eq!(
+                LoginProvider::from_provider_id(provider.provider_id()),
+                Some(provider)
+            );
+        }
+    }
+}
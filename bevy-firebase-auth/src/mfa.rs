@@ -0,0 +1,469 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::{
+    check_tenant, diagnostics::redact, diagnostics::LOG_MFA, token_claims, write_refresh_token,
+    write_saved_accounts, ApiKey, AuthEmulatorUrl, AuthErrorEvent, AuthState,
+    FirebaseErrorResponse, RememberLoginFlag, SavedAccounts, TenantId, TokenData,
+};
+
+/// A second factor enrolled on an account
+#[derive(Clone, Debug)]
+pub struct MfaFactor {
+    /// ID to send back in `FinalizeSecondFactor`
+    pub enrollment_id: String,
+    pub display_name: Option<String>,
+    pub kind: MfaFactorKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MfaFactorKind {
+    /// Code from an authenticator app
+    Totp,
+    /// Code sent by SMS to this masked phone number. Not yet supported for sign in
+    Phone(String),
+    Unknown,
+}
+
+impl MfaFactor {
+    fn from_mfa_info(info: &Value) -> Option<Self> {
+        let kind = if info.get("totpInfo").is_some() {
+            MfaFactorKind::Totp
+        } else if let Some(phone) = info["phoneInfo"].as_str() {
+            MfaFactorKind::Phone(phone.into())
+        } else {
+            MfaFactorKind::Unknown
+        };
+
+        Some(MfaFactor {
+            enrollment_id: info["mfaEnrollmentId"].as_str()?.into(),
+            display_name: info["displayName"].as_str().map(String::from),
+            kind,
+        })
+    }
+
+    pub(crate) fn from_sign_in_response(response: &Value) -> Vec<Self> {
+        response["mfaInfo"]
+            .as_array()
+            .map(|info| info.iter().filter_map(MfaFactor::from_mfa_info).collect())
+            .unwrap_or_default()
+    }
+}
+
+// Proof the first factor passed, exchanged for tokens with the second
+#[derive(Resource, Clone)]
+pub(crate) struct MfaPendingCredential(pub(crate) String);
+
+/// Event sent when a sign in needs a second factor to finish
+///
+/// Answer with `FinalizeSecondFactor`.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn ask_for_code(mut er: EventReader<SecondFactorRequired>) {
+///     for e in er.iter() {
+///         for factor in e.factors.iter() {
+///             if factor.kind == MfaFactorKind::Totp {
+///                 println!("Enter the code from {:?}", factor.display_name);
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct SecondFactorRequired {
+    pub factors: Vec<MfaFactor>,
+}
+
+/// Event to finish a sign in with a TOTP code
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn submit_code(mut ew: EventWriter<FinalizeSecondFactor>, factor: &MfaFactor) {
+///     ew.send(FinalizeSecondFactor {
+///         enrollment_id: factor.enrollment_id.clone(),
+///         code: "123456".into(),
+///     });
+/// }
+/// ```
+#[derive(Event, Clone)]
+pub struct FinalizeSecondFactor {
+    pub enrollment_id: String,
+    pub code: String,
+}
+
+/// Event to start enrolling an authenticator app for the logged in user
+///
+/// Answered with `TotpEnrollmentStarted`. Firebase may ask for a recent
+/// login first, which arrives as an `AuthErrorEvent`.
+#[derive(Event, Clone)]
+pub struct StartTotpEnrollment {
+    /// Shown in the authenticator app, usually the game's name
+    pub issuer: String,
+}
+
+/// Event holding the TOTP secret to add to an authenticator app
+///
+/// Show `otpauth_uri` as a QR code, or `secret_key` for manual entry, then
+/// send `FinalizeTotpEnrollment` with a code from the app. The secret is
+/// redacted in `Debug` output.
+#[derive(Event, Clone)]
+pub struct TotpEnrollmentStarted {
+    pub secret_key: String,
+    pub otpauth_uri: String,
+    pub code_length: u32,
+    pub period_secs: u32,
+}
+
+impl fmt::Debug for TotpEnrollmentStarted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TotpEnrollmentStarted")
+            .field("secret_key", &redact(&self.secret_key))
+            .field("otpauth_uri", &redact(&self.otpauth_uri))
+            .field("code_length", &self.code_length)
+            .field("period_secs", &self.period_secs)
+            .finish()
+    }
+}
+
+/// Event to finish enrolling an authenticator app with a code from it
+#[derive(Event, Clone)]
+pub struct FinalizeTotpEnrollment {
+    pub code: String,
+    /// Name shown for this factor in `SecondFactorRequired`
+    pub display_name: Option<String>,
+}
+
+/// Event sent once an authenticator app is enrolled
+#[derive(Event, Clone, Debug)]
+pub struct TotpEnrolled;
+
+#[derive(Resource, Clone)]
+pub(crate) struct TotpEnrollmentSession(String);
+
+fn mfa_url(emulator: Option<Res<AuthEmulatorUrl>>, method: &str, api_key: &str) -> String {
+    let root_url = match emulator {
+        Some(url) => format!("{}/identitytoolkit.googleapis.com", url.0.clone()),
+        None => "https://identitytoolkit.googleapis.com".into(),
+    };
+
+    format!("{}/v2/accounts/{}?key={}", root_url, method, api_key)
+}
+
+async fn post_mfa(
+    url: String,
+    mut body: Value,
+    tenant_id: Option<String>,
+) -> Result<Value, String> {
+    if let Some(tenant_id) = tenant_id {
+        body["tenantId"] = Value::String(tenant_id);
+    }
+
+    let response = Client::new()
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(match response.json::<FirebaseErrorResponse>().await {
+            Ok(body) => body.error.message,
+            Err(err) => err.to_string(),
+        });
+    }
+
+    response
+        .json::<Value>()
+        .await
+        .map_err(|err| err.to_string())
+}
+
+// MFA endpoints only return the tokens, so the rest comes from the ID token's claims
+fn token_data_from_response(
+    response: &Value,
+    tenant_id: Option<&str>,
+) -> Result<TokenData, String> {
+    let id_token = response["idToken"].as_str().unwrap_or_default().to_string();
+    let claims = token_claims(&id_token).unwrap_or_default();
+
+    // Sent as a string like other endpoints when present, otherwise it's the
+    // ID token's lifetime
+    let expires_in = match response["expiresIn"].as_str() {
+        Some(expires_in) => expires_in.to_string(),
+        None => match (claims["exp"].as_u64(), claims["iat"].as_u64()) {
+            (Some(exp), Some(iat)) if exp > iat => (exp - iat).to_string(),
+            _ => return Err("MISSING_EXPIRES_IN : no expiresIn or exp claim".into()),
+        },
+    };
+
+    let mut token_data = TokenData {
+        local_id: claims["user_id"].as_str().unwrap_or_default().into(),
+        email: claims["email"].as_str().map(String::from),
+        email_verified: claims["email_verified"].as_bool(),
+        display_name: claims["name"].as_str().map(String::from),
+        provider_id: claims["firebase"]["sign_in_provider"]
+            .as_str()
+            .map(String::from),
+        id_token,
+        refresh_token: response["refreshToken"].as_str().unwrap_or_default().into(),
+        expires_in,
+        ..Default::default()
+    };
+
+    check_tenant(&mut token_data, tenant_id)?;
+
+    Ok(token_data)
+}
+
+pub(crate) fn finalize_second_factor(
+    mut er: EventReader<FinalizeSecondFactor>,
+    runtime: ResMut<TokioTasksRuntime>,
+    api_key: Res<ApiKey>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    tenant_id: Option<Res<TenantId>>,
+    pending: Option<Res<MfaPendingCredential>>,
+) {
+    let url = mfa_url(emulator, "mfaSignIn:finalize", &api_key.0);
+    let tenant_id = tenant_id.map(|tenant_id| tenant_id.0.clone());
+
+    for e in er.iter() {
+        let Some(pending) = &pending else {
+            warn!(target: LOG_MFA, "No sign in is waiting for a second factor");
+            continue;
+        };
+
+        let body = json!({
+            "mfaPendingCredential": pending.0,
+            "mfaEnrollmentId": e.enrollment_id,
+            "totpVerificationInfo": { "verificationCode": e.code },
+        });
+        let url = url.clone();
+        let tenant_id = tenant_id.clone();
+
+        runtime.spawn_background_task(move |mut ctx| async move {
+            let token_data = post_mfa(url, body, tenant_id.clone())
+                .await
+                .and_then(|response| token_data_from_response(&response, tenant_id.as_deref()));
+
+            ctx.run_on_main_thread(move |ctx| match token_data {
+                Ok(token_data) => {
+                    ctx.world.remove_resource::<MfaPendingCredential>();
+                    ctx.world.insert_resource(token_data);
+                    ctx.world
+                        .insert_resource(NextState(Some(AuthState::LoggedIn)));
+                }
+                Err(message) => {
+                    warn!(target: LOG_MFA, "Second factor failed: {}", message);
+                    ctx.world.send_event(AuthErrorEvent(message));
+                }
+            })
+            .await;
+        });
+    }
+}
+
+pub(crate) fn start_totp_enrollment(
+    mut er: EventReader<StartTotpEnrollment>,
+    runtime: ResMut<TokioTasksRuntime>,
+    api_key: Res<ApiKey>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    token_data: Res<TokenData>,
+) {
+    let url = mfa_url(emulator, "mfaEnrollment:start", &api_key.0);
+
+    for e in er.iter() {
+        let body = json!({
+            "idToken": token_data.id_token,
+            "totpEnrollmentInfo": {},
+        });
+        let url = url.clone();
+        let tenant_id = token_data.tenant_id.clone();
+        let issuer = e.issuer.clone();
+        let account = token_data
+            .email
+            .clone()
+            .unwrap_or_else(|| token_data.local_id.clone());
+
+        runtime.spawn_background_task(move |mut ctx| async move {
+            let response = post_mfa(url, body, tenant_id).await;
+
+            ctx.run_on_main_thread(move |ctx| {
+                let session = match response {
+                    Ok(response) => response["totpSessionInfo"].clone(),
+                    Err(message) => {
+                        warn!(target: LOG_MFA, "Couldn't start TOTP enrollment: {}", message);
+                        ctx.world.send_event(AuthErrorEvent(message));
+                        return;
+                    }
+                };
+
+                let secret_key = session["sharedSecretKey"].as_str().unwrap_or_default();
+                let algorithm = session["hashingAlgorithm"].as_str().unwrap_or("SHA1");
+                let code_length = session["verificationCodeLength"].as_u64().unwrap_or(6) as u32;
+                let period_secs = session["periodSec"].as_u64().unwrap_or(30) as u32;
+
+                let otpauth_uri = format!(
+                    "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+                    uri_encode(&issuer),
+                    uri_encode(&account),
+                    secret_key,
+                    uri_encode(&issuer),
+                    algorithm,
+                    code_length,
+                    period_secs
+                );
+
+                ctx.world.insert_resource(TotpEnrollmentSession(
+                    session["sessionInfo"].as_str().unwrap_or_default().into(),
+                ));
+
+                ctx.world.send_event(TotpEnrollmentStarted {
+                    secret_key: secret_key.into(),
+                    otpauth_uri,
+                    code_length,
+                    period_secs,
+                });
+            })
+            .await;
+        });
+    }
+}
+
+fn uri_encode(value: &str) -> String {
+    // byte_serialize escapes literal '+', so any left are spaces
+    url::form_urlencoded::byte_serialize(value.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+}
+
+pub(crate) fn finalize_totp_enrollment(
+    mut er: EventReader<FinalizeTotpEnrollment>,
+    runtime: ResMut<TokioTasksRuntime>,
+    api_key: Res<ApiKey>,
+    emulator: Option<Res<AuthEmulatorUrl>>,
+    token_data: Res<TokenData>,
+    session: Option<Res<TotpEnrollmentSession>>,
+) {
+    let url = mfa_url(emulator, "mfaEnrollment:finalize", &api_key.0);
+
+    for e in er.iter() {
+        let Some(session) = &session else {
+            warn!(target: LOG_MFA, "No TOTP enrollment has been started");
+            continue;
+        };
+
+        let mut body = json!({
+            "idToken": token_data.id_token,
+            "totpVerificationInfo": {
+                "sessionInfo": session.0,
+                "verificationCode": e.code,
+            },
+        });
+        if let Some(display_name) = &e.display_name {
+            body["displayName"] = Value::String(display_name.clone());
+        }
+        let url = url.clone();
+        let tenant_id = token_data.tenant_id.clone();
+
+        runtime.spawn_background_task(move |mut ctx| async move {
+            let response = post_mfa(url, body, tenant_id).await;
+
+            ctx.run_on_main_thread(move |ctx| {
+                let response = match response {
+                    Ok(response) => response,
+                    Err(message) => {
+                        warn!(target: LOG_MFA, "Couldn't finish TOTP enrollment: {}", message);
+                        ctx.world.send_event(AuthErrorEvent(message));
+                        return;
+                    }
+                };
+
+                ctx.world.remove_resource::<TotpEnrollmentSession>();
+
+                // Enrolling issues new tokens, and the old refresh token stops working
+                let mut token_data = ctx.world.resource_mut::<TokenData>();
+                if let Some(id_token) = response["idToken"].as_str() {
+                    token_data.id_token = id_token.into();
+                }
+                if let Some(refresh_token) = response["refreshToken"].as_str() {
+                    token_data.refresh_token = refresh_token.into();
+                }
+                let refresh_token = token_data.refresh_token.clone();
+
+                if ctx.world.resource::<RememberLoginFlag>().0 {
+                    write_refresh_token(&refresh_token);
+
+                    ctx.world
+                        .resource_scope(|world, mut saved_accounts: Mut<SavedAccounts>| {
+                            saved_accounts.remember(world.resource::<TokenData>());
+                            write_saved_accounts(&saved_accounts);
+                        });
+                }
+
+                ctx.world.send_event(TotpEnrolled);
+            })
+            .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    use super::*;
+
+    fn id_token(claims: Value) -> String {
+        format!(
+            "header.{}.signature",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn expires_in_comes_from_the_response() {
+        let response = json!({
+            "idToken": id_token(json!({ "user_id": "alice", "iat": 100, "exp": 3700 })),
+            "refreshToken": "refresh",
+            "expiresIn": "1800",
+        });
+
+        let token_data = token_data_from_response(&response, None).unwrap();
+
+        assert_eq!(token_data.local_id, "alice");
+        assert_eq!(token_data.expires_in, "1800");
+    }
+
+    #[test]
+    fn expires_in_falls_back_to_the_token_lifetime() {
+        let response = json!({
+            "idToken": id_token(json!({ "user_id": "alice", "iat": 100, "exp": 1900 })),
+            "refreshToken": "refresh",
+        });
+
+        let token_data = token_data_from_response(&response, None).unwrap();
+
+        assert_eq!(token_data.expires_in, "1800");
+    }
+
+    #[test]
+    fn expires_in_is_required() {
+        let response = json!({
+            "idToken": id_token(json!({ "user_id": "alice" })),
+            "refreshToken": "refresh",
+        });
+
+        assert!(token_data_from_response(&response, None).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use qrcode::{Color, QrCode};
+
+pub use qrcode::EcLevel;
+
+use crate::{diagnostics::LOG_LOGIN, AuthUrlsEvent, LoginProvider};
+
+/// Bevy `Resource` controlling how login QR codes are rendered
+///
+/// # Examples
+///
+/// Bigger codes with more error correction, for a TV across the room:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// # let mut app = App::new();
+/// app.insert_resource(LoginQrSettings {
+///     module_size: 12,
+///     error_correction: EcLevel::Q,
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct LoginQrSettings {
+    /// Width and height of one QR module, in pixels
+    pub module_size: u32,
+    /// Blank border around the code, in modules. Scanners expect at least 4
+    pub quiet_zone: u32,
+    pub error_correction: EcLevel,
+}
+
+impl Default for LoginQrSettings {
+    fn default() -> Self {
+        LoginQrSettings {
+            module_size: 8,
+            quiet_zone: 4,
+            error_correction: EcLevel::M,
+        }
+    }
+}
+
+/// Bevy `Resource` holding a QR code image of each provider's auth URL
+///
+/// Updated whenever an `AuthUrlsEvent` is sent, and removed on login.
+///
+/// # Examples
+///
+/// Showing the Google login code:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// fn show_qr_code(mut commands: Commands, qr_codes: Option<Res<LoginQrCodes>>) {
+///     let Some(qr_codes) = qr_codes else { return };
+///
+///     if qr_codes.is_added() {
+///         if let Some(image) = qr_codes.0.get(&LoginProvider::Google) {
+///             commands.spawn(ImageBundle {
+///                 image: image.clone().into(),
+///                 ..default()
+///             });
+///         }
+///     }
+/// }
+/// ```
+#[derive(Resource, Clone, Debug, Default)]
+pub struct LoginQrCodes(pub HashMap<LoginProvider, Handle<Image>>);
+
+/// Renders `data` as a black on white QR code `Image`
+///
+/// Returns `None` if the data is too long to fit in a QR code.
+pub fn qr_code_image(data: &str, settings: &LoginQrSettings) -> Option<Image> {
+    let code = QrCode::with_error_correction_level(data, settings.error_correction).ok()?;
+
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+
+    let module_size = settings.module_size.max(1);
+    let size = (modules + settings.quiet_zone * 2) * module_size;
+
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+
+    for y in 0..size {
+        for x in 0..size {
+            let module_x = (x / module_size).checked_sub(settings.quiet_zone);
+            let module_y = (y / module_size).checked_sub(settings.quiet_zone);
+
+            let dark = match (module_x, module_y) {
+                (Some(mx), Some(my)) if mx < modules && my < modules => {
+                    colors[(my * modules + mx) as usize] == Color::Dark
+                }
+                _ => false,
+            };
+
+            let value = if dark { 0 } else { 255 };
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+
+    Some(Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+    ))
+}
+
+pub(crate) fn auth_url_qr_codes(
+    mut commands: Commands,
+    mut er: EventReader<AuthUrlsEvent>,
+    settings: Res<LoginQrSettings>,
+    images: Option<ResMut<Assets<Image>>>,
+) {
+    // Headless apps have no image assets to render into
+    let Some(mut images) = images else {
+        er.clear();
+        return;
+    };
+
+    for e in er.iter() {
+        let mut qr_codes = HashMap::new();
+
+        for (provider, auth_url) in e.0.iter() {
+            match qr_code_image(auth_url.as_str(), &settings) {
+                Some(image) => {
+                    qr_codes.insert(provider.clone(), images.add(image));
+                }
+                None => warn!(
+                    target: LOG_LOGIN,
+                    "Couldn't fit {:?} auth URL in a QR code",
+                    provider
+                ),
+            }
+        }
+
+        commands.insert_resource(LoginQrCodes(qr_codes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://accounts.google.com/o/oauth2/v2/auth?client_id=abc&redirect_uri=http://127.0.0.1:4242";
+
+    // RGBA of the pixel at `x`, `y`
+    fn pixel(image: &Image, x: u32, y: u32) -> &[u8] {
+        let i = ((y * image.texture_descriptor.size.width + x) * 4) as usize;
+        &image.data[i..i + 4]
+    }
+
+    fn settings(module_size: u32, quiet_zone: u32, error_correction: EcLevel) -> LoginQrSettings {
+        LoginQrSettings {
+            module_size,
+            quiet_zone,
+            error_correction,
+        }
+    }
+
+    #[test]
+    fn size_follows_the_settings() {
+        let modules = QrCode::with_error_correction_level(URL, EcLevel::M)
+            .unwrap()
+            .width() as u32;
+
+        for (module_size, quiet_zone) in [(8, 4), (3, 0), (1, 10)] {
+            let image = qr_code_image(URL, &settings(module_size, quiet_zone, EcLevel::M)).unwrap();
+            let size = (modules + quiet_zone * 2) * module_size;
+
+            assert_eq!(image.texture_descriptor.size.width, size);
+            assert_eq!(image.texture_descriptor.size.height, size);
+            assert_eq!(image.data.len(), (size * size * 4) as usize);
+        }
+
+        // A zero module size would be an empty image
+        let image = qr_code_image(URL, &settings(0, 0, EcLevel::M)).unwrap();
+        assert_eq!(image.texture_descriptor.size.width, modules);
+    }
+
+    #[test]
+    fn quiet_zone_is_white_and_modules_are_black_or_white() {
+        let (module_size, quiet_zone) = (4, 2);
+        let image = qr_code_image(URL, &settings(module_size, quiet_zone, EcLevel::M)).unwrap();
+        let size = image.texture_descriptor.size.width;
+        let border = module_size * quiet_zone;
+
+        for y in 0..size {
+            for x in 0..size {
+                let rgba = pixel(&image, x, y);
+                assert!(rgba == [0, 0, 0, 255] || rgba == [255, 255, 255, 255]);
+
+                let in_border =
+                    x < border || y < border || x >= size - border || y >= size - border;
+                if in_border {
+                    assert_eq!(rgba, [255, 255, 255, 255], "({x}, {y})");
+                }
+
+                // Every pixel of a module has its colour
+                let module_corner = pixel(&image, x - x % module_size, y - y % module_size);
+                assert_eq!(rgba, module_corner);
+            }
+        }
+
+        // The top left finder pattern starts with a dark module
+        assert_eq!(pixel(&image, border, border), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn error_correction_is_used() {
+        let width = |level| {
+            qr_code_image(URL, &settings(1, 0, level))
+                .unwrap()
+                .texture_descriptor
+                .size
+                .width
+        };
+
+        assert!(width(EcLevel::H) > width(EcLevel::L));
+
+        // Fits at the lowest level only
+        let long = "a".repeat(2500);
+        assert!(qr_code_image(&long, &settings(1, 0, EcLevel::L)).is_some());
+        assert!(qr_code_image(&long, &settings(1, 0, EcLevel::H)).is_none());
+    }
+}
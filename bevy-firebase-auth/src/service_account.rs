@@ -0,0 +1,406 @@
+use std::{fmt, path::Path, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use reqwest::Client;
+use ring::{
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    diagnostics::{redact, LOG_LOGIN},
+    unix_now, AuthErrorEvent, AuthState, RefreshIdToken,
+};
+
+// Scopes asked for in the JWT-bearer exchange
+const ADMIN_SCOPES: &str =
+    "https://www.googleapis.com/auth/cloud-platform https://www.googleapis.com/auth/datastore";
+
+// How long before expiry an admin token is exchanged again
+const ADMIN_REFRESH_MARGIN: u64 = 300;
+
+// How long to wait between attempts at exchanging it again
+const ADMIN_RETRY_DELAY: u64 = 30;
+
+/// A Google service account key, as downloaded from the Cloud console
+///
+/// The private key is redacted in `Debug` output.
+#[derive(Deserialize, Clone)]
+pub struct ServiceAccountKey {
+    pub project_id: String,
+    pub private_key_id: String,
+    private_key: String,
+    pub client_email: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".into()
+}
+
+impl ServiceAccountKey {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|err| err.to_string())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::from_json(&json)
+    }
+
+    // Signs a JWT asserting this service account, for the JWT-bearer grant
+    fn assertion(&self, now: u64) -> Result<String, String> {
+        let header = json!({
+            "alg": "RS256",
+            "typ": "JWT",
+            "kid": self.private_key_id,
+        });
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": ADMIN_SCOPES,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+
+        let der = rustls_pemfile::pkcs8_private_keys(&mut self.private_key.as_bytes())
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("No PKCS#8 private key in service account")?;
+
+        let key_pair = RsaKeyPair::from_pkcs8(&der).map_err(|err| err.to_string())?;
+
+        let mut signature = vec![0; key_pair.public().modulus_len()];
+        key_pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|err| err.to_string())?;
+
+        Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+impl fmt::Debug for ServiceAccountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAccountKey")
+            .field("project_id", &self.project_id)
+            .field("private_key_id", &self.private_key_id)
+            .field("private_key", &redact(&self.private_key))
+            .field("client_email", &self.client_email)
+            .field("token_uri", &self.token_uri)
+            .finish()
+    }
+}
+
+/// Admin credentials to log in with instead of a user, for backend tools
+///
+/// Admin requests bypass Firestore security rules.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_auth::*;
+/// # let mut app = App::new();
+/// app.add_plugins(AuthPlugin {
+///     service_account: Some(ServiceAccount::Key(
+///         ServiceAccountKey::from_file("service-account.json").unwrap(),
+///     )),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub enum ServiceAccount {
+    /// Exchanges a signed JWT for an OAuth2 access token
+    Key(ServiceAccountKey),
+    /// The emulators' `Bearer owner` token, for local admin tools and tests
+    EmulatorOwner,
+}
+
+/// Bevy `Resource` holding the admin access token while logged in with a
+/// `ServiceAccount`
+///
+/// bevy-firebase-firestore sends this in place of the user's ID token.
+#[derive(Resource, Clone)]
+pub struct AdminToken {
+    pub access_token: String,
+    /// Seconds since the unix epoch. `None` if the token doesn't expire
+    pub expires_at: Option<u64>,
+}
+
+impl AdminToken {
+    /// Time left before the token expires. `None` if it doesn't expire
+    pub fn time_to_expiry(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| Duration::from_secs(expires_at.saturating_sub(unix_now())))
+    }
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminToken")
+            .field("access_token", &redact(&self.access_token))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct AdminTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+async fn exchange_service_account_key(
+    client: &Client,
+    key: &ServiceAccountKey,
+) -> Result<AdminToken, String> {
+    let now = unix_now();
+    let assertion = key.assertion(now)?;
+
+    let response = client
+        .post(&key.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // OAuth2 errors look like `{"error": "invalid_grant", "error_description": ...}`
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("{}: {}", status, body));
+    }
+
+    let response = response
+        .json::<AdminTokenResponse>()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(AdminToken {
+        access_token: response.access_token,
+        expires_at: Some(now + response.expires_in),
+    })
+}
+
+async fn fetch_admin_token(service_account: &ServiceAccount) -> Result<AdminToken, String> {
+    match service_account {
+        ServiceAccount::Key(key) => exchange_service_account_key(&Client::new(), key).await,
+        ServiceAccount::EmulatorOwner => Ok(AdminToken {
+            access_token: "owner".into(),
+            expires_at: None,
+        }),
+    }
+}
+
+// Logs in, and refreshes, with the service account instead of a user
+pub(crate) fn service_account_login(
+    service_account: Res<ServiceAccount>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let service_account = service_account.clone();
+
+    runtime.spawn_background_task(|mut ctx| async move {
+        let admin_token = fetch_admin_token(&service_account).await;
+
+        ctx.run_on_main_thread(move |ctx| match admin_token {
+            Ok(admin_token) => {
+                info!(target: LOG_LOGIN, "Logged in with service account");
+                ctx.world.insert_resource(admin_token);
+                ctx.world
+                    .insert_resource(NextState(Some(AuthState::LoggedIn)));
+            }
+            Err(message) => {
+                warn!(target: LOG_LOGIN, "Service account login failed: {}", message);
+                ctx.world.send_event(AuthErrorEvent(message));
+
+                // Through LogOut, so anything left of the last token is cleared
+                ctx.world
+                    .insert_resource(NextState(Some(AuthState::LogOut)));
+            }
+        })
+        .await;
+    });
+}
+
+// Exchanges the service account key again shortly before the token expires,
+// or when a `RefreshIdToken` asks for it
+//
+// The new token replaces the old one in place, so there's no trip through
+// `AuthState::Refreshing` and nothing watching `LoggedIn` sees a log in.
+pub(crate) fn refresh_admin_token(
+    mut er: EventReader<RefreshIdToken>,
+    service_account: Res<ServiceAccount>,
+    admin_token: Option<Res<AdminToken>>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut next_attempt: Local<u64>,
+) {
+    let requested = !er.is_empty();
+    er.clear();
+
+    let Some(expires_at) = admin_token.and_then(|admin_token| admin_token.expires_at) else {
+        return;
+    };
+
+    let now = unix_now();
+    let expiring = now + ADMIN_REFRESH_MARGIN >= expires_at;
+    if !(expiring || requested) || now < *next_attempt {
+        return;
+    }
+
+    // Also stops a refresh starting every frame while one is underway
+    *next_attempt = now + ADMIN_RETRY_DELAY;
+
+    let service_account = service_account.clone();
+
+    runtime.spawn_background_task(move |mut ctx| async move {
+        let admin_token = fetch_admin_token(&service_account).await;
+
+        ctx.run_on_main_thread(move |ctx| {
+            // Logged out while the exchange was underway
+            if *ctx.world.resource::<State<AuthState>>().get() != AuthState::LoggedIn {
+                return;
+            }
+
+            match admin_token {
+                Ok(admin_token) => ctx.world.insert_resource(admin_token),
+                Err(message) => {
+                    warn!(target: LOG_LOGIN, "Couldn't refresh admin token: {}", message);
+                    ctx.world.send_event(AuthErrorEvent(message));
+
+                    // Retried until the old token runs out
+                    if unix_now() >= expires_at {
+                        ctx.world
+                            .insert_resource(NextState(Some(AuthState::LogOut)));
+                    }
+                }
+            }
+        })
+        .await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256};
+    use serde_json::Value;
+
+    use super::*;
+
+    // Generated for these tests only, it isn't a key for any project
+    const TEST_PRIVATE_KEY: &str = include_str!("../data/test-service-account-key.pem");
+
+    fn key_json(token_uri: Option<&str>) -> String {
+        let mut json = json!({
+            "type": "service_account",
+            "project_id": "demo-project",
+            "private_key_id": "key-1",
+            "private_key": TEST_PRIVATE_KEY,
+            "client_email": "admin@demo-project.iam.gserviceaccount.com",
+            "client_id": "1234",
+        });
+        if let Some(token_uri) = token_uri {
+            json["token_uri"] = token_uri.into();
+        }
+        json.to_string()
+    }
+
+    fn decode(part: &str) -> Value {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn from_json_reads_a_downloaded_key() {
+        let key =
+            ServiceAccountKey::from_json(&key_json(Some("http://127.0.0.1:9099/token"))).unwrap();
+
+        assert_eq!(key.project_id, "demo-project");
+        assert_eq!(key.private_key_id, "key-1");
+        assert_eq!(
+            key.client_email,
+            "admin@demo-project.iam.gserviceaccount.com"
+        );
+        assert_eq!(key.token_uri, "http://127.0.0.1:9099/token");
+        assert_eq!(key.private_key, TEST_PRIVATE_KEY);
+
+        let debug = format!("{:?}", key);
+        assert!(debug.contains("[redacted]"));
+        assert!(!debug.contains("PRIVATE KEY"));
+    }
+
+    #[test]
+    fn from_json_defaults_the_token_uri() {
+        let key = ServiceAccountKey::from_json(&key_json(None)).unwrap();
+
+        assert_eq!(key.token_uri, "https://oauth2.googleapis.com/token");
+    }
+
+    #[test]
+    fn from_json_rejects_incomplete_keys() {
+        assert!(ServiceAccountKey::from_json("not json").is_err());
+        assert!(ServiceAccountKey::from_json(r#"{"project_id": "demo-project"}"#).is_err());
+    }
+
+    #[test]
+    fn assertion_is_a_signed_jwt_bearer_grant() {
+        let key = ServiceAccountKey::from_json(&key_json(None)).unwrap();
+
+        let assertion = key.assertion(1_700_000_000).unwrap();
+        let parts: Vec<&str> = assertion.split('.').collect();
+        assert_eq!(parts.len(), 3);
+
+        assert_eq!(
+            decode(parts[0]),
+            json!({ "alg": "RS256", "typ": "JWT", "kid": "key-1" })
+        );
+        assert_eq!(
+            decode(parts[1]),
+            json!({
+                "iss": "admin@demo-project.iam.gserviceaccount.com",
+                "scope": ADMIN_SCOPES,
+                "aud": "https://oauth2.googleapis.com/token",
+                "iat": 1_700_000_000,
+                "exp": 1_700_003_600,
+            })
+        );
+
+        let der = rustls_pemfile::pkcs8_private_keys(&mut TEST_PRIVATE_KEY.as_bytes())
+            .unwrap()
+            .remove(0);
+        let key_pair = RsaKeyPair::from_pkcs8(&der).unwrap();
+        let public_key = UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public());
+
+        let message = format!("{}.{}", parts[0], parts[1]);
+        let signature = URL_SAFE_NO_PAD.decode(parts[2]).unwrap();
+        assert!(public_key.verify(message.as_bytes(), &signature).is_ok());
+        assert!(public_key.verify(b"tampered", &signature).is_err());
+    }
+
+    #[test]
+    fn assertion_needs_a_pkcs8_key() {
+        let mut json: Value = serde_json::from_str(&key_json(None)).unwrap();
+        json["private_key"] = "not a key".into();
+        let key = ServiceAccountKey::from_json(&json.to_string()).unwrap();
+
+        assert!(key.assertion(1_700_000_000).is_err());
+    }
+}
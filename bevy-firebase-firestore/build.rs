@@ -1,4 +1,31 @@
+use std::{fs, path::Path};
+
+const QUERY_PROTO: &str = "proto/googleapis/google/firestore/v1/query.proto";
+
 fn main() {
+    println!("cargo:rerun-if-changed=proto/googleapis/google/firestore/v1");
+
+    // Without the googleapis submodule, e.g. when built from crates.io, the
+    // generated code checked into src/googleapis is used as is
+    let Ok(query_proto) = fs::read_to_string(QUERY_PROTO) else {
+        if !Path::new("src/googleapis/mod.rs").exists() {
+            panic!("{QUERY_PROTO} is missing, run `git submodule update --init`");
+        }
+        return;
+    };
+
+    // Sum and avg aggregations need a googleapis revision from after they
+    // were added to query.proto. Regenerating from an older one would drop
+    // them and break src/aggregation.rs, so the checked-in code is kept
+    if !query_proto.contains("message Sum") || !query_proto.contains("message Avg") {
+        println!(
+            "cargo:warning=proto/googleapis predates sum and avg aggregations, \
+             using the generated code in src/googleapis. \
+             Run `git submodule update --remote proto/googleapis` to regenerate"
+        );
+        return;
+    }
+
     tonic_build::configure()
         .include_file("mod.rs")
         .build_server(false)
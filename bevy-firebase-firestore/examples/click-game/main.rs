@@ -10,15 +10,13 @@ mod util;
 use std::collections::HashMap;
 
 use bevy::{app::AppExit, prelude::*};
-use bevy_firebase_auth::{
-    delete_account, log_in, log_out, AuthState, AuthUrlsEvent, LoginProvider, ProjectId,
-    SelectedProvider, TokenData,
-};
+use bevy_firebase_auth::{log_in, log_out, AuthState, ProjectId, TokenData};
+use bevy_firebase_auth_ui::{AuthUiPlugin, AuthUiTheme, ButtonColors};
 use bevy_firebase_firestore::{
-    async_delete_document, async_read_document, async_update_document, value::ValueType,
-    BevyFirestoreClient, Document, DocumentMask, FirestoreState, QueryDirection,
-    QueryResponseEvent, RunQueryEvent, RunQueryResponse, Status, UpdateDocumentEvent,
-    UpdateDocumentRequest, Value,
+    async_read_document, delete_account_and_data, run_transaction, value::ValueType,
+    BevyFirestoreClient, CollectionRef, Document, DocumentMask, DocumentRef, FirestoreState,
+    QueryDirection, QueryResponseEvent, RunQueryEvent, RunQueryResponse, Status,
+    UpdateDocumentEvent, UpdateDocumentRequest, Value,
 };
 use bevy_tokio_tasks::TokioTasksRuntime;
 use textbox_plugin::TextBoxPlugin;
@@ -51,9 +49,6 @@ enum AppScreenState {
     Leaderboard,
 }
 
-#[derive(Component)]
-struct LogInScreenData;
-
 #[derive(Component)]
 struct MainMenuData;
 
@@ -68,7 +63,11 @@ fn main() {
         // PLUGINS
         .add_plugins(DefaultPlugins)
         .add_plugins(bevy_firebase_auth::AuthPlugin::default())
-        .add_plugins(bevy_firebase_firestore::FirestorePlugin::default())
+        .add_plugins(AuthUiPlugin)
+        .add_plugins(bevy_firebase_firestore::FirestorePlugin {
+            user_data_paths: vec!["click/{uid}".into()],
+            ..Default::default()
+        })
         .add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default())
         .add_plugins(TextBoxPlugin)
         // STATES
@@ -82,25 +81,16 @@ fn main() {
         // LOGIN
         .add_systems(OnEnter(AuthControllerState::LogIn), log_in)
         .add_systems(OnEnter(AuthControllerState::LogOut), log_out)
-        .add_systems(OnEnter(AuthControllerState::Delete), delete_account)
+        .add_systems(
+            OnEnter(AuthControllerState::Delete),
+            delete_account_and_data,
+        )
         .add_systems(OnEnter(AuthState::LoggedIn), logged_in)
         .add_systems(OnEnter(FirestoreState::Ready), firestore_ready)
         .add_systems(OnEnter(AuthState::LoggedOut), logged_out)
         // SCREENS
-        // login
-        .add_systems(OnEnter(AppScreenState::LogInScreen), build_login_screen)
-        .add_systems(
-            OnExit(AppScreenState::LogInScreen),
-            despawn_with::<LogInScreenData>,
-        )
-        .add_systems(
-            Update,
-            login_button_system.run_if(in_state(AppScreenState::LogInScreen)),
-        )
-        .add_systems(
-            Update,
-            auth_url_listener.run_if(in_state(AuthControllerState::LogIn)),
-        )
+        // login, the screen itself comes from AuthUiPlugin
+        .add_systems(OnEnter(AppScreenState::LogInScreen), start_login)
         // menu
         .add_systems(OnEnter(AppScreenState::MainMenu), build_main_menu)
         .add_systems(
@@ -191,11 +181,6 @@ struct TypeFaces {
 #[derive(Component)]
 struct UiBase;
 
-// LOGIN
-
-#[derive(Component)]
-struct LoginButton((String, String));
-
 #[derive(Component)]
 struct ExitButton;
 
@@ -247,6 +232,10 @@ struct Leaderboard;
 #[derive(Resource)]
 struct Score(i64);
 
+// The score last read from or written to firestore
+#[derive(Resource)]
+struct SyncedScore(i64);
+
 #[derive(Resource)]
 struct Nickname(String);
 
@@ -310,10 +299,26 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ));
         });
 
+    // Match the login screen to the rest of the game
+    commands.insert_resource(AuthUiTheme {
+        font: typefaces.p.font.clone(),
+        title: "CLiCK".into(),
+        title_size: 60.,
+        text_color: TEXT_COLOR,
+        button_colors: ButtonColors {
+            normal: NORMAL_BUTTON,
+            hovered: HOVERED_BUTTON,
+            pressed: PRESSED_BUTTON,
+        },
+        button_style: button.style.clone(),
+        ..Default::default()
+    });
+
     commands.insert_resource(UiSettings { typefaces, button });
 
     // This is populated on firestore load
     commands.insert_resource(Score(0));
+    commands.insert_resource(SyncedScore(0));
 }
 
 // UTILS
@@ -351,114 +356,12 @@ fn exit_button_system(
 
 // LOGIN
 
-fn build_login_screen(
-    mut commands: Commands,
-    mut next_state: ResMut<NextState<AuthControllerState>>,
-    mut q_ui_base: Query<Entity, With<UiBase>>,
-    ui: Res<UiSettings>,
-) {
-    println!("build_login_screen");
-    let ui_base = q_ui_base.single_mut();
-
-    commands.entity(ui_base).with_children(|parent| {
-        // TITLE
-        parent.spawn((
-            TextBundle::from_section("login", ui.typefaces.h2.clone()).with_style(Style {
-                ..Default::default()
-            }),
-            LogInScreenData,
-        ));
-
-        parent
-            .spawn(ui.button.clone())
-            .insert(ExitButton)
-            .insert(LogInScreenData)
-            .with_children(|parent| {
-                parent.spawn(TextBundle::from_section("quit", ui.typefaces.p.clone()));
-            });
-    });
-
-    // attempt auto login
+fn start_login(mut next_state: ResMut<NextState<AuthControllerState>>) {
+    println!("start_login");
+    // attempt auto login, AuthUiPlugin shows the login screen if that fails
     next_state.set(AuthControllerState::LogIn);
 }
 
-fn login_button_system(
-    mut q_interaction: Query<
-        (&Interaction, &LoginButton, &Children),
-        (Changed<Interaction>, With<LoginButton>),
-    >,
-    mut text_query: Query<&mut Text>,
-    mut commands: Commands,
-) {
-    for (interaction, login_url, children) in &mut q_interaction {
-        let mut text = text_query.get_mut(children[0]).unwrap();
-
-        if *interaction == Interaction::Pressed {
-            // Set selected provider
-            match login_url.0 .0.as_str() {
-                "Google" => {
-                    commands.insert_resource(SelectedProvider(LoginProvider::Google));
-                }
-                "Github" => {
-                    commands.insert_resource(SelectedProvider(LoginProvider::Github));
-                }
-                _ => (),
-            }
-
-            // open URL
-            let _ = open::that(login_url.0 .1.clone());
-            text.sections[0].value = "waiting for browser...".into();
-            // TODO display this text separately, allow users to close tab and try again
-        }
-    }
-}
-
-fn auth_url_listener(
-    mut commands: Commands,
-    mut er: EventReader<AuthUrlsEvent>,
-    mut q_ui_base: Query<Entity, With<UiBase>>,
-    ui: Res<UiSettings>,
-) {
-    for e in er.iter() {
-        for (provider, auth_url) in e.0.iter() {
-            let mut provider_name = "";
-            let mut display_url = "";
-            match provider {
-                LoginProvider::Google => {
-                    provider_name = "Google";
-                    display_url = auth_url.as_str();
-                }
-                LoginProvider::Github => {
-                    provider_name = "Github";
-                    display_url = auth_url.as_str();
-                }
-                _ => (),
-            }
-
-            println!(
-                "Go to this URL to sign in with {}:\n{}\n",
-                provider_name, display_url
-            );
-
-            // add login button
-            let ui_base = q_ui_base.single_mut();
-
-            commands.entity(ui_base).with_children(|parent| {
-                parent
-                    .spawn(ui.button.clone())
-                    .insert(LoginButton((provider_name.into(), display_url.into())))
-                    .insert(LogInScreenData)
-                    .with_children(|parent| {
-                        parent.spawn(TextBundle::from_section(
-                            format!("log in with {}", provider_name),
-                            ui.typefaces.p.clone(),
-                        ));
-                    });
-            });
-        }
-    }
-}
-
 fn logged_in(mut _next_state: ResMut<NextState<AppScreenState>>) {
     println!("logged_in");
     // set app state to main menu
@@ -550,6 +453,7 @@ fn firestore_ready(
                 .insert_resource(NextState(Some(AppScreenState::MainMenu)));
             ctx.world.insert_resource(Nickname(name));
             ctx.world.insert_resource(Score(score_res));
+            ctx.world.insert_resource(SyncedScore(score_res));
         })
         .await;
     });
@@ -746,13 +650,10 @@ fn nickname_submit_button_system(
                 },
             );
 
-            let document_path = format!("click/{uid}");
-
-            ew.send(UpdateDocumentEvent {
-                document_path,
-                document_data,
-                id: 0,
-            });
+            match DocumentRef::new(format!("click/{uid}")) {
+                Ok(document) => ew.send(UpdateDocumentEvent::new(document, document_data, 0)),
+                Err(e) => println!("NICKNAME NOT SAVED: {e}"),
+            }
 
             // TODO nickname update listener
         }
@@ -773,6 +674,7 @@ fn leaderboard_button_system(
 fn delete_score_button_system(
     mut q_interaction: Query<(&Interaction,), (Changed<Interaction>, With<DeleteScoreButton>)>,
     mut score: ResMut<Score>,
+    mut synced_score: ResMut<SyncedScore>,
     token_data: Option<Res<TokenData>>,
     mut ew: EventWriter<UpdateDocumentEvent>,
 ) {
@@ -781,6 +683,7 @@ fn delete_score_button_system(
         for (interaction,) in &mut q_interaction {
             if *interaction == Interaction::Pressed {
                 score.0 = 0;
+                synced_score.0 = 0;
 
                 let uid = token_data.local_id.clone();
                 let mut document_data = HashMap::new();
@@ -791,13 +694,10 @@ fn delete_score_button_system(
                     },
                 );
 
-                let document_path = format!("click/{uid}");
-
-                ew.send(UpdateDocumentEvent {
-                    document_path,
-                    document_data,
-                    id: 0,
-                });
+                match DocumentRef::new(format!("click/{uid}")) {
+                    Ok(document) => ew.send(UpdateDocumentEvent::new(document, document_data, 0)),
+                    Err(e) => println!("SCORE NOT DELETED: {e}"),
+                }
             }
         }
     }
@@ -817,31 +717,12 @@ fn logout_button_system(
 
 fn delete_account_button_system(
     mut q_interaction: Query<(&Interaction,), (Changed<Interaction>, With<DeleteAccountButton>)>,
-    runtime: ResMut<TokioTasksRuntime>,
-    client: ResMut<BevyFirestoreClient>,
-    project_id: Res<ProjectId>,
-    token_data: Option<Res<TokenData>>,
+    mut next_state: ResMut<NextState<AuthControllerState>>,
 ) {
-    // TODO right shift fix
-    if let Some(token_data) = token_data {
-        for (interaction,) in &mut q_interaction {
-            if *interaction == Interaction::Pressed {
-                let mut client = client.0.clone();
-                let project_id = project_id.0.clone();
-                let uid = token_data.local_id.clone();
-
-                runtime.spawn_background_task(|mut ctx| async move {
-                    let _ =
-                        async_delete_document(&mut client, &project_id, &format!("click/{}", uid))
-                            .await;
-
-                    ctx.run_on_main_thread(|ctx| {
-                        ctx.world
-                            .insert_resource(NextState(Some(AuthControllerState::Delete)));
-                    })
-                    .await;
-                });
-            }
+    for (interaction,) in &mut q_interaction {
+        if *interaction == Interaction::Pressed {
+            // Deletes the click/{uid} score, then the account
+            next_state.set(AuthControllerState::Delete)
         }
     }
 }
@@ -931,6 +812,7 @@ fn update_score(score: Res<Score>, mut q_score_text: Query<&mut Text, With<Score
 fn submit_score_button_system(
     mut q_interaction: Query<(&Interaction,), (Changed<Interaction>, With<SubmitScoreButton>)>,
     score: Res<Score>,
+    synced_score: Res<SyncedScore>,
     token_data: Res<TokenData>,
     runtime: ResMut<TokioTasksRuntime>,
     client: ResMut<BevyFirestoreClient>,
@@ -939,25 +821,60 @@ fn submit_score_button_system(
     for (interaction,) in &mut q_interaction {
         if *interaction == Interaction::Pressed {
             let uid = token_data.local_id.clone();
-            let mut document_data = HashMap::new();
-            document_data.insert(
-                "score".to_string(),
-                Value {
-                    value_type: Some(ValueType::IntegerValue(score.0)),
-                },
-            );
-
             let document_path = format!("click/{uid}");
+
+            // Only the clicks since the last sync, so clicks submitted from
+            // elsewhere in the meantime aren't overwritten
+            let clicks = score.0 - synced_score.0;
+
             let mut client = client.0.clone();
             let project_id = project_id.0.clone();
 
-            runtime.spawn_background_task(|mut ctx| async move {
-                //
-                let _ =
-                    async_update_document(&mut client, &project_id, &document_path, document_data)
-                        .await;
+            runtime.spawn_background_task(move |mut ctx| async move {
+                let result = run_transaction(&mut client, &project_id, |tx| {
+                    let document_path = document_path.clone();
+                    async move {
+                        let stored = match tx.get(&document_path).await? {
+                            Some(doc) => match doc.fields.get("score") {
+                                Some(Value {
+                                    value_type: Some(ValueType::IntegerValue(s)),
+                                }) => *s,
+                                _ => 0,
+                            },
+                            None => 0,
+                        };
+
+                        let new_score = stored + clicks;
+
+                        let mut document_data = HashMap::new();
+                        document_data.insert(
+                            "score".to_string(),
+                            Value {
+                                value_type: Some(ValueType::IntegerValue(new_score)),
+                            },
+                        );
+                        tx.update(&document_path, document_data);
+
+                        Ok(new_score)
+                    }
+                })
+                .await;
+
+                ctx.run_on_main_thread(move |ctx| {
+                    match result {
+                        Ok((new_score, _)) => {
+                            // Add what was committed instead of overwriting the
+                            // score, so clicks made while submitting aren't lost.
+                            // This player's clicks are already counted in it
+                            let mut synced_score = ctx.world.resource_mut::<SyncedScore>();
+                            let committed = new_score - synced_score.0;
+                            synced_score.0 = new_score;
+
+                            ctx.world.resource_mut::<Score>().0 += committed - clicks;
+                        }
+                        Err(status) => println!("SCORE NOT SUBMITTED: {:?}", status),
+                    }
 
-                ctx.run_on_main_thread(|ctx| {
                     ctx.world
                         .insert_resource(NextState(Some(AppScreenState::Leaderboard)));
                 })
@@ -988,13 +905,12 @@ fn build_leaderboard(
     let ui_base = q_ui_base.single_mut();
 
     // Run query
-    ew.send(RunQueryEvent {
-        parent: "".into(),
-        collection_id: "click".into(),
-        limit: Some(10),
-        order_by: ("score".into(), QueryDirection::Descending),
-        id: 420,
-    });
+    ew.send(RunQueryEvent::new(
+        CollectionRef::new("click").unwrap(),
+        Some(10),
+        ("score".into(), QueryDirection::Descending),
+        420,
+    ));
 
     commands.entity(ui_base).with_children(|parent| {
         // TITLE
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use futures_lite::StreamExt;
+
+use crate::{
+    credential::with_refresh,
+    query::{self, field_reference},
+    run_aggregation_query_request,
+    structured_aggregation_query::{
+        self,
+        aggregation::{Avg, Count, Operator, Sum},
+        Aggregation,
+    },
+    value::ValueType,
+    BevyFirestoreClient, Client, FirestoreCredential, FirestoreTasks, RunAggregationQueryRequest,
+    Status, StructuredAggregationQuery, Value,
+};
+
+/// Count, sum and average aggregations over the results of a `query::Query`
+///
+/// Each aggregation has an alias its result is read back with.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy_firebase_firestore::*;
+/// let scores = AggregationQuery::new(query::Query::collection("click"))
+///     .count("players")
+///     .sum("total_score", "score")
+///     .avg("average_score", "score");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregationQuery {
+    query: query::Query,
+    aggregations: Vec<Aggregation>,
+}
+
+impl AggregationQuery {
+    pub fn new(query: query::Query) -> Self {
+        AggregationQuery {
+            query,
+            aggregations: Vec::new(),
+        }
+    }
+
+    /// Counts the documents matching the query
+    pub fn count(self, alias: impl Into<String>) -> Self {
+        self.aggregate(alias, Operator::Count(Count { up_to: None }))
+    }
+
+    /// Counts the documents matching the query, stopping at `up_to`
+    pub fn count_up_to(self, alias: impl Into<String>, up_to: i64) -> Self {
+        self.aggregate(alias, Operator::Count(Count { up_to: Some(up_to) }))
+    }
+
+    /// Sums a numeric field. Other values are skipped
+    pub fn sum(self, alias: impl Into<String>, field_path: impl Into<String>) -> Self {
+        self.aggregate(
+            alias,
+            Operator::Sum(Sum {
+                field: Some(field_reference(field_path)),
+            }),
+        )
+    }
+
+    /// Averages a numeric field. Other values are skipped
+    pub fn avg(self, alias: impl Into<String>, field_path: impl Into<String>) -> Self {
+        self.aggregate(
+            alias,
+            Operator::Avg(Avg {
+                field: Some(field_reference(field_path)),
+            }),
+        )
+    }
+
+    fn aggregate(mut self, alias: impl Into<String>, operator: Operator) -> Self {
+        self.aggregations.push(Aggregation {
+            alias: alias.into(),
+            operator: Some(operator),
+        });
+        self
+    }
+
+    /// The `RunAggregationQueryRequest` for this aggregation in `project_id`
+    pub fn request(&self, project_id: &str) -> RunAggregationQueryRequest {
+        RunAggregationQueryRequest {
+            parent: self.query.parent_resource(project_id),
+            query_type: Some(
+                run_aggregation_query_request::QueryType::StructuredAggregationQuery(
+                    StructuredAggregationQuery {
+                        aggregations: self.aggregations.clone(),
+                        query_type: Some(structured_aggregation_query::QueryType::StructuredQuery(
+                            self.query.structured_query(),
+                        )),
+                    },
+                ),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+/// Aggregation results, by alias
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AggregationResults {
+    pub fields: HashMap<String, Value>,
+}
+
+impl AggregationResults {
+    pub fn get(&self, alias: &str) -> Option<&Value> {
+        self.fields.get(alias)
+    }
+
+    pub fn count(&self, alias: &str) -> Option<i64> {
+        match self.get(alias)?.value_type {
+            Some(ValueType::IntegerValue(count)) => Some(count),
+            _ => None,
+        }
+    }
+
+    /// A sum, as a double whether or not it was summed as integers
+    pub fn sum(&self, alias: &str) -> Option<f64> {
+        match self.get(alias)?.value_type {
+            Some(ValueType::IntegerValue(sum)) => Some(sum as f64),
+            Some(ValueType::DoubleValue(sum)) => Some(sum),
+            _ => None,
+        }
+    }
+
+    /// A sum of integers. `None` if it overflowed or included doubles
+    pub fn sum_integer(&self, alias: &str) -> Option<i64> {
+        match self.get(alias)?.value_type {
+            Some(ValueType::IntegerValue(sum)) => Some(sum),
+            _ => None,
+        }
+    }
+
+    /// An average. `None` if no values were averaged
+    pub fn avg(&self, alias: &str) -> Option<f64> {
+        match self.get(alias)?.value_type {
+            Some(ValueType::DoubleValue(avg)) => Some(avg),
+            _ => None,
+        }
+    }
+}
+
+pub type AggregationResponse = Result<AggregationResults, Status>;
+
+/// Runs an aggregation query
+pub async fn async_run_aggregation(
+    client: &mut Client,
+    project_id: &str,
+    aggregation: &AggregationQuery,
+) -> AggregationResponse {
+    let mut res = client
+        .run_aggregation_query(aggregation.request(project_id))
+        .await?
+        .into_inner();
+
+    let mut results = AggregationResults::default();
+
+    while let Some(response) = res.next().await {
+        if let Some(result) = response?.result {
+            results.fields = result.aggregate_fields;
+        }
+    }
+
+    Ok(results)
+}
+
+/// Implement this to create custom aggregation response events
+///
+/// # Examples
+///
+/// Implementing:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// trait MyAggregationResponseEventBuilder {
+///     fn new(aggregation_response: AggregationResponse, id: usize) -> Self;
+///     fn aggregation_response(&self) -> AggregationResponse;
+/// }
+/// impl MyAggregationResponseEventBuilder for AggregationResponseEvent {
+///     fn new(aggregation_response: AggregationResponse, id: usize) -> Self {
+///         AggregationResponseEvent { aggregation_response, id }
+///     }
+///     fn aggregation_response(&self) -> AggregationResponse {
+///         self.aggregation_response.clone()
+///     }
+/// }
+/// ```
+// `Status` is large, but it's what every response carries
+#[allow(clippy::result_large_err)]
+pub trait AggregationResponseEventBuilder {
+    fn new(aggregation_response: AggregationResponse, id: usize) -> Self;
+    fn aggregation_response(&self) -> AggregationResponse;
+}
+
+/// Event that contains an `AggregationResponse`
+///
+/// # Examples
+/// Responding to an `AggregationResponseEvent`:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn aggregation_response_event_handler(
+///     mut er: EventReader<AggregationResponseEvent>,
+/// ) {
+///     for e in er.iter() {
+///         if let Ok(results) = &e.aggregation_response {
+///             println!("PLAYERS: {:?}", results.count("players"));
+///         }
+///     }
+/// }
+/// ```
+#[derive(Event)]
+pub struct AggregationResponseEvent {
+    pub aggregation_response: AggregationResponse,
+    pub id: usize,
+}
+
+impl AggregationResponseEventBuilder for AggregationResponseEvent {
+    fn new(aggregation_response: AggregationResponse, id: usize) -> Self {
+        AggregationResponseEvent {
+            aggregation_response,
+            id,
+        }
+    }
+    fn aggregation_response(&self) -> AggregationResponse {
+        self.aggregation_response.clone()
+    }
+}
+
+/// Implement this to create custom aggregation events
+///
+/// # Examples
+///
+/// Implementing:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// trait MyRunAggregationEventBuilder {
+///     fn aggregation(&self) -> AggregationQuery;
+///     fn id(&self) -> usize;
+/// }
+/// impl MyRunAggregationEventBuilder for RunAggregationEvent {
+///     fn aggregation(&self) -> AggregationQuery {
+///         self.aggregation.clone()
+///     }
+///     fn id(&self) -> usize {
+///         self.id
+///     }
+/// }
+/// ```
+pub trait RunAggregationEventBuilder {
+    fn aggregation(&self) -> AggregationQuery;
+    fn id(&self) -> usize;
+}
+
+/// An event holding an `AggregationQuery` to run
+///
+/// Results arrive as an `AggregationResponseEvent` with the same `id`.
+///
+/// # Examples
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # use bevy_firebase_firestore::value::ValueType;
+/// use bevy_firebase_firestore::query::Filter;
+///
+/// // How many players scored more than 420
+/// let e = RunAggregationEvent {
+///     aggregation: AggregationQuery::new(
+///         query::Query::collection("click").filter(Filter::greater_than(
+///             "score",
+///             Value {
+///                 value_type: Some(ValueType::IntegerValue(420)),
+///             },
+///         )),
+///     )
+///     .count("ahead"),
+///     id: 1337,
+/// };
+/// ```
+#[derive(Clone, Event)]
+pub struct RunAggregationEvent {
+    pub aggregation: AggregationQuery,
+    pub id: usize,
+}
+
+impl RunAggregationEventBuilder for RunAggregationEvent {
+    fn aggregation(&self) -> AggregationQuery {
+        self.aggregation.clone()
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+}
+
+/// Listens for events and runs aggregation queries
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # let mut app = App::new();
+///
+/// app
+///     .add_event::<RunAggregationEvent>()
+///     .add_event::<AggregationResponseEvent>()
+///     .add_systems(Update, run_aggregation_event_handler::<RunAggregationEvent, AggregationResponseEvent>
+///         .run_if(in_state(FirestoreState::Ready)
+///     ),);
+/// ```
+pub fn run_aggregation_event_handler<T, R>(
+    mut er: EventReader<T>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) where
+    T: RunAggregationEventBuilder + Event,
+    R: AggregationResponseEventBuilder + Event,
+{
+    for e in er.iter() {
+        let aggregation = e.aggregation();
+        let id = e.id();
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                async_run_aggregation(&mut client, &project_id, &aggregation)
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.send_event(R::new(response, id));
+            })
+            .await;
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structured_query::FieldReference;
+
+    fn aggregations(request: &RunAggregationQueryRequest) -> &StructuredAggregationQuery {
+        match &request.query_type {
+            Some(run_aggregation_query_request::QueryType::StructuredAggregationQuery(query)) => {
+                query
+            }
+            None => panic!("no aggregation query"),
+        }
+    }
+
+    fn value(value_type: ValueType) -> Value {
+        Value {
+            value_type: Some(value_type),
+        }
+    }
+
+    #[test]
+    fn request_holds_the_query_and_aggregations() {
+        let query = query::Query::collection("saves").parent("users/alice");
+        let request = AggregationQuery::new(query.clone())
+            .count("saves")
+            .count_up_to("some_saves", 10)
+            .sum("total_score", "score")
+            .avg("average_score", "stats.best")
+            .request("demo-project");
+
+        assert_eq!(
+            request.parent,
+            "projects/demo-project/databases/(default)/documents/users/alice"
+        );
+
+        let aggregation_query = aggregations(&request);
+        assert_eq!(
+            aggregation_query.query_type,
+            Some(structured_aggregation_query::QueryType::StructuredQuery(
+                query.structured_query()
+            ))
+        );
+
+        let field = |field_path: &str| {
+            Some(FieldReference {
+                field_path: field_path.into(),
+            })
+        };
+        let operators: Vec<_> = aggregation_query
+            .aggregations
+            .iter()
+            .map(|aggregation| aggregation.operator.clone())
+            .collect();
+        assert_eq!(
+            operators,
+            [
+                Some(Operator::Count(Count { up_to: None })),
+                Some(Operator::Count(Count { up_to: Some(10) })),
+                Some(Operator::Sum(Sum {
+                    field: field("score")
+                })),
+                Some(Operator::Avg(Avg {
+                    field: field("stats.best")
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn aliases_are_kept_in_order() {
+        let request = AggregationQuery::new(query::Query::collection("click"))
+            .avg("b", "score")
+            .count("a")
+            .sum(String::from("c"), "score")
+            .request("demo-project");
+
+        let aliases: Vec<_> = aggregations(&request)
+            .aggregations
+            .iter()
+            .map(|aggregation| aggregation.alias.as_str())
+            .collect();
+        assert_eq!(aliases, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn results_are_read_by_alias() {
+        let results = AggregationResults {
+            fields: HashMap::from([
+                ("players".to_string(), value(ValueType::IntegerValue(3))),
+                ("total".to_string(), value(ValueType::IntegerValue(42))),
+                ("total_time".to_string(), value(ValueType::DoubleValue(1.5))),
+                ("average".to_string(), value(ValueType::DoubleValue(14.0))),
+                ("no_average".to_string(), value(ValueType::NullValue(0))),
+            ]),
+        };
+
+        assert_eq!(results.count("players"), Some(3));
+        assert_eq!(results.count("average"), None);
+
+        assert_eq!(results.sum("total"), Some(42.0));
+        assert_eq!(results.sum("total_time"), Some(1.5));
+        assert_eq!(results.sum_integer("total"), Some(42));
+        assert_eq!(results.sum_integer("total_time"), None);
+
+        assert_eq!(results.avg("average"), Some(14.0));
+        assert_eq!(results.avg("no_average"), None);
+
+        assert!(results.get("missing").is_none());
+        assert_eq!(results.count("missing"), None);
+        assert_eq!(results.sum("missing"), None);
+        assert_eq!(results.avg("missing"), None);
+    }
+}
@@ -0,0 +1,39 @@
+use ring::rand::{SecureRandom, SystemRandom};
+
+const AUTO_ID_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const AUTO_ID_LENGTH: usize = 20;
+
+/// A random 20 character document ID, as generated by the official SDKs
+///
+/// With 62 possible characters per position, IDs are unique without
+/// checking the server.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy_firebase_firestore::*;
+/// let document_id = auto_id();
+/// assert_eq!(document_id.len(), 20);
+/// ```
+pub fn auto_id() -> String {
+    let rng = SystemRandom::new();
+
+    // Bytes at or above the largest multiple of 62 are skipped, so every
+    // character is equally likely
+    let max_multiple = 256 - 256 % AUTO_ID_CHARS.len();
+
+    let mut id = String::with_capacity(AUTO_ID_LENGTH);
+    let mut bytes = [0u8; 40];
+
+    while id.len() < AUTO_ID_LENGTH {
+        rng.fill(&mut bytes).expect("system random source failed");
+
+        for byte in bytes {
+            if id.len() < AUTO_ID_LENGTH && (byte as usize) < max_multiple {
+                id.push(AUTO_ID_CHARS[byte as usize % AUTO_ID_CHARS.len()] as char);
+            }
+        }
+    }
+
+    id
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use tonic::Code;
+
+use crate::{
+    credential::with_refresh,
+    fields::{masked_fields, merge_mask, top_level_mask},
+    write::Operation,
+    BatchWriteRequest, BevyFirestoreClient, Client, CommitRequest, CommitResponse, Document,
+    FieldPath, FieldTransform, FieldValue, FirestoreCredential, FirestoreTasks, InvalidFieldPath,
+    Status, Value, Write, WritePrecondition, WriteResult,
+};
+
+/// Writes to send together, either atomically with `async_commit_batch` or
+/// independently with `async_batch_write`
+///
+/// Documents are given by path, e.g. `"click/player"`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy_firebase_firestore::*;
+/// let mut batch = WriteBatch::new();
+/// batch
+///     .set("saves/player", HashMap::new())
+///     .delete("saves/player/slots/old");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    writes: Vec<Write>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Replaces a document, creating it if needed
+    pub fn set(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+    ) -> &mut Self {
+        self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+    }
+
+    /// Merges `fields` into a document, creating it if needed
+    ///
+    /// Nested maps are merged too, so only the leaf fields given are replaced.
+    pub fn set_merge(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+    ) -> &mut Self {
+        let update_mask = merge_mask(&fields);
+
+        self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            update_mask: Some(update_mask),
+            ..Default::default()
+        })
+    }
+
+    /// Updates the given fields of a document, leaving the rest
+    pub fn update(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+    ) -> &mut Self {
+        let update_mask = top_level_mask(&fields);
+
+        self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            update_mask: Some(update_mask),
+            ..Default::default()
+        })
+    }
+
+    /// Updates the given fields of a document, if it meets `precondition`
+    ///
+    /// Otherwise the write fails with FAILED_PRECONDITION, and an atomic
+    /// commit fails as a whole.
+    pub fn update_if(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+        precondition: WritePrecondition,
+    ) -> &mut Self {
+        let update_mask = top_level_mask(&fields);
+
+        self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            update_mask: Some(update_mask),
+            current_document: Some(precondition.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Writes or deletes each field path given, leaving the rest of the
+    /// document
+    ///
+    /// Errors, adding nothing, if a path is given twice or inside another,
+    /// e.g. `stats` and `stats.best`.
+    pub fn update_fields(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: Vec<(FieldPath, FieldValue)>,
+    ) -> Result<&mut Self, InvalidFieldPath> {
+        let (fields, update_mask) = masked_fields(fields)?;
+
+        Ok(self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            update_mask: Some(update_mask),
+            ..Default::default()
+        }))
+    }
+
+    /// Deletes a document
+    pub fn delete(&mut self, document_path: impl AsRef<str>) -> &mut Self {
+        self.write(Write {
+            operation: Some(Operation::Delete(document_path.as_ref().into())),
+            ..Default::default()
+        })
+    }
+
+    /// Deletes a document, if it meets `precondition`
+    pub fn delete_if(
+        &mut self,
+        document_path: impl AsRef<str>,
+        precondition: WritePrecondition,
+    ) -> &mut Self {
+        self.write(Write {
+            operation: Some(Operation::Delete(document_path.as_ref().into())),
+            current_document: Some(precondition.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Updates the given fields of a document, then applies `transforms`
+    pub fn update_with_transforms(
+        &mut self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+        transforms: Vec<FieldTransform>,
+    ) -> &mut Self {
+        let update_mask = top_level_mask(&fields);
+
+        self.write(Write {
+            operation: Some(Operation::Update(Document {
+                name: document_path.as_ref().into(),
+                fields,
+                ..Default::default()
+            })),
+            update_mask: Some(update_mask),
+            update_transforms: transforms,
+            ..Default::default()
+        })
+    }
+
+    /// Applies server-side transforms to a document's fields, creating it if
+    /// needed
+    pub fn transform(
+        &mut self,
+        document_path: impl AsRef<str>,
+        transforms: Vec<FieldTransform>,
+    ) -> &mut Self {
+        self.update_with_transforms(document_path, HashMap::new(), transforms)
+    }
+
+    /// Adds any write. Document names in it are paths, like the rest of the
+    /// batch
+    pub fn write(&mut self, write: Write) -> &mut Self {
+        self.writes.push(write);
+        self
+    }
+
+    // The writes with full document names, ready to send
+    pub(crate) fn writes(&self, project_id: &str) -> Vec<Write> {
+        let documents = format!("projects/{project_id}/databases/(default)/documents");
+
+        self.writes
+            .iter()
+            .cloned()
+            .map(|mut write| {
+                match &mut write.operation {
+                    Some(Operation::Update(document)) => {
+                        document.name = format!("{documents}/{}", document.name)
+                    }
+                    Some(Operation::Delete(name)) => *name = format!("{documents}/{name}"),
+                    Some(Operation::Transform(transform)) => {
+                        transform.document = format!("{documents}/{}", transform.document)
+                    }
+                    None => (),
+                }
+                write
+            })
+            .collect()
+    }
+}
+
+pub type CommitBatchResponse = Result<CommitResponse, Status>;
+
+/// Each write's result, in the order they were added to the batch
+pub type BatchWriteResults = Result<Vec<Result<WriteResult, Status>>, Status>;
+
+/// Commits every write in `batch` atomically. Either all of them apply or
+/// none do
+pub async fn async_commit_batch(
+    client: &mut Client,
+    project_id: &str,
+    batch: &WriteBatch,
+) -> CommitBatchResponse {
+    client
+        .commit(CommitRequest {
+            database: format!("projects/{project_id}/databases/(default)"),
+            writes: batch.writes(project_id),
+            ..Default::default()
+        })
+        .await
+        .map(|response| response.into_inner())
+}
+
+/// Applies the writes in `batch` independently, in no particular order
+///
+/// Higher throughput than `async_commit_batch`, but some writes may fail
+/// while others apply. A document can only be written once per batch.
+pub async fn async_batch_write(
+    client: &mut Client,
+    project_id: &str,
+    batch: &WriteBatch,
+) -> BatchWriteResults {
+    let response = client
+        .batch_write(BatchWriteRequest {
+            database: format!("projects/{project_id}/databases/(default)"),
+            writes: batch.writes(project_id),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+
+    let mut results = Vec::new();
+
+    for (write_result, status) in response.write_results.into_iter().zip(response.status) {
+        results.push(match Code::from_i32(status.code) {
+            Code::Ok => Ok(write_result),
+            code => Err(Status::new(code, status.message)),
+        });
+    }
+
+    Ok(results)
+}
+
+/// An event holding a `WriteBatch` to commit atomically
+///
+/// The result arrives as a `CommitBatchResponseEvent` with the same `id`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn move_save(mut ew: EventWriter<CommitBatchEvent>, save: Res<SaveData>) {
+///     let mut batch = WriteBatch::new();
+///     batch.set("saves/slot-2", save.0.clone()).delete("saves/slot-1");
+///
+///     ew.send(CommitBatchEvent { batch, id: 1 });
+/// }
+/// # #[derive(Resource)]
+/// # struct SaveData(HashMap<String, Value>);
+/// ```
+#[derive(Clone, Event)]
+pub struct CommitBatchEvent {
+    pub batch: WriteBatch,
+    pub id: usize,
+}
+
+/// Event that contains a `CommitBatchResponse`
+#[derive(Event)]
+pub struct CommitBatchResponseEvent {
+    pub commit_response: CommitBatchResponse,
+    pub id: usize,
+}
+
+/// An event holding a `WriteBatch` to apply without atomicity
+///
+/// The result arrives as a `BatchWriteResponseEvent` with the same `id`.
+#[derive(Clone, Event)]
+pub struct BatchWriteEvent {
+    pub batch: WriteBatch,
+    pub id: usize,
+}
+
+/// Event that contains `BatchWriteResults`
+#[derive(Event)]
+pub struct BatchWriteResponseEvent {
+    pub batch_write_response: BatchWriteResults,
+    pub id: usize,
+}
+
+pub(crate) fn commit_batch_event_handler(
+    mut er: EventReader<CommitBatchEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let batch = e.batch.clone();
+        let id = e.id;
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let commit_response = with_refresh!(
+                credential,
+                async_commit_batch(&mut client, &project_id, &batch)
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.send_event(CommitBatchResponseEvent {
+                    commit_response,
+                    id,
+                });
+            })
+            .await;
+        }));
+    }
+}
+
+pub(crate) fn batch_write_event_handler(
+    mut er: EventReader<BatchWriteEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let batch = e.batch.clone();
+        let id = e.id;
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let batch_write_response = with_refresh!(
+                credential,
+                async_batch_write(&mut client, &project_id, &batch)
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.send_event(BatchWriteResponseEvent {
+                    batch_write_response,
+                    id,
+                });
+            })
+            .await;
+        }));
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_firebase_auth::{AdminToken, AuthState, RefreshIdToken, TokenData};
+use tokio::sync::watch;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    Code, Status,
+};
+
+// How long a request waits for a refreshed token before giving up
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long before an ID token expires it's refreshed, at most half its lifetime
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+struct CredentialState {
+    bearer_token: RwLock<Option<MetadataValue<Ascii>>>,
+    // Bumped every time the token changes
+    version: watch::Sender<u64>,
+    refresh_requested: AtomicBool,
+    // When to refresh ahead of the token expiring. `None` for tokens the auth
+    // plugin keeps fresh itself, like `AdminToken`
+    refresh_at: RwLock<Option<Instant>>,
+}
+
+/// Bevy `Resource` holding the bearer token shared by every Firestore client
+///
+/// It's kept up to date with `TokenData`, or `AdminToken` for service
+/// accounts, so clients and listeners keep working after a token refresh.
+#[derive(Resource, Clone)]
+pub struct FirestoreCredential(Arc<CredentialState>);
+
+impl Default for FirestoreCredential {
+    fn default() -> Self {
+        FirestoreCredential(Arc::new(CredentialState {
+            bearer_token: RwLock::new(None),
+            version: watch::channel(0).0,
+            refresh_requested: AtomicBool::new(false),
+            refresh_at: RwLock::new(None),
+        }))
+    }
+}
+
+impl FirestoreCredential {
+    /// Replaces the token sent with every request
+    pub fn set(&self, token: &str) {
+        let Ok(bearer_token) = format!("Bearer {}", token).parse() else {
+            warn!(target: "bevy_firebase_firestore::credential", "Token isn't valid ASCII");
+            return;
+        };
+
+        *self.0.bearer_token.write().unwrap() = Some(bearer_token);
+        self.0.version.send_modify(|version| *version += 1);
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.0.bearer_token.write().unwrap() = None;
+        *self.0.refresh_at.write().unwrap() = None;
+
+        // Wakes requests waiting in `refresh`, so they fail now rather than
+        // time out
+        self.0.version.send_modify(|version| *version += 1);
+    }
+
+    // Schedules a refresh shortly before a token that lasts `expires_in` expires
+    fn refresh_before_expiry(&self, expires_in: Option<Duration>) {
+        *self.0.refresh_at.write().unwrap() = expires_in
+            .map(|expires_in| Instant::now() + expires_in - REFRESH_MARGIN.min(expires_in / 2));
+    }
+
+    pub(crate) fn bearer_token(&self) -> Option<MetadataValue<Ascii>> {
+        self.0.bearer_token.read().unwrap().clone()
+    }
+
+    /// Asks for a new token if `result` was rejected as UNAUTHENTICATED
+    ///
+    /// Returns `true` once the token has been refreshed, meaning the request
+    /// is worth retrying. Retry only once, as a second rejection won't be
+    /// fixed by another refresh.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use bevy_firebase_firestore::*;
+    /// async fn read_save(client: &mut Client, credential: &FirestoreCredential) -> DocumentResult {
+    ///     let project_id = "my-project".to_string();
+    ///     let path = "saves/player".to_string();
+    ///
+    ///     let mut response = async_read_document(client, &project_id, &path).await;
+    ///     if credential.refreshed_after(&response).await {
+    ///         response = async_read_document(client, &project_id, &path).await;
+    ///     }
+    ///     response.map(|response| response.into_inner())
+    /// }
+    /// ```
+    pub fn refreshed_after<T>(
+        &self,
+        result: &Result<T, Status>,
+    ) -> impl Future<Output = bool> + '_ {
+        // Checked up front, so the future doesn't borrow `result`
+        let unauthenticated =
+            matches!(result, Err(status) if status.code() == Code::Unauthenticated);
+
+        async move { unauthenticated && self.refresh().await }
+    }
+
+    // Requests a token refresh and waits for it
+    pub(crate) async fn refresh(&self) -> bool {
+        let mut version = self.0.version.subscribe();
+        version.borrow_and_update();
+
+        self.0.refresh_requested.store(true, Ordering::Release);
+
+        matches!(
+            tokio::time::timeout(REFRESH_TIMEOUT, version.changed()).await,
+            Ok(Ok(()))
+        )
+    }
+}
+
+// Runs a request, and runs it again if it was rejected as UNAUTHENTICATED
+// and the credential was refreshed. The request expression is evaluated a
+// second time for the retry, so it has to clone anything it moves
+macro_rules! with_refresh {
+    ($credential:expr, $request:expr) => {{
+        let result = $request.await;
+        if $credential.refreshed_after(&result).await {
+            $request.await
+        } else {
+            result
+        }
+    }};
+}
+pub(crate) use with_refresh;
+
+// Keeps the shared token in step with the auth plugin's tokens
+pub(crate) fn update_credential(
+    credential: Res<FirestoreCredential>,
+    token_data: Option<Res<TokenData>>,
+    admin_token: Option<Res<AdminToken>>,
+) {
+    if let Some(admin_token) = admin_token {
+        if admin_token.is_changed() {
+            credential.set(&admin_token.access_token);
+            credential.refresh_before_expiry(None);
+        }
+        return;
+    }
+
+    if let Some(token_data) = token_data {
+        if token_data.is_changed() && !token_data.id_token.is_empty() {
+            credential.set(&token_data.id_token);
+
+            let expires_in = token_data.expires_in.parse().ok().map(Duration::from_secs);
+            credential.refresh_before_expiry(expires_in);
+        }
+    }
+}
+
+// Refreshes the ID token shortly before it expires, so requests and
+// listeners don't have to be rejected first. The token is replaced in place,
+// without leaving `AuthState::LoggedIn`
+pub(crate) fn refresh_expiring_credential(
+    credential: Res<FirestoreCredential>,
+    auth_state: Res<State<AuthState>>,
+    mut ew: EventWriter<RefreshIdToken>,
+) {
+    if *auth_state.get() != AuthState::LoggedIn {
+        return;
+    }
+
+    let mut refresh_at = credential.0.refresh_at.write().unwrap();
+
+    if refresh_at.is_some_and(|refresh_at| Instant::now() >= refresh_at) {
+        *refresh_at = None;
+        ew.send(RefreshIdToken);
+    }
+}
+
+// Refreshes the ID token when a request was rejected as UNAUTHENTICATED
+pub(crate) fn refresh_requested_credential(
+    credential: Res<FirestoreCredential>,
+    auth_state: Res<State<AuthState>>,
+    mut ew: EventWriter<RefreshIdToken>,
+) {
+    let requested = credential.0.refresh_requested.swap(false, Ordering::AcqRel);
+
+    // A refresh already underway will update the token anyway
+    if requested && *auth_state.get() == AuthState::LoggedIn {
+        ew.send(RefreshIdToken);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world(auth_state: AuthState) -> World {
+        let mut world = World::new();
+        world.insert_resource(State::new(auth_state));
+        world.init_resource::<NextState<AuthState>>();
+        world.init_resource::<Events<RefreshIdToken>>();
+        world.init_resource::<FirestoreCredential>();
+        world
+    }
+
+    fn run_system<M>(world: &mut World, system: impl IntoSystemConfigs<M>) {
+        let mut schedule = Schedule::default();
+        schedule.add_systems(system);
+        schedule.run(world);
+    }
+
+    fn refreshes_sent(world: &World) -> usize {
+        world.resource::<Events<RefreshIdToken>>().len()
+    }
+
+    #[test]
+    fn rejected_requests_refresh_in_place() {
+        let mut world = world(AuthState::LoggedIn);
+        let credential = world.resource::<FirestoreCredential>().clone();
+        credential
+            .0
+            .refresh_requested
+            .store(true, Ordering::Release);
+
+        run_system(&mut world, refresh_requested_credential);
+
+        assert_eq!(refreshes_sent(&world), 1);
+        assert_eq!(world.resource::<NextState<AuthState>>().0, None);
+        assert!(!credential.0.refresh_requested.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn expiring_tokens_refresh_in_place_once() {
+        let mut world = world(AuthState::LoggedIn);
+        let credential = world.resource::<FirestoreCredential>().clone();
+        credential.set("id-token");
+        credential.refresh_before_expiry(Some(Duration::ZERO));
+
+        run_system(&mut world, refresh_expiring_credential);
+        run_system(&mut world, refresh_expiring_credential);
+
+        assert_eq!(refreshes_sent(&world), 1);
+        assert_eq!(world.resource::<NextState<AuthState>>().0, None);
+    }
+
+    #[test]
+    fn nothing_is_refreshed_unless_logged_in() {
+        let mut world = world(AuthState::Refreshing);
+        let credential = world.resource::<FirestoreCredential>().clone();
+        credential
+            .0
+            .refresh_requested
+            .store(true, Ordering::Release);
+        credential.refresh_before_expiry(Some(Duration::ZERO));
+
+        run_system(
+            &mut world,
+            (refresh_requested_credential, refresh_expiring_credential),
+        );
+
+        assert_eq!(refreshes_sent(&world), 0);
+    }
+}
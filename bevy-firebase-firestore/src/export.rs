@@ -0,0 +1,302 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::prelude::*;
+use bevy_firebase_auth::{ProjectId, TokenData};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use serde_json::{json, Map};
+use tonic::Status;
+
+use crate::{
+    async_collect_documents, credential::with_refresh, value::ValueType, BevyFirestoreClient,
+    Client, Document, FirestoreCredential, FirestoreTasks, UserDataPaths, Value,
+};
+
+/// Event to export everything stored about the logged in user to a JSON file
+///
+/// Covers the documents under each of the `UserDataPaths`, subcollections
+/// included, plus the user's profile from `TokenData`. Runs in the
+/// background, sending `UserDataExportProgress` and then
+/// `UserDataExported` or `UserDataExportFailed`.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn export_button(mut ew: EventWriter<ExportUserDataEvent>) {
+///     ew.send(ExportUserDataEvent {
+///         file: "my-data.json".into(),
+///     });
+/// }
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct ExportUserDataEvent {
+    pub file: PathBuf,
+}
+
+/// Event sent after each user data path is exported
+#[derive(Event, Clone, Debug)]
+pub struct UserDataExportProgress {
+    pub paths_done: usize,
+    pub paths_total: usize,
+    /// Documents found so far
+    pub documents: usize,
+}
+
+/// Event sent once the export archive is written
+#[derive(Event, Clone, Debug)]
+pub struct UserDataExported {
+    pub file: PathBuf,
+    pub documents: usize,
+}
+
+/// Event sent when an export couldn't be finished. No file is written
+#[derive(Event, Clone, Debug)]
+pub struct UserDataExportFailed {
+    pub status: Status,
+}
+
+/// Converts a Firestore `Value` to plain JSON
+///
+/// Timestamps become RFC 3339 strings and bytes become base64 strings.
+pub fn value_to_json(value: &Value) -> serde_json::Value {
+    match &value.value_type {
+        None | Some(ValueType::NullValue(_)) => serde_json::Value::Null,
+        Some(ValueType::BooleanValue(value)) => json!(value),
+        Some(ValueType::IntegerValue(value)) => json!(value),
+        Some(ValueType::DoubleValue(value)) => json!(value),
+        Some(ValueType::TimestampValue(value)) => {
+            json!(rfc3339(value.seconds, value.nanos))
+        }
+        Some(ValueType::StringValue(value)) => json!(value),
+        Some(ValueType::BytesValue(value)) => json!(STANDARD.encode(value)),
+        Some(ValueType::ReferenceValue(value)) => json!(value),
+        Some(ValueType::GeoPointValue(value)) => json!({
+            "latitude": value.latitude,
+            "longitude": value.longitude,
+        }),
+        Some(ValueType::ArrayValue(value)) => {
+            serde_json::Value::Array(value.values.iter().map(value_to_json).collect())
+        }
+        Some(ValueType::MapValue(value)) => serde_json::Value::Object(
+            value
+                .fields
+                .iter()
+                .map(|(key, value)| (key.clone(), value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn rfc3339(seconds: i64, nanos: i32) -> String {
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's algorithms
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        nanos
+    )
+}
+
+fn document_to_json(document: &Document) -> serde_json::Value {
+    let fields: Map<String, serde_json::Value> = document
+        .fields
+        .iter()
+        .map(|(key, value)| (key.clone(), value_to_json(value)))
+        .collect();
+
+    json!({
+        "createTime": document.create_time.as_ref().map(|time| rfc3339(time.seconds, time.nanos)),
+        "updateTime": document.update_time.as_ref().map(|time| rfc3339(time.seconds, time.nanos)),
+        "fields": fields,
+    })
+}
+
+fn profile_to_json(token_data: &TokenData) -> serde_json::Value {
+    // Tokens are credentials, not data about the user, so they're left out
+    json!({
+        "uid": token_data.local_id,
+        "email": token_data.email,
+        "emailVerified": token_data.email_verified,
+        "displayName": token_data.display_name,
+        "photoUrl": token_data.photo_url,
+        "providerId": token_data.provider_id,
+        "tenantId": token_data.tenant_id,
+    })
+}
+
+// Adds documents to an archive, keyed by their path
+fn insert_documents(
+    archive: &mut Map<String, serde_json::Value>,
+    found: Vec<Document>,
+    root: &str,
+) {
+    for document in found {
+        // Missing documents only hold subcollections, which are exported themselves
+        if document.create_time.is_none() {
+            continue;
+        }
+
+        let document_path = document.name.trim_start_matches(root).to_string();
+        archive.insert(document_path, document_to_json(&document));
+    }
+}
+
+/// Builds an export archive of the documents under `paths`, keyed by path
+///
+/// `paths` are relative to the database root, like those from
+/// `UserDataPaths::for_user`.
+pub async fn async_export_documents(
+    client: &mut Client,
+    project_id: &str,
+    paths: &[String],
+) -> Result<serde_json::Value, Status> {
+    let root = format!("projects/{project_id}/databases/(default)/documents/");
+    let mut archive = Map::new();
+
+    for path in paths.iter() {
+        let found = async_collect_documents(client, project_id, path).await?;
+        insert_documents(&mut archive, found, &root);
+    }
+
+    Ok(serde_json::Value::Object(archive))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn export_user_data_event_handler(
+    mut er: EventReader<ExportUserDataEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+    token_data: Res<TokenData>,
+    user_data_paths: Res<UserDataPaths>,
+    mut ew: EventWriter<UserDataExportFailed>,
+) {
+    for e in er.iter() {
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+        let paths = match user_data_paths.for_user(&token_data.local_id) {
+            Ok(paths) => paths,
+            Err(err) => {
+                ew.send(UserDataExportFailed {
+                    status: Status::invalid_argument(err.to_string()),
+                });
+                continue;
+            }
+        };
+        let profile = profile_to_json(&token_data);
+        let file = e.file.clone();
+
+        tasks.track(runtime.spawn_background_task(|mut ctx| async move {
+            let root = format!("projects/{project_id}/databases/(default)/documents/");
+            let paths_total = paths.len();
+            let mut archive = Map::new();
+
+            for (paths_done, path) in paths.iter().enumerate() {
+                let found = with_refresh!(
+                    credential,
+                    async_collect_documents(&mut client, &project_id, path)
+                );
+
+                match found {
+                    Ok(found) => insert_documents(&mut archive, found, &root),
+                    Err(status) => {
+                        ctx.run_on_main_thread(move |ctx| {
+                            ctx.world.send_event(UserDataExportFailed { status });
+                        })
+                        .await;
+                        return;
+                    }
+                }
+
+                let documents = archive.len();
+                ctx.run_on_main_thread(move |ctx| {
+                    ctx.world.send_event(UserDataExportProgress {
+                        paths_done: paths_done + 1,
+                        paths_total,
+                        documents,
+                    });
+                })
+                .await;
+            }
+
+            let documents = archive.len();
+            let exported_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            let archive = json!({
+                "exportedAt": rfc3339(exported_at.as_secs() as i64, exported_at.subsec_nanos() as i32),
+                "user": profile,
+                "documents": archive,
+            });
+
+            let result = serde_json::to_string_pretty(&archive)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| std::fs::write(&file, contents).map_err(|err| err.to_string()));
+
+            ctx.run_on_main_thread(move |ctx| match result {
+                Ok(()) => ctx.world.send_event(UserDataExported { file, documents }),
+                Err(message) => ctx.world.send_event(UserDataExportFailed {
+                    status: Status::internal(message),
+                }),
+            })
+            .await;
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_formats_the_epoch() {
+        assert_eq!(rfc3339(0, 0), "1970-01-01T00:00:00.000000000Z");
+    }
+
+    #[test]
+    fn rfc3339_formats_leap_days() {
+        // 2024-02-29T12:34:56.5Z
+        assert_eq!(
+            rfc3339(1_709_210_096, 500_000_000),
+            "2024-02-29T12:34:56.500000000Z"
+        );
+        // 2000 is a leap year, 1900 wasn't
+        assert_eq!(rfc3339(951_782_400, 0), "2000-02-29T00:00:00.000000000Z");
+        assert_eq!(rfc3339(-2_203_891_200, 0), "1900-03-01T00:00:00.000000000Z");
+    }
+
+    #[test]
+    fn rfc3339_formats_times_before_1970() {
+        assert_eq!(rfc3339(-1, 0), "1969-12-31T23:59:59.000000000Z");
+        // Timestamps keep nanos positive, counting forward from `seconds`
+        assert_eq!(rfc3339(-86_400, 250), "1969-12-31T00:00:00.000000250Z");
+        assert_eq!(rfc3339(-14_182_940, 0), "1969-07-20T20:17:40.000000000Z");
+    }
+}
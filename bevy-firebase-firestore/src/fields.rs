@@ -0,0 +1,659 @@
+use std::{collections::HashMap, fmt, fmt::Write as _, str::FromStr};
+
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+
+use crate::{
+    credential::with_refresh, value::ValueType, BevyFirestoreClient, Client, Document,
+    DocumentMask, DocumentRef, DocumentResult, FirestoreCredential, FirestoreTasks, MapValue,
+    Status, UpdateDocumentRequest, Value,
+};
+
+/// A path to a field, possibly nested in maps, e.g. `stats.best`
+///
+/// Segments are escaped with backticks where needed when the path is sent, so
+/// they may contain dots or any other characters.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy_firebase_firestore::*;
+/// let path = FieldPath::new(["stats", "best.time"]);
+/// assert_eq!(path.to_string(), "stats.`best.time`");
+/// assert_eq!("stats.`best.time`".parse::<FieldPath>(), Ok(path));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FieldPath(Vec<String>);
+
+impl FieldPath {
+    /// A path from unescaped segments, outermost first
+    pub fn new<I, S>(segments: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        FieldPath(segments.into_iter().map(Into::into).collect())
+    }
+
+    /// Parses a dot-separated path, where segments may be quoted with
+    /// backticks and escaped with `\`
+    pub fn parse(path: &str) -> Result<Self, InvalidFieldPath> {
+        let invalid = |reason| InvalidFieldPath {
+            path: path.into(),
+            reason,
+        };
+
+        let mut segments = Vec::new();
+        let mut chars = path.chars().peekable();
+
+        loop {
+            let mut segment = String::new();
+
+            if chars.next_if_eq(&'`').is_some() {
+                loop {
+                    match chars.next() {
+                        Some('`') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => segment.push(c),
+                            None => return Err(invalid("unterminated backtick")),
+                        },
+                        Some(c) => segment.push(c),
+                        None => return Err(invalid("unterminated backtick")),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| *c != '.') {
+                    if c == '`' {
+                        return Err(invalid("backtick inside an unquoted segment"));
+                    }
+                    segment.push(c);
+                }
+            }
+
+            if segment.is_empty() {
+                return Err(invalid("empty segment"));
+            }
+            segments.push(segment);
+
+            match chars.next() {
+                None => return Ok(FieldPath(segments)),
+                Some('.') => (),
+                Some(_) => return Err(invalid("expected '.' after a quoted segment")),
+            }
+        }
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    /// This path, extended with `segment`
+    pub fn child(&self, segment: impl Into<String>) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(segment.into());
+        FieldPath(segments)
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_char('.')?;
+            }
+
+            let simple = segment
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if simple {
+                f.write_str(segment)?;
+            } else {
+                f.write_char('`')?;
+                for c in segment.chars() {
+                    if c == '`' || c == '\\' {
+                        f.write_char('\\')?;
+                    }
+                    f.write_char(c)?;
+                }
+                f.write_char('`')?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single top-level field, named as is. Dots don't nest, use
+/// `FieldPath::new` or `FieldPath::parse` for fields inside maps
+impl From<&str> for FieldPath {
+    fn from(field: &str) -> Self {
+        FieldPath::new([field])
+    }
+}
+
+impl From<String> for FieldPath {
+    fn from(field: String) -> Self {
+        FieldPath::new([field])
+    }
+}
+
+impl FromStr for FieldPath {
+    type Err = InvalidFieldPath;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        FieldPath::parse(path)
+    }
+}
+
+/// Why a string couldn't be parsed as a `FieldPath`
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidFieldPath {
+    pub path: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for InvalidFieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid field path {:?}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidFieldPath {}
+
+impl From<InvalidFieldPath> for Status {
+    fn from(error: InvalidFieldPath) -> Self {
+        Status::invalid_argument(error.to_string())
+    }
+}
+
+/// A value to write at a `FieldPath`, or `Delete` to remove the field
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Value(Value),
+    Delete,
+}
+
+impl From<Value> for FieldValue {
+    fn from(value: Value) -> Self {
+        FieldValue::Value(value)
+    }
+}
+
+// Masks only the top-level fields, as named. Keys are escaped, so a key with
+// a dot is a single field rather than a nested path
+pub(crate) fn top_level_mask(fields: &HashMap<String, Value>) -> DocumentMask {
+    DocumentMask {
+        field_paths: fields
+            .keys()
+            .map(|key| FieldPath::new([key]).to_string())
+            .collect(),
+    }
+}
+
+// Masks every leaf field, descending into maps, so that sibling fields in
+// nested maps are kept. Empty maps are leaves
+pub(crate) fn merge_mask(fields: &HashMap<String, Value>) -> DocumentMask {
+    fn leaves(fields: &HashMap<String, Value>, parent: &FieldPath, paths: &mut Vec<String>) {
+        for (key, value) in fields {
+            let path = parent.child(key);
+            match &value.value_type {
+                Some(ValueType::MapValue(map)) if !map.fields.is_empty() => {
+                    leaves(&map.fields, &path, paths)
+                }
+                _ => paths.push(path.to_string()),
+            }
+        }
+    }
+
+    let mut field_paths = Vec::new();
+    leaves(fields, &FieldPath::default(), &mut field_paths);
+    DocumentMask { field_paths }
+}
+
+// Nests each value under its path, and masks every path. Deleted fields are
+// masked but left out, which removes them. Firestore rejects masks with a
+// path inside another, and a value would be lost in the nesting, so paths
+// can't overlap
+pub(crate) fn masked_fields(
+    fields: Vec<(FieldPath, FieldValue)>,
+) -> Result<(HashMap<String, Value>, DocumentMask), InvalidFieldPath> {
+    fn insert(fields: &mut HashMap<String, Value>, segments: &[String], value: Value) {
+        let Some((first, rest)) = segments.split_first() else {
+            return;
+        };
+
+        if rest.is_empty() {
+            fields.insert(first.clone(), value);
+            return;
+        }
+
+        let entry = fields.entry(first.clone()).or_default();
+        if !matches!(entry.value_type, Some(ValueType::MapValue(_))) {
+            entry.value_type = Some(ValueType::MapValue(MapValue::default()));
+        }
+        if let Some(ValueType::MapValue(map)) = &mut entry.value_type {
+            insert(&mut map.fields, rest, value);
+        }
+    }
+
+    // Sorted, any path inside another comes right after it or a sibling
+    // inside it
+    let mut paths: Vec<_> = fields.iter().map(|(path, _)| path).collect();
+    paths.sort();
+    if let Some(pair) = paths
+        .windows(2)
+        .find(|pair| pair[1].0.starts_with(&pair[0].0))
+    {
+        return Err(InvalidFieldPath {
+            path: pair[1].to_string(),
+            reason: "overlaps another path given",
+        });
+    }
+
+    let mut document_fields = HashMap::new();
+    let mut field_paths = Vec::new();
+
+    for (path, value) in fields {
+        field_paths.push(path.to_string());
+        if let FieldValue::Value(value) = value {
+            insert(&mut document_fields, path.segments(), value);
+        }
+    }
+
+    Ok((document_fields, DocumentMask { field_paths }))
+}
+
+/// Replaces a document, creating it if needed
+pub async fn async_set_document(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    fields: HashMap<String, Value>,
+) -> DocumentResult {
+    write_document(client, project_id, document_path.as_ref(), fields, None).await
+}
+
+/// Merges `fields` into a document, creating it if needed
+///
+/// Nested maps are merged too, so only the leaf fields given are replaced.
+pub async fn async_set_document_merge(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    fields: HashMap<String, Value>,
+) -> DocumentResult {
+    let mask = merge_mask(&fields);
+    write_document(
+        client,
+        project_id,
+        document_path.as_ref(),
+        fields,
+        Some(mask),
+    )
+    .await
+}
+
+/// Writes or deletes each field path given, leaving the rest of the document
+///
+/// Fails with INVALID_ARGUMENT if a path is given twice or inside another,
+/// e.g. `stats` and `stats.best`.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy_firebase_firestore::*;
+/// # use bevy_firebase_firestore::value::ValueType;
+/// async fn reset_best(client: &mut Client) -> DocumentResult {
+///     async_update_document_fields(
+///         client,
+///         "my-project",
+///         "click/player",
+///         vec![
+///             (
+///                 FieldPath::new(["stats", "best"]),
+///                 FieldValue::Value(Value {
+///                     value_type: Some(ValueType::IntegerValue(0)),
+///                 }),
+///             ),
+///             (FieldPath::new(["stats", "best.time"]), FieldValue::Delete),
+///         ],
+///     )
+///     .await
+/// }
+/// ```
+pub async fn async_update_document_fields(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    fields: Vec<(FieldPath, FieldValue)>,
+) -> DocumentResult {
+    let (fields, mask) = masked_fields(fields)?;
+    write_document(
+        client,
+        project_id,
+        document_path.as_ref(),
+        fields,
+        Some(mask),
+    )
+    .await
+}
+
+async fn write_document(
+    client: &mut Client,
+    project_id: &str,
+    document_path: &str,
+    fields: HashMap<String, Value>,
+    update_mask: Option<DocumentMask>,
+) -> DocumentResult {
+    client
+        .update_document(UpdateDocumentRequest {
+            document: Some(Document {
+                name: format!(
+                    "projects/{project_id}/databases/(default)/documents/{document_path}"
+                ),
+                fields,
+                ..Default::default()
+            }),
+            update_mask,
+            ..Default::default()
+        })
+        .await
+        .map(|response| response.into_inner())
+}
+
+/// An event holding a document to replace, or merge into if `merge` is set
+///
+/// The result arrives as a `SetDocumentResponseEvent` with the same `id`.
+#[derive(Clone, Event)]
+pub struct SetDocumentEvent {
+    pub document_path: String,
+    pub document_data: HashMap<String, Value>,
+    pub merge: bool,
+    pub id: usize,
+}
+
+impl SetDocumentEvent {
+    pub fn new(
+        document: impl Into<DocumentRef>,
+        document_data: HashMap<String, Value>,
+        merge: bool,
+        id: usize,
+    ) -> Self {
+        SetDocumentEvent {
+            document_path: document.into().into(),
+            document_data,
+            merge,
+            id,
+        }
+    }
+}
+
+/// Event that contains the `DocumentResult` of a `SetDocumentEvent`
+#[derive(Event)]
+pub struct SetDocumentResponseEvent {
+    pub result: DocumentResult,
+    pub id: usize,
+}
+
+/// An event holding field paths to write or delete in a document
+///
+/// The result arrives as an `UpdateFieldsResponseEvent` with the same `id`.
+#[derive(Clone, Event)]
+pub struct UpdateFieldsEvent {
+    pub document_path: String,
+    pub fields: Vec<(FieldPath, FieldValue)>,
+    pub id: usize,
+}
+
+impl UpdateFieldsEvent {
+    pub fn new(
+        document: impl Into<DocumentRef>,
+        fields: Vec<(FieldPath, FieldValue)>,
+        id: usize,
+    ) -> Self {
+        UpdateFieldsEvent {
+            document_path: document.into().into(),
+            fields,
+            id,
+        }
+    }
+}
+
+/// Event that contains the `DocumentResult` of an `UpdateFieldsEvent`
+#[derive(Event)]
+pub struct UpdateFieldsResponseEvent {
+    pub result: DocumentResult,
+    pub id: usize,
+}
+
+pub(crate) fn set_document_event_handler(
+    mut er: EventReader<SetDocumentEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let e = e.clone();
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let mask = e.merge.then(|| merge_mask(&e.document_data));
+
+            let result = with_refresh!(
+                credential,
+                write_document(
+                    &mut client,
+                    &project_id,
+                    &e.document_path,
+                    e.document_data.clone(),
+                    mask.clone(),
+                )
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world
+                    .send_event(SetDocumentResponseEvent { result, id: e.id });
+            })
+            .await;
+        }));
+    }
+}
+
+pub(crate) fn update_fields_event_handler(
+    mut er: EventReader<UpdateFieldsEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let e = e.clone();
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let result = with_refresh!(
+                credential,
+                async_update_document_fields(
+                    &mut client,
+                    &project_id,
+                    &e.document_path,
+                    e.fields.clone(),
+                )
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world
+                    .send_event(UpdateFieldsResponseEvent { result, id: e.id });
+            })
+            .await;
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i64) -> Value {
+        Value {
+            value_type: Some(ValueType::IntegerValue(value)),
+        }
+    }
+
+    fn map(fields: impl IntoIterator<Item = (&'static str, Value)>) -> Value {
+        Value {
+            value_type: Some(ValueType::MapValue(MapValue {
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+            })),
+        }
+    }
+
+    fn sorted(mask: DocumentMask) -> Vec<String> {
+        let mut field_paths = mask.field_paths;
+        field_paths.sort();
+        field_paths
+    }
+
+    #[test]
+    fn field_paths_round_trip() {
+        let cases = [
+            (vec!["score"], "score"),
+            (vec!["stats", "_best2"], "stats._best2"),
+            (vec!["stats", "best.time"], "stats.`best.time`"),
+            (vec!["2fa"], "`2fa`"),
+            (vec!["with space", "ünï"], "`with space`.`ünï`"),
+            (vec!["back`tick"], r"`back\`tick`"),
+            (vec![r"back\slash"], r"`back\\slash`"),
+        ];
+
+        for (segments, escaped) in cases {
+            let path = FieldPath::new(segments);
+            assert_eq!(path.to_string(), escaped);
+            assert_eq!(escaped.parse::<FieldPath>(), Ok(path));
+        }
+    }
+
+    #[test]
+    fn invalid_field_paths_are_rejected() {
+        let cases = [
+            ("", "empty segment"),
+            ("a..b", "empty segment"),
+            ("a.", "empty segment"),
+            ("``", "empty segment"),
+            ("`a", "unterminated backtick"),
+            (r"`a\", "unterminated backtick"),
+            ("a`b`", "backtick inside an unquoted segment"),
+            ("`a`b", "expected '.' after a quoted segment"),
+        ];
+
+        for (path, reason) in cases {
+            assert_eq!(
+                FieldPath::parse(path),
+                Err(InvalidFieldPath {
+                    path: path.into(),
+                    reason
+                }),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn top_level_mask_escapes_keys() {
+        let fields = HashMap::from([
+            ("score".to_string(), int(1)),
+            ("best.time".to_string(), int(2)),
+        ]);
+
+        assert_eq!(
+            sorted(top_level_mask(&fields)),
+            vec!["`best.time`", "score"]
+        );
+    }
+
+    #[test]
+    fn merge_mask_masks_leaves() {
+        let fields = HashMap::from([
+            ("score".to_string(), int(1)),
+            (
+                "stats".to_string(),
+                map([("best", int(2)), ("run.1", map([("time", int(3))]))]),
+            ),
+            ("empty".to_string(), map([])),
+        ]);
+
+        assert_eq!(
+            sorted(merge_mask(&fields)),
+            vec!["empty", "score", "stats.`run.1`.time", "stats.best"]
+        );
+    }
+
+    #[test]
+    fn masked_fields_nests_values_and_masks_deletes() {
+        let (fields, mask) = masked_fields(vec![
+            (FieldPath::new(["stats", "best"]), int(1).into()),
+            (FieldPath::new(["stats", "runs"]), int(2).into()),
+            (FieldPath::new(["stats", "best.time"]), FieldValue::Delete),
+            (FieldPath::new(["score"]), int(3).into()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            fields,
+            HashMap::from([
+                (
+                    "stats".to_string(),
+                    map([("best", int(1)), ("runs", int(2))])
+                ),
+                ("score".to_string(), int(3)),
+            ])
+        );
+        assert_eq!(
+            sorted(mask),
+            vec!["score", "stats.`best.time`", "stats.best", "stats.runs"]
+        );
+    }
+
+    #[test]
+    fn masked_fields_rejects_overlapping_paths() {
+        let error = masked_fields(vec![
+            (FieldPath::new(["stats", "best"]), int(1).into()),
+            (FieldPath::new(["stats", "runs"]), int(2).into()),
+            (FieldPath::new(["stats"]), FieldValue::Delete),
+        ])
+        .unwrap_err();
+        assert_eq!(error.path, "stats.best");
+
+        let error = masked_fields(vec![
+            (FieldPath::new(["score"]), int(1).into()),
+            (FieldPath::new(["score"]), int(2).into()),
+        ])
+        .unwrap_err();
+        assert_eq!(error.path, "score");
+
+        // Only whole segments overlap
+        assert!(masked_fields(vec![
+            (FieldPath::new(["stats"]), int(1).into()),
+            (FieldPath::new(["stats2"]), int(2).into()),
+            (FieldPath::new(["stats.best"]), int(3).into()),
+        ])
+        .is_ok());
+    }
+}
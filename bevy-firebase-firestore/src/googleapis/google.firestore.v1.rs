@@ -808,7 +808,7 @@ pub mod structured_aggregation_query {
         #[prost(string, tag = "7")]
         pub alias: ::prost::alloc::string::String,
         /// The type of aggregation to perform, required.
-        #[prost(oneof = "aggregation::Operator", tags = "1")]
+        #[prost(oneof = "aggregation::Operator", tags = "1, 2, 3")]
         pub operator: ::core::option::Option<aggregation::Operator>,
     }
     /// Nested message and enum types in `Aggregation`.
@@ -840,6 +840,29 @@ pub mod structured_aggregation_query {
             #[prost(message, optional, tag = "1")]
             pub up_to: ::core::option::Option<i64>,
         }
+        /// Sum of the values of the requested field.
+        ///
+        /// Only numeric values are aggregated. The result is a 64-bit integer if
+        /// all aggregated numbers are integers and the sum does not overflow,
+        /// otherwise a double.
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[derive(Clone, PartialEq, ::prost::Message)]
+        pub struct Sum {
+            /// The field to aggregate on.
+            #[prost(message, optional, tag = "1")]
+            pub field: ::core::option::Option<super::super::structured_query::FieldReference>,
+        }
+        /// Average of the values of the requested field.
+        ///
+        /// Only numeric values are aggregated. The result is always a double, or
+        /// `NULL` if the aggregated value set is empty.
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[derive(Clone, PartialEq, ::prost::Message)]
+        pub struct Avg {
+            /// The field to aggregate on.
+            #[prost(message, optional, tag = "1")]
+            pub field: ::core::option::Option<super::super::structured_query::FieldReference>,
+        }
         /// The type of aggregation to perform, required.
         #[allow(clippy::derive_partial_eq_without_eq)]
         #[derive(Clone, PartialEq, ::prost::Oneof)]
@@ -847,6 +870,12 @@ pub mod structured_aggregation_query {
             /// Count aggregator.
             #[prost(message, tag = "1")]
             Count(Count),
+            /// Sum aggregator.
+            #[prost(message, tag = "2")]
+            Sum(Sum),
+            /// Average aggregator.
+            #[prost(message, tag = "3")]
+            Avg(Avg),
         }
     }
     /// The base query to aggregate over.
@@ -1,33 +1,58 @@
+mod aggregation;
+mod auto_id;
+mod batch;
+mod credential;
+use credential::with_refresh;
+mod export;
+mod fields;
+// Generated by tonic-build from the proto sources, lints don't apply
+#[allow(dead_code, clippy::all)]
 mod googleapis;
+mod pagination;
+mod preconditions;
+pub mod query;
+mod reference;
+mod transaction;
+mod transform;
+mod user_data;
 
-use std::fs::read_to_string;
-use std::{collections::HashMap, path::PathBuf};
+use std::collections::HashMap;
+use std::time::Duration;
 
 pub use crate::googleapis::google::firestore::v1::*;
+pub use aggregation::*;
+pub use auto_id::*;
+pub use batch::*;
+pub use credential::FirestoreCredential;
+pub use export::*;
+pub use fields::*;
+pub use googleapis::google::firestore::v1::document_transform::FieldTransform;
 pub use googleapis::google::firestore::v1::listen_response::ResponseType;
 pub use googleapis::google::firestore::v1::structured_query::Direction as QueryDirection;
+pub use pagination::*;
+pub use preconditions::{WriteError, WritePrecondition};
+pub use reference::*;
 pub use tonic::Status;
+pub use transaction::*;
+pub use transform::*;
+pub use user_data::*;
 
 use bevy::prelude::*;
 
-use bevy_firebase_auth::{AuthState, ProjectId, TokenData};
+use bevy_firebase_auth::{AdminToken, AuthState, ProjectId, TokenData};
 use bevy_tokio_tasks::TokioTasksRuntime;
 
 use futures_lite::{stream, StreamExt};
+use tokio::task::JoinHandle;
 
 use googleapis::google::firestore::v1::firestore_client::FirestoreClient;
-use googleapis::google::firestore::v1::listen_request::TargetChange;
-use googleapis::google::firestore::v1::run_query_request::QueryType;
-use googleapis::google::firestore::v1::structured_query::{
-    CollectionSelector, FieldReference, Order,
-};
-use googleapis::google::firestore::v1::target::{DocumentsTarget, TargetType};
+use googleapis::google::firestore::v1::target::{DocumentsTarget, ResumeType, TargetType};
 use tonic::{
     codegen::InterceptedService,
     metadata::{Ascii, MetadataValue},
     service::Interceptor,
     transport::{Certificate, Channel, ClientTlsConfig},
-    Request, Response,
+    Code, Request, Response,
 };
 
 // FIRESTORE
@@ -42,9 +67,18 @@ pub struct BevyFirestoreClient(
 );
 
 /// Adds authorization headers to RPC requests
+///
+/// The bearer token is the signed in user's ID token. With a tenant set on
+/// `AuthPlugin` it's scoped to that tenant, and security rules see it as
+/// `request.auth.token.firebase.tenant`. When logged in with a
+/// `ServiceAccount`, the `AdminToken` is sent instead and security rules are
+/// bypassed.
+///
+/// The token is read from the shared `FirestoreCredential` on every request,
+/// so it follows token refreshes.
 #[derive(Clone)]
 pub struct FirebaseInterceptor {
-    bearer_token: MetadataValue<Ascii>,
+    credential: FirestoreCredential,
     db: MetadataValue<Ascii>,
 }
 
@@ -53,9 +87,12 @@ impl Interceptor for FirebaseInterceptor {
         &mut self,
         mut request: tonic::Request<()>,
     ) -> Result<tonic::Request<()>, tonic::Status> {
-        request
-            .metadata_mut()
-            .insert("authorization", self.bearer_token.clone());
+        let bearer_token = self
+            .credential
+            .bearer_token()
+            .ok_or_else(|| Status::unauthenticated("No Firestore credential"))?;
+
+        request.metadata_mut().insert("authorization", bearer_token);
 
         request
             .metadata_mut()
@@ -65,15 +102,114 @@ impl Interceptor for FirebaseInterceptor {
 }
 
 /// Firestore connection status. Use Firestore only when this is `FirestoreState::Ready`
-#[derive(Default, States, Debug, Clone, Eq, PartialEq, Hash)]
+///
+/// # Examples
+///
+/// Reporting why Firestore gave up connecting:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn show_connection_error(state: Res<State<FirestoreState>>) {
+///     if let FirestoreState::Disconnected { error: Some(error) } = state.get() {
+///         println!("Couldn't connect to Firestore: {error}");
+///     }
+/// }
+/// ```
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum FirestoreState {
+    /// Not connected yet, waiting for a login
     #[default]
     Start,
-    Init,
-    CreateClient,
+    Connecting,
     Ready,
+    /// Connecting failed and will be tried again after a delay, see
+    /// `FirestoreReconnectSettings`
+    Reconnecting,
+    /// Logged out, or gave up connecting with `error`. Logging in again
+    /// reconnects.
+    ///
+    /// `OnEnter(FirestoreState::Disconnected { error: None })` runs on log out.
+    Disconnected {
+        error: Option<String>,
+    },
+}
+
+// Written by hand, as the derive only supports unit variants
+impl States for FirestoreState {
+    type Iter = std::array::IntoIter<FirestoreState, 5>;
+
+    fn variants() -> Self::Iter {
+        [
+            FirestoreState::Start,
+            FirestoreState::Connecting,
+            FirestoreState::Ready,
+            FirestoreState::Reconnecting,
+            FirestoreState::Disconnected { error: None },
+        ]
+        .into_iter()
+    }
+}
+
+/// Bevy `Resource` controlling how connecting to Firestore is retried
+///
+/// # Examples
+///
+/// Give up after five attempts:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # let mut app = App::new();
+/// app.insert_resource(FirestoreReconnectSettings {
+///     max_attempts: Some(5),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct FirestoreReconnectSettings {
+    /// Delay before the first retry, doubled after each failure
+    pub initial_delay: Duration,
+    /// Longest delay between retries
+    pub max_delay: Duration,
+    /// Attempts before going to `FirestoreState::Disconnected`. `None` keeps
+    /// retrying forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for FirestoreReconnectSettings {
+    fn default() -> Self {
+        FirestoreReconnectSettings {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Bevy `Resource` holding the background tasks started by Firestore systems
+///
+/// They're all aborted on log out, so nothing keeps running as the old user.
+/// Custom event handlers can `track` their own tasks too.
+#[derive(Resource, Default)]
+pub struct FirestoreTasks(Vec<JoinHandle<()>>);
+
+impl FirestoreTasks {
+    pub fn track(&mut self, task: JoinHandle<()>) {
+        self.0.retain(|task| !task.is_finished());
+        self.0.push(task);
+    }
+
+    fn abort_all(&mut self) {
+        for task in self.0.drain(..) {
+            task.abort();
+        }
+    }
 }
 
+// Failed attempts to connect since the last successful one. Reset once
+// connected, and on log out
+#[derive(Resource, Default)]
+struct ConnectAttempts(u32);
+
 /// Bevy plugin for Firestore systems. Expects access to resources added
 /// by bevy-firebase-auth: `TokenData`, `AuthState` and `ProjectId`
 ///
@@ -94,16 +230,35 @@ pub enum FirestoreState {
 /// App::new()
 ///     .add_plugins(FirestorePlugin {
 ///         emulator_url: None,
+///         ..Default::default()
+///     });
+/// ```
+///
+/// Deleting a player's data along with their account:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// App::new()
+///     .add_plugins(FirestorePlugin {
+///         user_data_paths: vec!["click/{uid}".into(), "saves/{uid}/**".into()],
+///         ..Default::default()
 ///     });
 /// ```
 pub struct FirestorePlugin {
     pub emulator_url: Option<String>,
+    /// Documents and collections owned by each user, see `UserDataPaths`
+    pub user_data_paths: Vec<String>,
+    /// PEM encoded CAs to trust as well as the usual roots, e.g. for a TLS
+    /// inspecting proxy
+    pub extra_ca_certificates: Vec<String>,
 }
 
 impl Default for FirestorePlugin {
     fn default() -> Self {
         FirestorePlugin {
             emulator_url: Some("http://127.0.0.1:8080".into()),
+            user_data_paths: vec![],
+            extra_ca_certificates: vec![],
         }
     }
 }
@@ -111,16 +266,57 @@ impl Default for FirestorePlugin {
 #[derive(Resource, Clone)]
 struct FirestoreEmulatorUrl(String);
 
+#[derive(Resource, Clone)]
+struct ExtraCaCertificates(Vec<String>);
+
 impl Plugin for FirestorePlugin {
     fn build(&self, app: &mut App) {
+        let user_data_paths = UserDataPaths(self.user_data_paths.clone());
+        if let Err(err) = user_data_paths.validate() {
+            panic!("FirestorePlugin::user_data_paths: {err}");
+        }
+
         if self.emulator_url.is_some() {
             app.insert_resource(FirestoreEmulatorUrl(self.emulator_url.clone().unwrap()));
         }
 
         app.add_state::<FirestoreState>()
+            .insert_resource(user_data_paths)
+            .insert_resource(ExtraCaCertificates(self.extra_ca_certificates.clone()))
+            .init_resource::<FirestoreCredential>()
+            .init_resource::<FirestoreReconnectSettings>()
+            .init_resource::<FirestoreTasks>()
+            .init_resource::<ConnectAttempts>()
+            .add_systems(
+                Update,
+                (
+                    credential::update_credential,
+                    credential::refresh_requested_credential,
+                    credential::refresh_expiring_credential,
+                ),
+            )
+            .add_event::<UserDataDeleteProgress>()
+            .add_event::<UserDataDeleted>()
+            .add_event::<UserDataDeleteFailed>()
+            // EXPORT
+            .add_event::<ExportUserDataEvent>()
+            .add_event::<UserDataExportProgress>()
+            .add_event::<UserDataExported>()
+            .add_event::<UserDataExportFailed>()
+            .add_systems(
+                Update,
+                export_user_data_event_handler.run_if(in_state(FirestoreState::Ready)),
+            )
             .add_systems(OnEnter(AuthState::LoggedIn), logged_in)
-            .add_systems(OnEnter(FirestoreState::Init), init)
-            .add_systems(OnEnter(FirestoreState::CreateClient), create_client)
+            // Refreshing and Offline keep the client, it picks up the new token
+            .add_systems(OnEnter(AuthState::LogOut), disconnect)
+            .add_systems(OnEnter(AuthState::LoggedOut), disconnect)
+            .add_systems(OnEnter(AuthState::LogIn), disconnect)
+            .add_systems(OnEnter(AuthState::GotAuthCode), disconnect)
+            .add_systems(OnEnter(AuthState::SwitchingAccount), disconnect)
+            .add_systems(OnEnter(FirestoreState::Connecting), create_client)
+            .add_systems(OnEnter(FirestoreState::Ready), connected)
+            .add_systems(OnEnter(FirestoreState::Reconnecting), reconnect)
             // LISTENER
             .add_event::<CreateListenerEvent>()
             .add_event::<ListenerResponseEvent>()
@@ -132,11 +328,62 @@ impl Plugin for FirestorePlugin {
             // QUERY
             .add_event::<QueryResponseEvent>()
             .add_event::<RunQueryEvent>()
+            .add_event::<StructuredQueryEvent>()
             .add_systems(
                 Update,
                 run_query_event_handler::<RunQueryEvent, QueryResponseEvent>
                     .run_if(in_state(FirestoreState::Ready)),
             )
+            .add_systems(
+                Update,
+                structured_query_event_handler::<StructuredQueryEvent, QueryResponseEvent>
+                    .run_if(in_state(FirestoreState::Ready)),
+            )
+            .add_event::<RunAggregationEvent>()
+            .add_event::<AggregationResponseEvent>()
+            .add_systems(
+                Update,
+                run_aggregation_event_handler::<RunAggregationEvent, AggregationResponseEvent>
+                    .run_if(in_state(FirestoreState::Ready)),
+            )
+            .add_event::<CommitBatchEvent>()
+            .add_event::<CommitBatchResponseEvent>()
+            .add_event::<BatchWriteEvent>()
+            .add_event::<BatchWriteResponseEvent>()
+            .add_systems(
+                Update,
+                (commit_batch_event_handler, batch_write_event_handler)
+                    .run_if(in_state(FirestoreState::Ready)),
+            )
+            .add_event::<SetDocumentEvent>()
+            .add_event::<SetDocumentResponseEvent>()
+            .add_event::<UpdateFieldsEvent>()
+            .add_event::<UpdateFieldsResponseEvent>()
+            .add_systems(
+                Update,
+                (set_document_event_handler, update_fields_event_handler)
+                    .run_if(in_state(FirestoreState::Ready)),
+            )
+            .add_event::<TransformDocumentEvent>()
+            .add_event::<TransformDocumentResponseEvent>()
+            .add_systems(
+                Update,
+                transform_document_event_handler.run_if(in_state(FirestoreState::Ready)),
+            )
+            .init_resource::<TransactionSettings>()
+            .add_event::<RunTransactionEvent>()
+            .add_event::<TransactionResponseEvent>()
+            .add_systems(
+                Update,
+                run_transaction_event_handler.run_if(in_state(FirestoreState::Ready)),
+            )
+            .add_event::<LoadPageEvent>()
+            .add_event::<PageLoaded>()
+            .add_event::<PageLoadFailed>()
+            .add_systems(
+                Update,
+                load_page_event_handler.run_if(in_state(FirestoreState::Ready)),
+            )
             // CREATE
             .add_event::<CreateDocumentEvent>()
             .add_event::<CreateDocumentResponseEvent>()
@@ -172,72 +419,208 @@ impl Plugin for FirestorePlugin {
     }
 }
 
-fn logged_in(mut next_state: ResMut<NextState<FirestoreState>>) {
-    next_state.set(FirestoreState::Init);
+fn logged_in(
+    current_state: Res<State<FirestoreState>>,
+    mut next_state: ResMut<NextState<FirestoreState>>,
+) {
+    match current_state.get() {
+        // After a token refresh the client is kept, and picks up the new token
+        FirestoreState::Connecting | FirestoreState::Ready | FirestoreState::Reconnecting => {}
+        _ => next_state.set(FirestoreState::Connecting),
+    }
 }
 
-fn init(mut next_state: ResMut<NextState<FirestoreState>>) {
-    next_state.set(FirestoreState::CreateClient);
+// Stops everything running as the logged out user, on leaving the logged in
+// states for anything but a refresh
+fn disconnect(
+    mut commands: Commands,
+    mut tasks: ResMut<FirestoreTasks>,
+    credential: Res<FirestoreCredential>,
+    mut attempts: ResMut<ConnectAttempts>,
+    current_state: Res<State<FirestoreState>>,
+    mut next_state: ResMut<NextState<FirestoreState>>,
+) {
+    // Never connected, or already torn down
+    if matches!(
+        current_state.get(),
+        FirestoreState::Start | FirestoreState::Disconnected { .. }
+    ) {
+        return;
+    }
+
+    tasks.abort_all();
+    credential.clear();
+    *attempts = ConnectAttempts::default();
+
+    commands.remove_resource::<BevyFirestoreClient>();
+
+    next_state.set(FirestoreState::Disconnected { error: None });
 }
 
-// CLIENT
+fn connected(mut attempts: ResMut<ConnectAttempts>) {
+    *attempts = ConnectAttempts::default();
+}
 
-fn create_client(
+fn reconnect(
     runtime: ResMut<TokioTasksRuntime>,
-    user_info: Res<TokenData>,
-    emulator: Option<Res<FirestoreEmulatorUrl>>,
-    project_id: Res<ProjectId>,
+    mut tasks: ResMut<FirestoreTasks>,
+    attempts: Res<ConnectAttempts>,
+    settings: Res<FirestoreReconnectSettings>,
 ) {
-    let id_token = user_info.id_token.clone();
-    let project_id = project_id.0.clone();
+    let mut delay = settings.initial_delay;
+    for _ in 1..attempts.0 {
+        delay = (delay * 2).min(settings.max_delay);
+    }
 
-    let emulator_url = match emulator {
-        Some(e) => Some(e.0.clone()),
-        None => None,
-    };
+    info!(
+        target: "bevy_firebase_firestore::connection",
+        "Reconnecting to Firestore in {:?}", delay
+    );
 
-    // CREATE BG TASK TO INSERT CLIENT AS RESOURCE
-    runtime.spawn_background_task(|mut ctx| async move {
-        let data_dir = PathBuf::from_iter([std::env!("CARGO_MANIFEST_DIR"), "data"]);
-        let certs = read_to_string(data_dir.join("gcp/gtsr1.pem")).unwrap();
+    tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+        tokio::time::sleep(delay).await;
+
+        ctx.run_on_main_thread(|ctx| {
+            if *ctx.world.resource::<State<FirestoreState>>().get() == FirestoreState::Reconnecting
+            {
+                ctx.world
+                    .insert_resource(NextState(Some(FirestoreState::Connecting)));
+            }
+        })
+        .await;
+    }));
+}
+
+// CLIENT
+
+// Google Trust Services root that firestore.googleapis.com chains to
+const GTS_ROOT_R1: &str = include_str!("../data/gcp/gtsr1.pem");
+
+// Builds the trusted CAs: the embedded Google root, unless the OS or webpki
+// roots are used instead, plus any extra CAs
+fn ca_certificates(extra_ca_certificates: &[String]) -> Option<Certificate> {
+    let mut pem = String::new();
+
+    if !cfg!(any(feature = "native-roots", feature = "webpki-roots")) {
+        pem.push_str(GTS_ROOT_R1);
+    }
+
+    for extra in extra_ca_certificates {
+        pem.push('\n');
+        pem.push_str(extra);
+    }
+
+    if pem.trim().is_empty() {
+        None
+    } else {
+        Some(Certificate::from_pem(pem))
+    }
+}
 
-        let channel = if emulator_url.is_none() {
-            let tls_config = ClientTlsConfig::new()
-                .ca_certificate(Certificate::from_pem(certs))
-                .domain_name("firestore.googleapis.com");
+async fn connect(
+    emulator_url: Option<String>,
+    project_id: &str,
+    credential: FirestoreCredential,
+    extra_ca_certificates: &[String],
+) -> Result<Client, String> {
+    let channel = match emulator_url {
+        None => {
+            let mut tls_config = ClientTlsConfig::new().domain_name("firestore.googleapis.com");
+
+            if let Some(ca_certificates) = ca_certificates(extra_ca_certificates) {
+                tls_config = tls_config.ca_certificate(ca_certificates);
+            }
 
             Channel::from_static("https://firestore.googleapis.com")
                 .tls_config(tls_config)
-                .unwrap()
+                .map_err(|err| err.to_string())?
                 .connect()
                 .await
-                .unwrap()
-        } else {
-            Channel::from_shared(emulator_url.unwrap())
-                .unwrap()
-                .connect()
-                .await
-                .unwrap()
-        };
+                .map_err(|err| err.to_string())?
+        }
+        Some(emulator_url) => Channel::from_shared(emulator_url)
+            .map_err(|err| err.to_string())?
+            .connect()
+            .await
+            .map_err(|err| err.to_string())?,
+    };
 
-        let service = FirestoreClient::with_interceptor(
-            channel,
-            FirebaseInterceptor {
-                bearer_token: format!("Bearer {}", id_token).parse().unwrap(),
-                db: format!("projects/{}/databases/(default)", project_id.clone())
-                    .parse()
-                    .unwrap(),
-            },
-        );
+    let db = format!("projects/{}/databases/(default)", project_id)
+        .parse()
+        .map_err(|_| format!("Invalid project ID {:?}", project_id))?;
+
+    Ok(FirestoreClient::with_interceptor(
+        channel,
+        FirebaseInterceptor { credential, db },
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_client(
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    user_info: Option<Res<TokenData>>,
+    admin_token: Option<Res<AdminToken>>,
+    credential: Res<FirestoreCredential>,
+    emulator: Option<Res<FirestoreEmulatorUrl>>,
+    extra_ca_certificates: Res<ExtraCaCertificates>,
+    project_id: Res<ProjectId>,
+    settings: Res<FirestoreReconnectSettings>,
+) {
+    match (admin_token, user_info) {
+        (Some(admin_token), _) => credential.set(&admin_token.access_token),
+        (None, Some(user_info)) => credential.set(&user_info.id_token),
+        (None, None) => {}
+    };
+    let credential = credential.clone();
+    let project_id = project_id.0.clone();
+    let max_attempts = settings.max_attempts;
+
+    let emulator_url = emulator.map(|emulator| emulator.0.clone());
+    let extra_ca_certificates = extra_ca_certificates.0.clone();
+
+    // CREATE BG TASK TO INSERT CLIENT AS RESOURCE
+    tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+        let client = connect(
+            emulator_url,
+            &project_id,
+            credential,
+            &extra_ca_certificates,
+        )
+        .await;
 
         ctx.run_on_main_thread(move |ctx| {
-            ctx.world.insert_resource(BevyFirestoreClient(service));
+            // Logged out while connecting
+            if *ctx.world.resource::<State<FirestoreState>>().get() != FirestoreState::Connecting {
+                return;
+            }
 
-            ctx.world
-                .insert_resource(NextState(Some(FirestoreState::Ready)));
+            let next_state = match client {
+                Ok(client) => {
+                    ctx.world.insert_resource(BevyFirestoreClient(client));
+                    FirestoreState::Ready
+                }
+                Err(error) => {
+                    warn!(
+                        target: "bevy_firebase_firestore::connection",
+                        "Couldn't connect to Firestore: {}", error
+                    );
+
+                    let mut attempts = ctx.world.resource_mut::<ConnectAttempts>();
+                    attempts.0 += 1;
+
+                    if max_attempts.is_some_and(|max_attempts| attempts.0 >= max_attempts) {
+                        FirestoreState::Disconnected { error: Some(error) }
+                    } else {
+                        FirestoreState::Reconnecting
+                    }
+                }
+            };
+
+            ctx.world.insert_resource(NextState(Some(next_state)));
         })
         .await;
-    });
+    }));
 }
 
 // LISTENER
@@ -342,44 +725,82 @@ impl ListenerResponseEventBuilder for ListenerResponseEvent {
 // Put JoinHandle and id in a resource vec?
 fn add_listener<T>(
     runtime: &ResMut<TokioTasksRuntime>,
+    tasks: &mut FirestoreTasks,
     client: &mut Client,
+    credential: &FirestoreCredential,
     project_id: String,
     target: String,
 ) where
     T: ListenerResponseEventBuilder + Event,
 {
     let mut client = client.clone();
+    let credential = credential.clone();
 
-    runtime.spawn_background_task(|mut ctx| async move {
+    tasks.track(runtime.spawn_background_task(|mut ctx| async move {
         let db = format!("projects/{project_id}/databases/(default)");
-        let req = ListenRequest {
-            database: db.clone(),
-            labels: HashMap::new(),
-            target_change: Some(TargetChange::AddTarget(Target {
-                target_id: 0x52757374, // rust in hex, for... reasons?
-                once: false,
-                resume_type: None,
-                target_type: Some(TargetType::Documents(DocumentsTarget {
-                    documents: vec![db + "/documents/" + &*target],
+        let mut resume_token: Option<Vec<u8>> = None;
+        let mut resumed = false;
+
+        loop {
+            let req = ListenRequest {
+                database: db.clone(),
+                labels: HashMap::new(),
+                target_change: Some(listen_request::TargetChange::AddTarget(Target {
+                    target_id: 0x52757374, // rust in hex, for... reasons?
+                    once: false,
+                    resume_type: resume_token.clone().map(ResumeType::ResumeToken),
+                    target_type: Some(TargetType::Documents(DocumentsTarget {
+                        documents: vec![format!("{db}/documents/{target}")],
+                    })),
+                    ..Default::default()
                 })),
-                ..Default::default()
-            })),
-        };
-
-        let req = Request::new(stream::iter(vec![req]).chain(stream::pending()));
+            };
 
-        // TODO handle errors
-        let res = client.listen(req).await.unwrap();
+            let req = Request::new(stream::iter(vec![req]).chain(stream::pending()));
+
+            let status = match client.listen(req).await {
+                Ok(res) => {
+                    let mut res = res.into_inner();
+
+                    loop {
+                        match res.next().await {
+                            Some(Ok(msg)) => {
+                                resumed = false;
+
+                                if let Some(ResponseType::TargetChange(change)) = &msg.response_type
+                                {
+                                    if !change.resume_token.is_empty() {
+                                        resume_token = Some(change.resume_token.clone());
+                                    }
+                                }
+
+                                ctx.run_on_main_thread(move |ctx| {
+                                    ctx.world.send_event(T::new(msg));
+                                })
+                                .await;
+                            }
+                            Some(Err(status)) => break status,
+                            None => return,
+                        }
+                    }
+                }
+                Err(status) => status,
+            };
 
-        let mut res = res.into_inner();
+            // Streams close when the token expires. Pick up where we left off
+            // with a new one, but only once per failure
+            if !resumed && status.code() == Code::Unauthenticated && credential.refresh().await {
+                resumed = true;
+                continue;
+            }
 
-        while let Some(msg) = res.next().await {
-            ctx.run_on_main_thread(move |ctx| {
-                ctx.world.send_event(T::new(msg.unwrap()));
-            })
-            .await;
+            warn!(
+                target: "bevy_firebase_firestore::listener",
+                "Listener on {} closed: {}", target, status
+            );
+            return;
         }
-    });
+    }));
 }
 
 /// Implement this to create custom listener create events
@@ -426,6 +847,15 @@ pub struct CreateListenerEvent {
     pub target: String,
 }
 
+impl CreateListenerEvent {
+    /// Listens to `document`
+    pub fn new(document: impl Into<DocumentRef>) -> Self {
+        CreateListenerEvent {
+            target: document.into().into(),
+        }
+    }
+}
+
 impl CreateListenerEventBuilder for CreateListenerEvent {
     fn target(&self) -> String {
         self.target.clone()
@@ -459,7 +889,9 @@ impl CreateListenerEventBuilder for CreateListenerEvent {
 pub fn create_listener_event_handler<T, R>(
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
     mut client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
 ) where
     T: CreateListenerEventBuilder + Event,
@@ -468,7 +900,9 @@ pub fn create_listener_event_handler<T, R>(
     for e in er.iter() {
         add_listener::<R>(
             &runtime,
+            &mut tasks,
             &mut client.0,
+            &credential,
             project_id.0.clone(),
             e.target().clone(),
         )
@@ -500,6 +934,8 @@ pub type QueryResponse = Result<Vec<RunQueryResponse>, Status>;
 ///     }
 /// }
 /// ```
+// `Status` is large, but it's what every response carries
+#[allow(clippy::result_large_err)]
 pub trait QueryResponseEventBuilder {
     fn new(query_response: QueryResponse, id: usize) -> Self;
     fn query_response(&self) -> QueryResponse;
@@ -599,6 +1035,25 @@ pub struct RunQueryEvent {
     pub id: usize,
 }
 
+impl RunQueryEvent {
+    /// Queries `collection`, wherever it's nested
+    pub fn new(
+        collection: impl Into<CollectionRef>,
+        limit: Option<i32>,
+        order_by: (String, QueryDirection),
+        id: usize,
+    ) -> Self {
+        let collection = collection.into();
+        RunQueryEvent {
+            parent: collection.parent_path().into(),
+            collection_id: collection.id().into(),
+            limit,
+            order_by,
+            id,
+        }
+    }
+}
+
 impl RunQueryEventBuilder for RunQueryEvent {
     fn collection_id(&self) -> String {
         self.collection_id.clone()
@@ -653,120 +1108,275 @@ impl RunQueryEventBuilder for RunQueryEvent {
 pub fn run_query_event_handler<T, R>(
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
     mut client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
 ) where
     T: RunQueryEventBuilder + Event,
     R: QueryResponseEventBuilder + Event,
 {
     for e in er.iter() {
+        let (order_field, order_direction) = e.order_by();
+
+        let mut query = query::Query::collection(e.collection_id())
+            .parent(e.parent())
+            .order_by(order_field, order_direction);
+        if let Some(limit) = e.limit() {
+            query = query.limit(limit);
+        }
+
         run_query::<R>(
             &runtime,
+            &mut tasks,
             &mut client.0,
+            &credential,
             project_id.0.clone(),
-            e.parent(),
-            e.collection_id(),
-            e.limit(),
-            e.order_by(),
+            query,
             e.id(),
         )
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn run_query<T>(
-    runtime: &ResMut<TokioTasksRuntime>,
-    client: &mut Client,
-    project_id: String,
-    parent: String,
-    collection_id: String,
-    limit: Option<i32>,
-    order_by: (String, QueryDirection),
-    id: usize,
+/// Implement this to create custom structured query events
+///
+/// # Examples
+///
+/// Implementing:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// trait MyStructuredQueryEventBuilder {
+///     fn query(&self) -> query::Query;
+///     fn id(&self) -> usize;
+/// }
+/// impl MyStructuredQueryEventBuilder for StructuredQueryEvent {
+///     fn query(&self) -> query::Query {
+///         self.query.clone()
+///     }
+///     fn id(&self) -> usize {
+///         self.id
+///     }
+/// }
+/// ```
+pub trait StructuredQueryEventBuilder {
+    fn query(&self) -> query::Query;
+    fn id(&self) -> usize;
+}
+
+/// An event holding a `query::Query` to run
+///
+/// Results arrive as a `QueryResponseEvent` with the same `id`.
+///
+/// # Examples
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// use bevy_firebase_firestore::query::{Filter, Query as FirestoreQuery};
+///
+/// let e = StructuredQueryEvent {
+///     query: FirestoreQuery::collection_group("scores")
+///         .filter(Filter::is_not_null("nickname"))
+///         .order_by("score", QueryDirection::Descending)
+///         .offset(10)
+///         .limit(10),
+///     id: 1337,
+/// };
+/// ```
+#[derive(Clone, Event)]
+pub struct StructuredQueryEvent {
+    pub query: query::Query,
+    pub id: usize,
+}
+
+impl StructuredQueryEventBuilder for StructuredQueryEvent {
+    fn query(&self) -> query::Query {
+        self.query.clone()
+    }
+    fn id(&self) -> usize {
+        self.id
+    }
+}
+
+/// Listens for events and runs structured queries
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # let mut app = App::new();
+///
+/// app
+///     .add_event::<StructuredQueryEvent>()
+///     .add_event::<QueryResponseEvent>()
+///     .add_systems(Update, structured_query_event_handler::<StructuredQueryEvent, QueryResponseEvent>
+///         .run_if(in_state(FirestoreState::Ready)
+///     ),);
+/// ```
+pub fn structured_query_event_handler<T, R>(
+    mut er: EventReader<T>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    mut client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
 ) where
-    T: QueryResponseEventBuilder + Event,
+    T: StructuredQueryEventBuilder + Event,
+    R: QueryResponseEventBuilder + Event,
 {
-    let parent = if !parent.is_empty() {
-        format!("/{parent}")
-    } else {
-        "".into()
-    };
-
-    let mut client = client.clone();
+    for e in er.iter() {
+        run_query::<R>(
+            &runtime,
+            &mut tasks,
+            &mut client.0,
+            &credential,
+            project_id.0.clone(),
+            e.query(),
+            e.id(),
+        )
+    }
+}
 
-    let order_field = order_by.0.clone();
-    let order_direction = order_by.1;
-
-    runtime.spawn_background_task(move |mut ctx| async move {
-        let req = RunQueryRequest {
-            parent: format!("projects/{project_id}/databases/(default)/documents{parent}"),
-            query_type: Some(QueryType::StructuredQuery(StructuredQuery {
-                from: vec![CollectionSelector {
-                    collection_id,
-                    all_descendants: false,
-                }],
-                limit,
-                order_by: vec![Order {
-                    field: Some(FieldReference {
-                        field_path: order_field,
-                    }),
-                    direction: order_direction as i32,
-                }],
-                ..Default::default()
-            })),
-            ..Default::default()
-        };
+/// Runs a structured query, collecting every result
+pub async fn async_run_query(
+    client: &mut Client,
+    project_id: &str,
+    query: &query::Query,
+) -> QueryResponse {
+    collect_query(client, query.request(project_id)).await
+}
 
-        let mut res = client.run_query(req).await.unwrap().into_inner();
+// Runs a query request, collecting every result
+pub(crate) async fn collect_query(client: &mut Client, request: RunQueryRequest) -> QueryResponse {
+    let mut res = client.run_query(request).await?.into_inner();
 
-        let mut responses = Vec::new();
+    let mut responses = Vec::new();
 
-        let mut response_result = Ok(Vec::new());
+    while let Some(result) = res.next().await {
+        let query_response = result?;
+        let done = query_response.continuation_selector.is_some();
 
-        while let Some(result) = res.next().await {
-            match result {
-                Ok(query_response) => {
-                    responses.push(query_response.clone());
+        responses.push(query_response);
 
-                    if let Some(_continuation_selector) = query_response.continuation_selector {
-                        // Break when at end of results
-                        break;
-                    }
-                }
-                Err(err) => {
-                    responses.clear();
-                    response_result = Err(err);
-                    // break on error
-                    break;
-                }
-            }
+        // Break when at end of results
+        if done {
+            break;
         }
+    }
 
-        response_result = match response_result {
-            Ok(_) => Ok(responses),
-            Err(err) => Err(err),
-        };
+    Ok(responses)
+}
+
+fn run_query<T>(
+    runtime: &ResMut<TokioTasksRuntime>,
+    tasks: &mut FirestoreTasks,
+    client: &mut Client,
+    credential: &FirestoreCredential,
+    project_id: String,
+    query: query::Query,
+    id: usize,
+) where
+    T: QueryResponseEventBuilder + Event,
+{
+    let mut client = client.clone();
+    let credential = credential.clone();
+
+    tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+        let response = with_refresh!(
+            credential,
+            async_run_query(&mut client, &project_id, &query)
+        );
 
         ctx.run_on_main_thread(move |ctx| {
-            ctx.world.send_event(T::new(response_result, id));
+            ctx.world.send_event(T::new(response, id));
         })
         .await;
-    });
+    }));
 }
 
 // CRUD
 
-/// Creates a Firestore document
+/// Creates a Firestore document in a top-level collection
+///
+/// Use `async_create_document_in` with a `DocumentRef` for subcollections.
 pub async fn async_create_document(
     client: &mut Client,
-    project_id: &String,
-    document_id: &String,
-    collection_id: &String,
+    project_id: &str,
+    document_id: &str,
+    collection_id: &str,
+    fields: HashMap<String, Value>,
+) -> Result<Response<Document>, Status> {
+    create_document(client, project_id, "", collection_id, document_id, fields).await
+}
+
+/// Creates a Firestore document at `document`, e.g. `saves.doc("slot-1")?`,
+/// or `saves.auto_doc()` for a generated ID known beforehand
+pub async fn async_create_document_in(
+    client: &mut Client,
+    project_id: &str,
+    document: impl Into<DocumentRef>,
+    fields: HashMap<String, Value>,
+) -> DocumentResult {
+    let document = document.into();
+    let collection = document.parent();
+
+    create_document(
+        client,
+        project_id,
+        collection.parent_path(),
+        collection.id(),
+        document.id(),
+        fields,
+    )
+    .await
+    .map(|response| response.into_inner())
+}
+
+/// Creates a Firestore document with an `auto_id` in `collection`
+///
+/// The ID is the last segment of the returned document's name. Use
+/// `async_create_document_in` with `CollectionRef::auto_doc` to know it
+/// beforehand.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy_firebase_firestore::*;
+/// async fn new_save(client: &mut Client, uid: &str) -> Result<Document, Status> {
+///     let saves = CollectionRef::new("users")?.doc(uid)?.collection("saves")?;
+///
+///     let save = async_add_document(client, "my-project", &saves, HashMap::new()).await?;
+///     async_create_document_in(client, "my-project", saves.doc("latest")?, save.fields).await
+/// }
+/// ```
+pub async fn async_add_document(
+    client: &mut Client,
+    project_id: &str,
+    collection: impl Into<CollectionRef>,
+    fields: HashMap<String, Value>,
+) -> DocumentResult {
+    async_create_document_in(client, project_id, collection.into().auto_doc(), fields).await
+}
+
+async fn create_document(
+    client: &mut Client,
+    project_id: &str,
+    parent_path: &str,
+    collection_id: &str,
+    document_id: &str,
     fields: HashMap<String, Value>,
 ) -> Result<Response<Document>, Status> {
+    let parent = if parent_path.is_empty() {
+        format!("projects/{project_id}/databases/(default)/documents")
+    } else {
+        format!("projects/{project_id}/databases/(default)/documents/{parent_path}")
+    };
+
     client
         .create_document(CreateDocumentRequest {
-            parent: format!("projects/{project_id}/databases/(default)/documents"),
+            parent,
             collection_id: collection_id.into(),
             document_id: document_id.into(),
             document: Some(Document {
@@ -779,13 +1389,49 @@ pub async fn async_create_document(
 }
 
 /// Updates a Firestore document
+///
+/// Replaces the top-level fields named by the keys of `fields`. Keys are
+/// field names rather than paths, and are escaped in the update mask, so a
+/// key like `"best.time"` updates a field with a dot in its name. Before field
+/// paths were escaped, such a key updated `time` in the `best` map. Use
+/// `async_update_document_fields` to reach into maps.
 pub async fn async_update_document(
     client: &mut Client,
-    project_id: &String,
-    document_path: &String,
+    project_id: &str,
+    document_path: impl AsRef<str>,
     fields: HashMap<String, Value>,
 ) -> Result<Response<Document>, Status> {
-    let field_paths = Vec::from_iter(fields.clone().keys().cloned());
+    update_document(client, project_id, document_path.as_ref(), fields, None).await
+}
+
+/// Updates a Firestore document, if it meets `precondition`
+pub async fn async_update_document_with_precondition(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    fields: HashMap<String, Value>,
+    precondition: WritePrecondition,
+) -> Result<Document, WriteError> {
+    update_document(
+        client,
+        project_id,
+        document_path.as_ref(),
+        fields,
+        Some(precondition),
+    )
+    .await
+    .map(|response| response.into_inner())
+    .map_err(WriteError::from)
+}
+
+async fn update_document(
+    client: &mut Client,
+    project_id: &str,
+    document_path: &str,
+    fields: HashMap<String, Value>,
+    precondition: Option<WritePrecondition>,
+) -> Result<Response<Document>, Status> {
+    let update_mask = fields::top_level_mask(&fields);
 
     client
         .update_document(UpdateDocumentRequest {
@@ -796,7 +1442,8 @@ pub async fn async_update_document(
                 fields,
                 ..Default::default()
             }),
-            update_mask: Some(DocumentMask { field_paths }),
+            update_mask: Some(update_mask),
+            current_document: precondition.map(Into::into),
             ..Default::default()
         })
         .await
@@ -805,9 +1452,11 @@ pub async fn async_update_document(
 /// Reads a Firestore document
 pub async fn async_read_document(
     client: &mut Client,
-    project_id: &String,
-    document_path: &String,
+    project_id: &str,
+    document_path: impl AsRef<str>,
 ) -> Result<Response<Document>, Status> {
+    let document_path = document_path.as_ref();
+
     client
         .get_document(GetDocumentRequest {
             name: format!("projects/{project_id}/databases/(default)/documents/{document_path}"),
@@ -819,13 +1468,40 @@ pub async fn async_read_document(
 /// Deletes a Firestore document
 pub async fn async_delete_document(
     client: &mut Client,
-    project_id: &String,
-    document_path: &String,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+) -> Result<Response<()>, Status> {
+    delete_document(client, project_id, document_path.as_ref(), None).await
+}
+
+/// Deletes a Firestore document, if it meets `precondition`
+pub async fn async_delete_document_with_precondition(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    precondition: WritePrecondition,
+) -> Result<(), WriteError> {
+    delete_document(
+        client,
+        project_id,
+        document_path.as_ref(),
+        Some(precondition),
+    )
+    .await
+    .map(|_| ())
+    .map_err(WriteError::from)
+}
+
+async fn delete_document(
+    client: &mut Client,
+    project_id: &str,
+    document_path: &str,
+    precondition: Option<WritePrecondition>,
 ) -> Result<Response<()>, Status> {
     client
         .delete_document(DeleteDocumentRequest {
             name: format!("projects/{project_id}/databases/(default)/documents/{document_path}"),
-            ..Default::default()
+            current_document: precondition.map(Into::into),
         })
         .await
 }
@@ -836,11 +1512,6 @@ pub type Client = FirestoreClient<InterceptedService<Channel, FirebaseIntercepto
 // CREATE
 //
 
-/// what
-/// assert!(systems);
-/// systemssss
-///
-
 /// Implement this to create custom document create events
 ///
 /// # Examples
@@ -854,6 +1525,7 @@ pub type Client = FirestoreClient<InterceptedService<Channel, FirebaseIntercepto
 /// trait MyCreateDocumentEventBuilder {
 ///    fn new(event: Self) -> Self;
 ///    fn document_id(&self) -> String;
+///    fn parent_path(&self) -> String;
 ///    fn collection_id(&self) -> String;
 ///    fn document_data(&self) -> HashMap<String, Value>;
 ///    fn id(&self) -> usize;
@@ -872,13 +1544,21 @@ pub type Client = FirestoreClient<InterceptedService<Channel, FirebaseIntercepto
 ///     fn document_id(&self) -> String {
 ///         self.document_id.clone()
 ///     }
+///     fn parent_path(&self) -> String {
+///         self.parent_path.clone()
+///     }
 ///     fn id(&self) -> usize {
 ///         self.id
 ///     }
 /// }
 pub trait CreateDocumentEventBuilder {
     fn new(event: Self) -> Self;
+    /// An `auto_id` is generated if this is empty
     fn document_id(&self) -> String;
+    /// Document the collection is under. Empty by default, for the root
+    fn parent_path(&self) -> String {
+        String::new()
+    }
     fn collection_id(&self) -> String;
     fn document_data(&self) -> HashMap<String, Value>;
     fn id(&self) -> usize;
@@ -906,6 +1586,7 @@ pub trait CreateDocumentEventBuilder {
 ///
 ///     document_creator.send(CreateDocumentEvent {
 ///         document_id,
+///         parent_path: String::new(),
 ///         collection_id: "test_collection".into(),
 ///         document_data,
 ///         id: 0,
@@ -913,12 +1594,35 @@ pub trait CreateDocumentEventBuilder {
 /// }
 #[derive(Clone, Event)]
 pub struct CreateDocumentEvent {
+    /// Left empty, an `auto_id` is generated and returned in the response
     pub document_id: String,
+    /// Document the collection is under, e.g. `users/alice` to create in
+    /// `users/alice/saves`. Empty for a top-level collection
+    pub parent_path: String,
     pub collection_id: String,
     pub document_data: HashMap<String, Value>,
     pub id: usize,
 }
 
+impl CreateDocumentEvent {
+    /// Creates `document`. Use `CollectionRef::auto_doc` for a new ID
+    pub fn new(
+        document: impl Into<DocumentRef>,
+        document_data: HashMap<String, Value>,
+        id: usize,
+    ) -> Self {
+        let document = document.into();
+        let collection = document.parent();
+        CreateDocumentEvent {
+            document_id: document.id().into(),
+            parent_path: collection.parent_path().into(),
+            collection_id: collection.id().into(),
+            document_data,
+            id,
+        }
+    }
+}
+
 impl CreateDocumentEventBuilder for CreateDocumentEvent {
     fn new(event: Self) -> Self {
         event
@@ -932,6 +1636,9 @@ impl CreateDocumentEventBuilder for CreateDocumentEvent {
     fn document_id(&self) -> String {
         self.document_id.clone()
     }
+    fn parent_path(&self) -> String {
+        self.parent_path.clone()
+    }
     fn id(&self) -> usize {
         self.id
     }
@@ -952,11 +1659,23 @@ impl CreateDocumentEventBuilder for CreateDocumentEvent {
 ///
 /// impl MyCreateDocumentResponseEventBuilder for CreateDocumentResponseEvent {
 ///     fn new(result: DocumentResult, id: usize) -> Self {
-///         CreateDocumentResponseEvent { result, id }
+///         CreateDocumentResponseEvent {
+///             result,
+///             document_id: String::new(),
+///             id,
+///         }
 ///     }
 /// }
 pub trait CreateDocumentResponseEventBuilder {
     fn new(result: DocumentResult, id: usize) -> Self;
+    /// Also given the ID the document was created with, which may have been
+    /// generated. Calls `new` by default
+    fn new_with_document_id(result: DocumentResult, _document_id: String, id: usize) -> Self
+    where
+        Self: Sized,
+    {
+        Self::new(result, id)
+    }
 }
 
 /// Event that holds the result of a DocumentCreateEvent
@@ -984,12 +1703,30 @@ pub trait CreateDocumentResponseEventBuilder {
 #[derive(Clone, Event)]
 pub struct CreateDocumentResponseEvent {
     pub result: DocumentResult,
+    /// ID of the document, including when it was generated
+    pub document_id: String,
     pub id: usize,
 }
 
 impl CreateDocumentResponseEventBuilder for CreateDocumentResponseEvent {
     fn new(result: DocumentResult, id: usize) -> Self {
-        CreateDocumentResponseEvent { result, id }
+        let document_id = match &result {
+            Ok(document) => document.name.rsplit('/').next().unwrap_or_default().into(),
+            Err(_) => String::new(),
+        };
+
+        CreateDocumentResponseEvent {
+            result,
+            document_id,
+            id,
+        }
+    }
+    fn new_with_document_id(result: DocumentResult, document_id: String, id: usize) -> Self {
+        CreateDocumentResponseEvent {
+            result,
+            document_id,
+            id,
+        }
     }
 }
 
@@ -1013,31 +1750,41 @@ impl CreateDocumentResponseEventBuilder for CreateDocumentResponseEvent {
 /// );
 pub fn create_document_event_handler<T, R>(
     client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
 ) where
     T: CreateDocumentEventBuilder + Event + Clone,
     R: CreateDocumentResponseEventBuilder + Event + Clone,
 {
     for e in er.iter() {
         let mut client = client.0.clone();
+        let credential = credential.clone();
         let project_id = project_id.0.clone();
 
+        let parent_path = e.parent_path();
         let collection_id = e.collection_id();
-        let document_id = e.document_id();
+        let document_id = match e.document_id() {
+            document_id if document_id.is_empty() => auto_id(),
+            document_id => document_id,
+        };
         let fields = e.document_data();
         let id = e.id();
 
-        runtime.spawn_background_task(move |mut ctx| async move {
-            let response = async_create_document(
-                &mut client,
-                &project_id,
-                &document_id,
-                &collection_id,
-                fields,
-            )
-            .await;
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                create_document(
+                    &mut client,
+                    &project_id,
+                    &parent_path,
+                    &collection_id,
+                    &document_id,
+                    fields.clone(),
+                )
+            );
 
             let result = match response {
                 Ok(result) => Ok(result.into_inner()),
@@ -1045,10 +1792,11 @@ pub fn create_document_event_handler<T, R>(
             };
 
             ctx.run_on_main_thread(move |ctx| {
-                ctx.world.send_event(R::new(result, id));
+                ctx.world
+                    .send_event(R::new_with_document_id(result, document_id, id));
             })
             .await;
-        });
+        }));
     }
 }
 
@@ -1067,6 +1815,7 @@ pub fn create_document_event_handler<T, R>(
 ///    fn new(event: Self) -> Self;
 ///    fn document_path(&self) -> String;
 ///    fn document_data(&self) -> HashMap<String, Value>;
+///    fn precondition(&self) -> Option<WritePrecondition>;
 ///    fn id(&self) -> usize;
 /// }
 /// impl MyUpdateDocumentEventBuilder for UpdateDocumentEvent {
@@ -1079,6 +1828,9 @@ pub fn create_document_event_handler<T, R>(
 ///     fn document_path(&self) -> String {
 ///         self.document_path.clone()
 ///     }
+///     fn precondition(&self) -> Option<WritePrecondition> {
+///         self.precondition.clone()
+///     }
 ///     fn id(&self) -> usize {
 ///         self.id
 ///     }
@@ -1087,6 +1839,10 @@ pub trait UpdateDocumentEventBuilder {
     fn new(event: Self) -> Self;
     fn document_path(&self) -> String;
     fn document_data(&self) -> HashMap<String, Value>;
+    /// Condition the document must meet. `None` by default
+    fn precondition(&self) -> Option<WritePrecondition> {
+        None
+    }
     fn id(&self) -> usize;
 }
 
@@ -1115,16 +1871,38 @@ pub trait UpdateDocumentEventBuilder {
 ///     document_updater.send(UpdateDocumentEvent {
 ///         document_path,
 ///         document_data,
+///         precondition: Some(WritePrecondition::Exists(true)),
 ///         id: 2,
 ///     })
 /// }
 #[derive(Clone, Event)]
 pub struct UpdateDocumentEvent {
     pub document_path: String,
+    /// Top-level fields to replace. Keys are escaped, as in
+    /// `async_update_document`
     pub document_data: HashMap<String, Value>,
+    /// Errors with FAILED_PRECONDITION if the document doesn't meet this. See
+    /// `WriteError`
+    pub precondition: Option<WritePrecondition>,
     pub id: usize,
 }
 
+impl UpdateDocumentEvent {
+    /// Updates `document`, without a precondition
+    pub fn new(
+        document: impl Into<DocumentRef>,
+        document_data: HashMap<String, Value>,
+        id: usize,
+    ) -> Self {
+        UpdateDocumentEvent {
+            document_path: document.into().into(),
+            document_data,
+            precondition: None,
+            id,
+        }
+    }
+}
+
 impl UpdateDocumentEventBuilder for UpdateDocumentEvent {
     fn new(event: UpdateDocumentEvent) -> Self {
         event
@@ -1135,6 +1913,9 @@ impl UpdateDocumentEventBuilder for UpdateDocumentEvent {
     fn document_path(&self) -> String {
         self.document_path.clone()
     }
+    fn precondition(&self) -> Option<WritePrecondition> {
+        self.precondition.clone()
+    }
     fn id(&self) -> usize {
         self.id
     }
@@ -1149,15 +1930,15 @@ impl UpdateDocumentEventBuilder for UpdateDocumentEvent {
 /// # use bevy::prelude::*;
 /// # use bevy_firebase_firestore::*;
 /// trait MyUpdateDocumentResponseEventBuilder {
-///    fn new(result: DocumentResult, id: usize) -> Self;
+///    fn new(result: Result<Document, WriteError>, id: usize) -> Self;
 /// }
 /// impl MyUpdateDocumentResponseEventBuilder for UpdateDocumentResponseEvent {
-///     fn new(result: DocumentResult, id: usize) -> Self {
+///     fn new(result: Result<Document, WriteError>, id: usize) -> Self {
 ///         UpdateDocumentResponseEvent { result, id }
 ///     }
 /// }
 pub trait UpdateDocumentResponseEventBuilder {
-    fn new(result: DocumentResult, id: usize) -> Self;
+    fn new(result: Result<Document, WriteError>, id: usize) -> Self;
 }
 
 /// Event that holds the result of an UpdateDocumentEvent
@@ -1178,20 +1959,23 @@ pub trait UpdateDocumentResponseEventBuilder {
 ///             Ok(result) => {
 ///                 println!("Document updated: {:?}", result);
 ///             }
-///             Err(status) => {
-///                 println!("ERROR: Document update failed: {}", status)
+///             Err(WriteError::PreconditionFailed(_)) => {
+///                 println!("Document changed, not updated")
+///             }
+///             Err(e) => {
+///                 println!("ERROR: Document update failed: {}", e)
 ///             }
 ///         }
 ///     }
 /// }
 #[derive(Clone, Event)]
 pub struct UpdateDocumentResponseEvent {
-    pub result: DocumentResult,
+    pub result: Result<Document, WriteError>,
     pub id: usize,
 }
 
 impl UpdateDocumentResponseEventBuilder for UpdateDocumentResponseEvent {
-    fn new(result: DocumentResult, id: usize) -> Self {
+    fn new(result: Result<Document, WriteError>, id: usize) -> Self {
         UpdateDocumentResponseEvent { result, id }
     }
 }
@@ -1217,35 +2001,46 @@ impl UpdateDocumentResponseEventBuilder for UpdateDocumentResponseEvent {
 /// );
 pub fn update_document_event_handler<T, R>(
     client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
 ) where
     T: UpdateDocumentEventBuilder + Event + Clone,
     R: UpdateDocumentResponseEventBuilder + Event + Clone,
 {
     for e in er.iter() {
         let mut client = client.0.clone();
+        let credential = credential.clone();
         let project_id = project_id.0.clone();
 
         let document_path = e.document_path();
         let fields = e.document_data();
+        let precondition = e.precondition();
         let id = e.id();
 
-        runtime.spawn_background_task(move |mut ctx| async move {
-            let response =
-                async_update_document(&mut client, &project_id, &document_path, fields).await;
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                update_document(
+                    &mut client,
+                    &project_id,
+                    &document_path,
+                    fields.clone(),
+                    precondition.clone(),
+                )
+            );
 
-            let result = match response {
-                Ok(result) => Ok(result.into_inner()),
-                Err(status) => Err(status),
-            };
+            let result = response
+                .map(|response| response.into_inner())
+                .map_err(WriteError::from);
 
             ctx.run_on_main_thread(move |ctx| {
                 ctx.world.send_event(R::new(result, id));
             })
             .await;
-        });
+        }));
     }
 }
 
@@ -1291,11 +2086,8 @@ pub trait ReadDocumentEventBuilder {
 /// # use bevy::prelude::*;
 /// # use bevy_firebase_firestore::*;
 /// fn read_test_document(mut document_reader: EventWriter<ReadDocumentEvent>) {
-///     let document_path = "test_collection/test_document".into();
-///     document_reader.send(ReadDocumentEvent {
-///         document_path,
-///         id: 1,
-///     })
+///     let document = DocumentRef::new("test_collection/test_document").unwrap();
+///     document_reader.send(ReadDocumentEvent::new(document, 1))
 /// }
 #[derive(Clone, Event)]
 pub struct ReadDocumentEvent {
@@ -1303,6 +2095,15 @@ pub struct ReadDocumentEvent {
     pub id: usize,
 }
 
+impl ReadDocumentEvent {
+    pub fn new(document: impl Into<DocumentRef>, id: usize) -> Self {
+        ReadDocumentEvent {
+            document_path: document.into().into(),
+            id,
+        }
+    }
+}
+
 impl ReadDocumentEventBuilder for ReadDocumentEvent {
     fn new(event: ReadDocumentEvent) -> Self {
         event
@@ -1389,23 +2190,29 @@ impl ReadDocumentResponseEventBuilder for ReadDocumentResponseEvent {
 /// );
 pub fn read_document_event_handler<T, R>(
     client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
 ) where
     T: ReadDocumentEventBuilder + Event + Clone,
     R: ReadDocumentResponseEventBuilder + Event + Clone,
 {
     for e in er.iter() {
         let mut client = client.0.clone();
+        let credential = credential.clone();
         let project_id = project_id.0.clone();
 
         let document_path = e.document_path();
 
         let id = e.id();
 
-        runtime.spawn_background_task(move |mut ctx| async move {
-            let response = async_read_document(&mut client, &project_id, &document_path).await;
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                async_read_document(&mut client, &project_id, &document_path)
+            );
 
             let result = match response {
                 Ok(result) => Ok(result.into_inner()),
@@ -1416,7 +2223,7 @@ pub fn read_document_event_handler<T, R>(
                 ctx.world.send_event(R::new(result, id));
             })
             .await;
-        });
+        }));
     }
 }
 
@@ -1433,6 +2240,7 @@ pub fn read_document_event_handler<T, R>(
 /// trait MyDeleteDocumentEventBuilder {
 ///     fn new(event: Self) -> Self;
 ///     fn document_path(&self) -> String;
+///     fn precondition(&self) -> Option<WritePrecondition>;
 ///     fn id(&self) -> usize;
 /// }
 /// impl MyDeleteDocumentEventBuilder for DeleteDocumentEvent {
@@ -1442,6 +2250,9 @@ pub fn read_document_event_handler<T, R>(
 ///     fn document_path(&self) -> String {
 ///         self.document_path.clone()
 ///     }
+///     fn precondition(&self) -> Option<WritePrecondition> {
+///         self.precondition.clone()
+///     }
 ///     fn id(&self) -> usize {
 ///         self.id
 ///     }
@@ -1449,6 +2260,10 @@ pub fn read_document_event_handler<T, R>(
 pub trait DeleteDocumentEventBuilder {
     fn new(event: Self) -> Self;
     fn document_path(&self) -> String;
+    /// Condition the document must meet. `None` by default
+    fn precondition(&self) -> Option<WritePrecondition> {
+        None
+    }
     fn id(&self) -> usize;
 }
 
@@ -1461,18 +2276,29 @@ pub trait DeleteDocumentEventBuilder {
 /// # use bevy::prelude::*;
 /// # use bevy_firebase_firestore::*;
 /// fn delete_test_document(mut document_deleter: EventWriter<DeleteDocumentEvent>) {
-///     let document_path = "test_collection/test_document".into();
-///     document_deleter.send(DeleteDocumentEvent {
-///         document_path,
-///         id: 3,
-///     })
+///     let document = DocumentRef::new("test_collection/test_document").unwrap();
+///     document_deleter.send(DeleteDocumentEvent::new(document, 3))
 /// }
 #[derive(Clone, Event)]
 pub struct DeleteDocumentEvent {
     pub document_path: String,
+    /// Errors with FAILED_PRECONDITION if the document doesn't meet this. See
+    /// `WriteError`
+    pub precondition: Option<WritePrecondition>,
     pub id: usize,
 }
 
+impl DeleteDocumentEvent {
+    /// Deletes `document`, without a precondition
+    pub fn new(document: impl Into<DocumentRef>, id: usize) -> Self {
+        DeleteDocumentEvent {
+            document_path: document.into().into(),
+            precondition: None,
+            id,
+        }
+    }
+}
+
 impl DeleteDocumentEventBuilder for DeleteDocumentEvent {
     fn new(event: DeleteDocumentEvent) -> Self {
         event
@@ -1480,6 +2306,9 @@ impl DeleteDocumentEventBuilder for DeleteDocumentEvent {
     fn document_path(&self) -> String {
         self.document_path.clone()
     }
+    fn precondition(&self) -> Option<WritePrecondition> {
+        self.precondition.clone()
+    }
     fn id(&self) -> usize {
         self.id
     }
@@ -1495,15 +2324,15 @@ impl DeleteDocumentEventBuilder for DeleteDocumentEvent {
 /// # use bevy_firebase_firestore::*;
 ///
 /// trait MyDeleteDocumentResponseEventBuilder {
-///    fn new(result: Result<(), Status>, id: usize) -> Self;
+///    fn new(result: Result<(), WriteError>, id: usize) -> Self;
 /// }
 /// impl MyDeleteDocumentResponseEventBuilder for DeleteDocumentResponseEvent {
-///     fn new(result: Result<(), Status>, id: usize) -> Self {
+///     fn new(result: Result<(), WriteError>, id: usize) -> Self {
 ///         DeleteDocumentResponseEvent { result, id }
 ///     }
 /// }
 pub trait DeleteDocumentResponseEventBuilder {
-    fn new(result: Result<(), Status>, id: usize) -> Self;
+    fn new(result: Result<(), WriteError>, id: usize) -> Self;
 }
 
 /// Event that holds the result of a DeleteDocumentEvent
@@ -1524,20 +2353,23 @@ pub trait DeleteDocumentResponseEventBuilder {
 ///             Ok(result) => {
 ///                 println!("Document deleted: {:?}", result);
 ///             }
-///             Err(status) => {
-///                 println!("ERROR: Document delete failed: {}", status)
+///             Err(WriteError::PreconditionFailed(_)) => {
+///                 println!("Document changed, not deleted")
+///             }
+///             Err(e) => {
+///                 println!("ERROR: Document delete failed: {}", e)
 ///             }
 ///         }
 ///     }
 /// }
 #[derive(Clone, Event)]
 pub struct DeleteDocumentResponseEvent {
-    pub result: Result<(), Status>,
+    pub result: Result<(), WriteError>,
     pub id: usize,
 }
 
 impl DeleteDocumentResponseEventBuilder for DeleteDocumentResponseEvent {
-    fn new(result: Result<(), Status>, id: usize) -> Self {
+    fn new(result: Result<(), WriteError>, id: usize) -> Self {
         DeleteDocumentResponseEvent { result, id }
     }
 }
@@ -1562,32 +2394,41 @@ impl DeleteDocumentResponseEventBuilder for DeleteDocumentResponseEvent {
 /// );
 pub fn delete_document_event_handler<T, R>(
     client: ResMut<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
     project_id: Res<ProjectId>,
     mut er: EventReader<T>,
     runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
 ) where
     T: DeleteDocumentEventBuilder + Event + Clone,
     R: DeleteDocumentResponseEventBuilder + Event + Clone,
 {
     for e in er.iter() {
         let mut client = client.0.clone();
+        let credential = credential.clone();
         let project_id = project_id.0.clone();
 
         let document_path = e.document_path();
+        let precondition = e.precondition();
         let id = e.id();
 
-        runtime.spawn_background_task(move |mut ctx| async move {
-            let response = async_delete_document(&mut client, &project_id, &document_path).await;
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                delete_document(
+                    &mut client,
+                    &project_id,
+                    &document_path,
+                    precondition.clone(),
+                )
+            );
 
-            let result = match response {
-                Ok(_) => Ok(()),
-                Err(status) => Err(status),
-            };
+            let result = response.map(|_| ()).map_err(WriteError::from);
 
             ctx.run_on_main_thread(move |ctx| {
                 ctx.world.send_event(R::new(result, id));
             })
             .await;
-        });
+        }));
     }
 }
@@ -0,0 +1,426 @@
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+
+use crate::{
+    async_run_query,
+    credential::with_refresh,
+    query::{self, NAME_FIELD},
+    value::ValueType,
+    BevyFirestoreClient, Document, FirestoreCredential, FirestoreTasks, Status, Value,
+};
+
+/// Bevy `Component` that pages through the results of a `query::Query`
+///
+/// It keeps the first and last document of the current page, and builds the
+/// cursors for the next and previous pages from their order by fields. The
+/// query is also ordered by `__name__`, so documents with the same values at
+/// a page boundary are neither skipped nor repeated. Any limit on the query is
+/// replaced, while its cursors bound the results paged through.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn spawn_leaderboard(mut commands: Commands, mut ew: EventWriter<LoadPageEvent>) {
+///     let query = query::Query::collection("click")
+///         .order_by("score", QueryDirection::Descending);
+///
+///     let paginator = commands.spawn(Paginator::new(query, 10)).id();
+///
+///     ew.send(LoadPageEvent {
+///         paginator,
+///         page: PageRequest::First,
+///     });
+/// }
+/// ```
+#[derive(Component, Clone, Debug)]
+pub struct Paginator {
+    query: query::Query,
+    page_size: i32,
+    page_index: usize,
+    first: Option<Document>,
+    last: Option<Document>,
+    has_more: bool,
+}
+
+impl Paginator {
+    pub fn new(query: query::Query, page_size: i32) -> Self {
+        Paginator {
+            query: query.ordered_by_name(),
+            page_size,
+            page_index: 0,
+            first: None,
+            last: None,
+            has_more: false,
+        }
+    }
+
+    /// Index of the current page, starting at 0
+    pub fn page_index(&self) -> usize {
+        self.page_index
+    }
+
+    /// Whether there's a page after the current one
+    pub fn has_more(&self) -> bool {
+        self.has_more
+    }
+
+    /// Whether there's a page before the current one
+    pub fn has_previous(&self) -> bool {
+        self.page_index > 0
+    }
+
+    /// The last document of the current page
+    pub fn last_document(&self) -> Option<&Document> {
+        self.last.as_ref()
+    }
+
+    /// The values of `document` for each ordering of the query, for use as a
+    /// cursor
+    pub fn cursor(&self, document: &Document) -> Vec<Value> {
+        self.query
+            .orderings()
+            .iter()
+            .map(|order| {
+                let field_path = order
+                    .field
+                    .as_ref()
+                    .map(|field| field.field_path.as_str())
+                    .unwrap_or_default();
+
+                field_value(document, field_path)
+            })
+            .collect()
+    }
+
+    // The query for `page`, with one extra result to tell if there's more
+    fn page_query(&self, page: PageRequest) -> query::Query {
+        let query = self.query.clone().limit(self.page_size + 1);
+
+        match (page, &self.first, &self.last) {
+            (PageRequest::Next, _, Some(last)) => query.start_after(self.cursor(last)),
+            (PageRequest::Previous, Some(first), _) if self.page_index > 0 => {
+                query.end_before(self.cursor(first)).reversed()
+            }
+            // An empty page ends just after the previous page
+            (PageRequest::Previous, None, Some(last)) if self.page_index > 0 => {
+                query.end_at(self.cursor(last)).reversed()
+            }
+            _ => query,
+        }
+    }
+
+    // Moves to the page that was loaded for `page`, returning its index
+    fn load(&mut self, page: PageRequest, mut documents: Vec<Document>) -> (usize, Vec<Document>) {
+        let page_size = self.page_size.max(0) as usize;
+        let extra = documents.len() > page_size;
+        documents.truncate(page_size);
+
+        match page {
+            PageRequest::Next if self.last.is_some() => {
+                self.page_index += 1;
+                self.has_more = extra;
+            }
+            PageRequest::Previous if self.page_index > 0 && self.last.is_some() => {
+                documents.reverse();
+                // Fewer results than asked for means there's nothing before
+                self.page_index = if extra { self.page_index - 1 } else { 0 };
+                self.has_more = true;
+            }
+            _ => {
+                self.page_index = 0;
+                self.has_more = extra;
+            }
+        }
+
+        // An empty page keeps the last document, to find its way back
+        self.first = documents.first().cloned();
+        if let Some(last) = documents.last() {
+            self.last = Some(last.clone());
+        }
+
+        (self.page_index, documents)
+    }
+}
+
+/// Which page a `LoadPageEvent` loads, relative to the current one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageRequest {
+    First,
+    Next,
+    Previous,
+}
+
+/// Loads a page for the `Paginator` on the `paginator` entity
+///
+/// Without a current page, `Next` and `Previous` load the first page.
+#[derive(Event, Clone, Debug)]
+pub struct LoadPageEvent {
+    pub paginator: Entity,
+    pub page: PageRequest,
+}
+
+/// Sent when a page has been loaded
+#[derive(Event, Clone, Debug)]
+pub struct PageLoaded {
+    pub paginator: Entity,
+    pub page_index: usize,
+    pub documents: Vec<Document>,
+    /// Whether there's a page after this one
+    pub has_more: bool,
+}
+
+/// Sent when a page couldn't be loaded. The `Paginator` stays on its page
+#[derive(Event, Clone, Debug)]
+pub struct PageLoadFailed {
+    pub paginator: Entity,
+    pub status: Status,
+}
+
+pub(crate) fn load_page_event_handler(
+    mut er: EventReader<LoadPageEvent>,
+    paginators: Query<&Paginator>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let Ok(paginator) = paginators.get(e.paginator) else {
+            warn!(
+                target: "bevy_firebase_firestore::pagination",
+                "No Paginator on {:?}",
+                e.paginator
+            );
+            continue;
+        };
+
+        let entity = e.paginator;
+        let page = e.page;
+        let query = paginator.page_query(page);
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                async_run_query(&mut client, &project_id, &query)
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                let documents = match response {
+                    Ok(responses) => responses
+                        .into_iter()
+                        .filter_map(|response| response.document)
+                        .collect(),
+                    Err(status) => {
+                        ctx.world.send_event(PageLoadFailed {
+                            paginator: entity,
+                            status,
+                        });
+                        return;
+                    }
+                };
+
+                // The paginator may have been despawned in the meantime
+                let Some(mut paginator) = ctx.world.get_mut::<Paginator>(entity) else {
+                    return;
+                };
+
+                let (page_index, documents) = paginator.load(page, documents);
+                let has_more = paginator.has_more;
+
+                ctx.world.send_event(PageLoaded {
+                    paginator: entity,
+                    page_index,
+                    documents,
+                    has_more,
+                });
+            })
+            .await;
+        }));
+    }
+}
+
+// Looks up a dotted field path, with `__name__` as a reference to the document
+fn field_value(document: &Document, field_path: &str) -> Value {
+    if field_path == NAME_FIELD {
+        return Value {
+            value_type: Some(ValueType::ReferenceValue(document.name.clone())),
+        };
+    }
+
+    let mut segments = field_path.split('.');
+    let mut value = segments
+        .next()
+        .and_then(|segment| document.fields.get(segment));
+
+    for segment in segments {
+        value = match value.and_then(|value| value.value_type.as_ref()) {
+            Some(ValueType::MapValue(map)) => map.fields.get(segment),
+            _ => None,
+        };
+    }
+
+    // Documents missing an order by field aren't in the results anyway
+    value.cloned().unwrap_or(Value {
+        value_type: Some(ValueType::NullValue(0)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryDirection;
+
+    fn int(value: i64) -> Value {
+        Value {
+            value_type: Some(ValueType::IntegerValue(value)),
+        }
+    }
+
+    fn reference(name: &str) -> Value {
+        Value {
+            value_type: Some(ValueType::ReferenceValue(name.to_string())),
+        }
+    }
+
+    fn document(name: &str, score: i64) -> Document {
+        Document {
+            name: name.to_string(),
+            fields: [("score".to_string(), int(score))].into_iter().collect(),
+            ..default()
+        }
+    }
+
+    // Documents `first..first + count`, with descending scores
+    fn documents(first: i64, count: i64) -> Vec<Document> {
+        (first..first + count)
+            .map(|i| document(&format!("click/{i}"), 100 - i))
+            .collect()
+    }
+
+    fn paginator() -> Paginator {
+        let query = query::Query::collection("click").order_by("score", QueryDirection::Descending);
+        Paginator::new(query, 2)
+    }
+
+    // A cursor's values and whether it's before them
+    type Bound = Option<(Vec<Value>, bool)>;
+
+    fn cursors(query: &query::Query) -> (Bound, Bound) {
+        let structured_query = query.structured_query();
+        (
+            structured_query
+                .start_at
+                .map(|cursor| (cursor.values, cursor.before)),
+            structured_query
+                .end_at
+                .map(|cursor| (cursor.values, cursor.before)),
+        )
+    }
+
+    #[test]
+    fn first_page_asks_for_one_extra_document() {
+        let paginator = paginator();
+        let query = paginator.page_query(PageRequest::First);
+
+        assert_eq!(query.structured_query().limit, Some(3));
+        assert_eq!(cursors(&query), (None, None));
+
+        // Without a current page, Next and Previous start from the beginning
+        assert_eq!(paginator.page_query(PageRequest::Next), query);
+        assert_eq!(paginator.page_query(PageRequest::Previous), query);
+    }
+
+    #[test]
+    fn next_page_starts_after_the_last_name() {
+        let mut paginator = paginator();
+        let (page_index, page) = paginator.load(PageRequest::First, documents(0, 3));
+        assert_eq!(page_index, 0);
+        assert_eq!(page, documents(0, 2));
+        assert!(paginator.has_more());
+        assert!(!paginator.has_previous());
+
+        let query = paginator.page_query(PageRequest::Next);
+        assert_eq!(
+            cursors(&query),
+            (Some((vec![int(99), reference("click/1")], false)), None)
+        );
+
+        let (page_index, page) = paginator.load(PageRequest::Next, documents(2, 1));
+        assert_eq!(page_index, 1);
+        assert_eq!(page, documents(2, 1));
+        assert!(!paginator.has_more());
+        assert!(paginator.has_previous());
+    }
+
+    #[test]
+    fn previous_page_reads_backwards_from_the_first_document() {
+        let mut paginator = paginator();
+        paginator.load(PageRequest::First, documents(0, 3));
+        paginator.load(PageRequest::Next, documents(2, 3));
+        paginator.load(PageRequest::Next, documents(4, 2));
+        assert_eq!(paginator.page_index(), 2);
+
+        let query = paginator.page_query(PageRequest::Previous);
+        let orderings = query.structured_query().order_by;
+        assert!(orderings
+            .iter()
+            .all(|order| order.direction == QueryDirection::Ascending as i32));
+        assert_eq!(
+            cursors(&query),
+            (Some((vec![int(96), reference("click/4")], false)), None)
+        );
+
+        // Results come back in reverse, with one more before them
+        let mut results = documents(1, 3);
+        results.reverse();
+        let (page_index, page) = paginator.load(PageRequest::Previous, results);
+        assert_eq!(page_index, 1);
+        assert_eq!(page, documents(2, 2));
+        assert!(paginator.has_more());
+    }
+
+    #[test]
+    fn previous_page_without_more_results_is_the_first() {
+        let mut paginator = paginator();
+        paginator.load(PageRequest::First, documents(0, 3));
+        paginator.load(PageRequest::Next, documents(2, 3));
+        paginator.load(PageRequest::Next, documents(4, 1));
+
+        // Documents were deleted, so only two are left before this page
+        let mut results = documents(0, 2);
+        results.reverse();
+        let (page_index, page) = paginator.load(PageRequest::Previous, results);
+        assert_eq!(page_index, 0);
+        assert_eq!(page, documents(0, 2));
+        assert!(!paginator.has_previous());
+    }
+
+    #[test]
+    fn previous_page_from_an_empty_page_includes_the_last_document() {
+        let mut paginator = paginator();
+        paginator.load(PageRequest::First, documents(0, 2));
+        paginator.load(PageRequest::Next, vec![]);
+        assert_eq!(paginator.page_index(), 1);
+        assert_eq!(paginator.last_document(), Some(&documents(1, 1)[0]));
+
+        let query = paginator.page_query(PageRequest::Previous);
+        assert_eq!(
+            cursors(&query),
+            (Some((vec![int(99), reference("click/1")], true)), None)
+        );
+
+        let mut results = documents(0, 2);
+        results.reverse();
+        let (page_index, page) = paginator.load(PageRequest::Previous, results);
+        assert_eq!(page_index, 0);
+        assert_eq!(page, documents(0, 2));
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use prost_types::Timestamp;
+use tonic::{Code, Status};
+
+use crate::googleapis::google::firestore::v1::{precondition::ConditionType, Precondition};
+
+/// A condition the document must meet for a write to apply
+///
+/// Writes that don't meet it fail with `WriteError::PreconditionFailed`.
+/// Converts into the generated `Precondition` message with `into()`.
+///
+/// # Examples
+///
+/// Saving only if nobody else has since the save was read:
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy_firebase_firestore::*;
+/// async fn save(
+///     client: &mut Client,
+///     read: &Document,
+///     fields: HashMap<String, Value>,
+/// ) -> Result<Document, WriteError> {
+///     let precondition = match &read.update_time {
+///         Some(update_time) => WritePrecondition::UpdateTime(update_time.clone()),
+///         None => WritePrecondition::Exists(false),
+///     };
+///
+///     async_update_document_with_precondition(
+///         client,
+///         "my-project",
+///         "saves/player",
+///         fields,
+///         precondition,
+///     )
+///     .await
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum WritePrecondition {
+    /// The document must exist, or must not exist if `false`
+    Exists(bool),
+    /// The document must exist and have last been updated at this time
+    UpdateTime(Timestamp),
+}
+
+impl From<WritePrecondition> for Precondition {
+    fn from(precondition: WritePrecondition) -> Self {
+        Precondition {
+            condition_type: Some(match precondition {
+                WritePrecondition::Exists(exists) => ConditionType::Exists(exists),
+                WritePrecondition::UpdateTime(update_time) => {
+                    ConditionType::UpdateTime(update_time)
+                }
+            }),
+        }
+    }
+}
+
+/// Why a write failed, with failed preconditions told apart
+#[derive(Clone, Debug)]
+pub enum WriteError {
+    /// The document didn't meet the write's `WritePrecondition`
+    PreconditionFailed(Status),
+    Status(Status),
+}
+
+impl WriteError {
+    pub fn status(&self) -> &Status {
+        match self {
+            WriteError::PreconditionFailed(status) | WriteError::Status(status) => status,
+        }
+    }
+}
+
+impl From<Status> for WriteError {
+    fn from(status: Status) -> Self {
+        if status.code() == Code::FailedPrecondition {
+            WriteError::PreconditionFailed(status)
+        } else {
+            WriteError::Status(status)
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::PreconditionFailed(status) => {
+                write!(f, "Precondition failed: {}", status.message())
+            }
+            WriteError::Status(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_preconditions_are_told_apart() {
+        let error = WriteError::from(Status::failed_precondition("changed"));
+        assert!(matches!(error, WriteError::PreconditionFailed(_)));
+        assert_eq!(error.to_string(), "Precondition failed: changed");
+
+        let error = WriteError::from(Status::not_found("gone"));
+        assert!(matches!(error, WriteError::Status(_)));
+        assert_eq!(error.status().code(), Code::NotFound);
+    }
+
+    #[test]
+    fn converts_into_the_generated_precondition() {
+        let precondition: Precondition = WritePrecondition::Exists(false).into();
+        assert_eq!(
+            precondition.condition_type,
+            Some(ConditionType::Exists(false))
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Typed builder for Firestore structured queries
+//!
+//! This module isn't glob re-exported, as its `Query` would clash with bevy's.
+//! Refer to it as `query::Query`, or import it under another name.
+//!
+//! # Examples
+//!
+//! ```
+//! # use bevy_firebase_firestore::*;
+//! # use bevy_firebase_firestore::value::ValueType;
+//! use bevy_firebase_firestore::query::{Filter, Query as FirestoreQuery};
+//!
+//! let top_scores = FirestoreQuery::collection("scores")
+//!     .filter(Filter::or(vec![
+//!         Filter::equal("mode", string_value("ranked")),
+//!         Filter::is_in("region", vec![string_value("eu"), string_value("na")]),
+//!     ]))
+//!     .filter(Filter::not_equal("banned", Value {
+//!         value_type: Some(ValueType::BooleanValue(true)),
+//!     }))
+//!     .order_by("score", QueryDirection::Descending)
+//!     .order_by("name", QueryDirection::Ascending)
+//!     .select(["name", "score"])
+//!     .limit(10);
+//!
+//! fn string_value(value: &str) -> Value {
+//!     Value {
+//!         value_type: Some(ValueType::StringValue(value.into())),
+//!     }
+//! }
+//! ```
+
+use crate::googleapis::google::firestore::v1::{
+    run_query_request::QueryType,
+    structured_query::{
+        composite_filter, field_filter, filter::FilterType, unary_filter, CollectionSelector,
+        CompositeFilter, FieldFilter, FieldReference, Filter as StructuredFilter, Order,
+        Projection, UnaryFilter,
+    },
+    value::ValueType,
+    ArrayValue, Cursor, RunQueryRequest, StructuredQuery, Value,
+};
+use crate::QueryDirection;
+
+/// Field path that refers to the document's name, for ordering and cursors
+pub const NAME_FIELD: &str = "__name__";
+
+pub use field_filter::Operator as FieldOperator;
+pub use unary_filter::Operator as UnaryOperator;
+
+/// A condition documents must meet to be returned by a `Query`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter(StructuredFilter);
+
+impl Filter {
+    /// Compares a field with `value`
+    pub fn field(field_path: impl Into<String>, op: FieldOperator, value: Value) -> Self {
+        Filter(StructuredFilter {
+            filter_type: Some(FilterType::FieldFilter(FieldFilter {
+                field: Some(field_reference(field_path)),
+                op: op as i32,
+                value: Some(value),
+            })),
+        })
+    }
+
+    /// Checks a field without comparing it to a value, e.g. `IS NULL`
+    pub fn unary(field_path: impl Into<String>, op: UnaryOperator) -> Self {
+        Filter(StructuredFilter {
+            filter_type: Some(FilterType::UnaryFilter(UnaryFilter {
+                op: op as i32,
+                operand_type: Some(unary_filter::OperandType::Field(field_reference(
+                    field_path,
+                ))),
+            })),
+        })
+    }
+
+    /// Matches documents that match every one of `filters`
+    pub fn and(filters: Vec<Filter>) -> Self {
+        Self::composite(composite_filter::Operator::And, filters)
+    }
+
+    /// Matches documents that match at least one of `filters`
+    pub fn or(filters: Vec<Filter>) -> Self {
+        Self::composite(composite_filter::Operator::Or, filters)
+    }
+
+    fn composite(op: composite_filter::Operator, filters: Vec<Filter>) -> Self {
+        Filter(StructuredFilter {
+            filter_type: Some(FilterType::CompositeFilter(CompositeFilter {
+                op: op as i32,
+                filters: filters.into_iter().map(|filter| filter.0).collect(),
+            })),
+        })
+    }
+
+    pub fn equal(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::Equal, value)
+    }
+
+    /// `!=`. Documents without the field don't match
+    pub fn not_equal(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::NotEqual, value)
+    }
+
+    pub fn less_than(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::LessThan, value)
+    }
+
+    pub fn less_than_or_equal(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::LessThanOrEqual, value)
+    }
+
+    pub fn greater_than(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::GreaterThan, value)
+    }
+
+    pub fn greater_than_or_equal(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::GreaterThanOrEqual, value)
+    }
+
+    /// Matches array fields containing `value`
+    pub fn array_contains(field_path: impl Into<String>, value: Value) -> Self {
+        Self::field(field_path, FieldOperator::ArrayContains, value)
+    }
+
+    /// Matches array fields containing any of `values`, at most 30 of them
+    pub fn array_contains_any(field_path: impl Into<String>, values: Vec<Value>) -> Self {
+        Self::field(field_path, FieldOperator::ArrayContainsAny, array(values))
+    }
+
+    /// `in`. Matches fields equal to any of `values`, at most 30 of them
+    pub fn is_in(field_path: impl Into<String>, values: Vec<Value>) -> Self {
+        Self::field(field_path, FieldOperator::In, array(values))
+    }
+
+    /// `not-in`. Documents without the field don't match
+    pub fn not_in(field_path: impl Into<String>, values: Vec<Value>) -> Self {
+        Self::field(field_path, FieldOperator::NotIn, array(values))
+    }
+
+    pub fn is_null(field_path: impl Into<String>) -> Self {
+        Self::unary(field_path, UnaryOperator::IsNull)
+    }
+
+    pub fn is_not_null(field_path: impl Into<String>) -> Self {
+        Self::unary(field_path, UnaryOperator::IsNotNull)
+    }
+
+    pub fn is_nan(field_path: impl Into<String>) -> Self {
+        Self::unary(field_path, UnaryOperator::IsNan)
+    }
+
+    pub fn is_not_nan(field_path: impl Into<String>) -> Self {
+        Self::unary(field_path, UnaryOperator::IsNotNan)
+    }
+}
+
+impl From<Filter> for StructuredFilter {
+    fn from(filter: Filter) -> Self {
+        filter.0
+    }
+}
+
+/// A Firestore structured query
+///
+/// Filters added with `filter` are combined with AND. Cursor values follow
+/// the order of `order_by` calls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    parent: String,
+    from: CollectionSelector,
+    filters: Vec<Filter>,
+    order_by: Vec<Order>,
+    start_at: Option<Cursor>,
+    end_at: Option<Cursor>,
+    offset: i32,
+    limit: Option<i32>,
+    select: Option<Projection>,
+}
+
+impl Query {
+    /// Queries the collection `collection_id` directly under the parent
+    pub fn collection(collection_id: impl Into<String>) -> Self {
+        Query {
+            parent: "".into(),
+            from: CollectionSelector {
+                collection_id: collection_id.into(),
+                all_descendants: false,
+            },
+            filters: Vec::new(),
+            order_by: Vec::new(),
+            start_at: None,
+            end_at: None,
+            offset: 0,
+            limit: None,
+            select: None,
+        }
+    }
+
+    /// Queries every collection named `collection_id` under the parent, at
+    /// any depth
+    pub fn collection_group(collection_id: impl Into<String>) -> Self {
+        let mut query = Self::collection(collection_id);
+        query.from.all_descendants = true;
+        query
+    }
+
+    /// Path of the document to query under, e.g. `"users/alice"`
+    ///
+    /// Defaults to the database root.
+    pub fn parent(mut self, document_path: impl Into<String>) -> Self {
+        self.parent = document_path.into();
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Adds an ordering. Earlier orderings take precedence
+    pub fn order_by(mut self, field_path: impl Into<String>, direction: QueryDirection) -> Self {
+        self.order_by.push(Order {
+            field: Some(field_reference(field_path)),
+            direction: direction as i32,
+        });
+        self
+    }
+
+    /// Starts at the document with these order by values, inclusive
+    pub fn start_at(mut self, values: Vec<Value>) -> Self {
+        self.start_at = Some(Cursor {
+            values,
+            before: true,
+        });
+        self
+    }
+
+    /// Starts after the document with these order by values
+    pub fn start_after(mut self, values: Vec<Value>) -> Self {
+        self.start_at = Some(Cursor {
+            values,
+            before: false,
+        });
+        self
+    }
+
+    /// Ends at the document with these order by values, inclusive
+    pub fn end_at(mut self, values: Vec<Value>) -> Self {
+        self.end_at = Some(Cursor {
+            values,
+            before: false,
+        });
+        self
+    }
+
+    /// Ends before the document with these order by values
+    pub fn end_before(mut self, values: Vec<Value>) -> Self {
+        self.end_at = Some(Cursor {
+            values,
+            before: true,
+        });
+        self
+    }
+
+    /// Skips the first `offset` results
+    pub fn offset(mut self, offset: i32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit: i32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only returns these fields. An empty list returns only document names
+    pub fn select<S: Into<String>>(mut self, field_paths: impl IntoIterator<Item = S>) -> Self {
+        self.select = Some(Projection {
+            fields: field_paths.into_iter().map(field_reference).collect(),
+        });
+        self
+    }
+
+    pub(crate) fn orderings(&self) -> &[Order] {
+        &self.order_by
+    }
+
+    // Adds the `__name__` ordering Firestore sorts ties by, so cursors can
+    // refer to it. It follows the direction of the last ordering
+    pub(crate) fn ordered_by_name(mut self) -> Self {
+        let ordered = self
+            .order_by
+            .iter()
+            .any(|order| matches!(&order.field, Some(field) if field.field_path == NAME_FIELD));
+        if ordered {
+            return self;
+        }
+
+        let direction = self
+            .order_by
+            .last()
+            .map(|order| order.direction)
+            .unwrap_or(QueryDirection::Ascending as i32);
+
+        self.order_by.push(Order {
+            field: Some(field_reference(NAME_FIELD)),
+            direction,
+        });
+        self
+    }
+
+    // The same results in the opposite order, for reading backwards from a
+    // cursor. The cursors swap ends and sides
+    pub(crate) fn reversed(mut self) -> Self {
+        for order in &mut self.order_by {
+            order.direction = if order.direction == QueryDirection::Descending as i32 {
+                QueryDirection::Ascending as i32
+            } else {
+                QueryDirection::Descending as i32
+            };
+        }
+
+        let start_at = self.end_at.take().map(|cursor| Cursor {
+            before: !cursor.before,
+            ..cursor
+        });
+        self.end_at = self.start_at.take().map(|cursor| Cursor {
+            before: !cursor.before,
+            ..cursor
+        });
+        self.start_at = start_at;
+        self
+    }
+
+    /// The `StructuredQuery` sent to Firestore
+    pub fn structured_query(&self) -> StructuredQuery {
+        let r#where = match self.filters.as_slice() {
+            [] => None,
+            [filter] => Some(filter.0.clone()),
+            filters => Some(Filter::and(filters.to_vec()).0),
+        };
+
+        StructuredQuery {
+            select: self.select.clone(),
+            from: vec![self.from.clone()],
+            r#where,
+            order_by: self.order_by.clone(),
+            start_at: self.start_at.clone(),
+            end_at: self.end_at.clone(),
+            offset: self.offset,
+            limit: self.limit,
+        }
+    }
+
+    /// The `RunQueryRequest` for this query in `project_id`
+    pub fn request(&self, project_id: &str) -> RunQueryRequest {
+        RunQueryRequest {
+            parent: self.parent_resource(project_id),
+            query_type: Some(QueryType::StructuredQuery(self.structured_query())),
+            ..Default::default()
+        }
+    }
+
+    // Full resource name of the document the query runs under
+    pub(crate) fn parent_resource(&self, project_id: &str) -> String {
+        let parent = if !self.parent.is_empty() {
+            format!("/{}", self.parent)
+        } else {
+            "".into()
+        };
+
+        format!("projects/{project_id}/databases/(default)/documents{parent}")
+    }
+}
+
+pub(crate) fn field_reference(field_path: impl Into<String>) -> FieldReference {
+    FieldReference {
+        field_path: field_path.into(),
+    }
+}
+
+fn array(values: Vec<Value>) -> Value {
+    Value {
+        value_type: Some(ValueType::ArrayValue(ArrayValue { values })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i64) -> Value {
+        Value {
+            value_type: Some(ValueType::IntegerValue(value)),
+        }
+    }
+
+    #[test]
+    fn filters_are_combined_with_and() {
+        let query = Query::collection("scores");
+        assert_eq!(query.structured_query().r#where, None);
+
+        let query = query.filter(Filter::greater_than("score", int(10)));
+        assert_eq!(
+            query.structured_query().r#where,
+            Some(Filter::greater_than("score", int(10)).0)
+        );
+
+        let query = query.filter(Filter::is_not_null("name"));
+        assert_eq!(
+            query.structured_query().r#where,
+            Some(
+                Filter::and(vec![
+                    Filter::greater_than("score", int(10)),
+                    Filter::is_not_null("name"),
+                ])
+                .0
+            )
+        );
+    }
+
+    #[test]
+    fn cursors_set_their_side() {
+        let query = Query::collection("scores").start_at(vec![int(1)]);
+        assert!(query.structured_query().start_at.unwrap().before);
+
+        let query = query.start_after(vec![int(1)]).end_at(vec![int(9)]);
+        let structured_query = query.structured_query();
+        assert!(!structured_query.start_at.unwrap().before);
+        assert!(!structured_query.end_at.unwrap().before);
+
+        let query = query.end_before(vec![int(9)]);
+        assert!(query.structured_query().end_at.unwrap().before);
+    }
+
+    fn directions(query: &Query) -> Vec<(String, i32)> {
+        query
+            .orderings()
+            .iter()
+            .map(|order| {
+                let field = order.field.as_ref().unwrap();
+                (field.field_path.clone(), order.direction)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ordered_by_name_follows_the_last_direction() {
+        let query = Query::collection("scores")
+            .order_by("level", QueryDirection::Ascending)
+            .order_by("score", QueryDirection::Descending)
+            .ordered_by_name();
+
+        assert_eq!(
+            directions(&query),
+            vec![
+                ("level".to_string(), QueryDirection::Ascending as i32),
+                ("score".to_string(), QueryDirection::Descending as i32),
+                (NAME_FIELD.to_string(), QueryDirection::Descending as i32),
+            ]
+        );
+
+        // Once is enough
+        assert_eq!(query.clone().ordered_by_name(), query);
+
+        let query = Query::collection("scores").ordered_by_name();
+        assert_eq!(
+            directions(&query),
+            vec![(NAME_FIELD.to_string(), QueryDirection::Ascending as i32)]
+        );
+    }
+
+    #[test]
+    fn ordered_by_name_keeps_an_existing_name_ordering() {
+        let query = Query::collection("scores")
+            .order_by(NAME_FIELD, QueryDirection::Ascending)
+            .order_by("score", QueryDirection::Descending);
+
+        assert_eq!(query.clone().ordered_by_name(), query);
+    }
+
+    #[test]
+    fn reversed_flips_orderings_and_cursors() {
+        let query = Query::collection("scores")
+            .order_by("score", QueryDirection::Descending)
+            .ordered_by_name()
+            .start_after(vec![int(10)])
+            .end_at(vec![int(1)]);
+
+        let reversed = query.clone().reversed();
+        assert_eq!(
+            directions(&reversed),
+            vec![
+                ("score".to_string(), QueryDirection::Ascending as i32),
+                (NAME_FIELD.to_string(), QueryDirection::Ascending as i32),
+            ]
+        );
+
+        // The cursor that ended at 1 now starts at it, and the one after 10
+        // ends before it
+        let start_at = reversed.start_at.clone().unwrap();
+        assert_eq!(start_at.values, vec![int(1)]);
+        assert!(start_at.before);
+        let end_at = reversed.end_at.clone().unwrap();
+        assert_eq!(end_at.values, vec![int(10)]);
+        assert!(end_at.before);
+
+        assert_eq!(reversed.reversed(), query);
+    }
+
+    #[test]
+    fn reversed_moves_a_single_cursor_to_the_other_end() {
+        let reversed = Query::collection("scores")
+            .end_before(vec![int(5)])
+            .reversed();
+
+        assert_eq!(reversed.end_at, None);
+        let start_at = reversed.start_at.unwrap();
+        assert_eq!(start_at.values, vec![int(5)]);
+        assert!(!start_at.before);
+    }
+
+    #[test]
+    fn collection_groups_query_all_descendants() {
+        let query = Query::collection_group("saves").parent("users/alice");
+        let request = query.request("my-project");
+
+        assert_eq!(
+            request.parent,
+            "projects/my-project/databases/(default)/documents/users/alice"
+        );
+        assert!(query.structured_query().from[0].all_descendants);
+        assert_eq!(
+            Query::collection("saves").parent_resource("my-project"),
+            "projects/my-project/databases/(default)/documents"
+        );
+    }
+}
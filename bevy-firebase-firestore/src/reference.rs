@@ -0,0 +1,469 @@
+use std::{fmt, str::FromStr};
+
+use crate::{auto_id, query, value::ValueType, Status, Value};
+
+// Limits from https://firebase.google.com/docs/firestore/quotas
+const MAX_ID_BYTES: usize = 1500;
+const MAX_PATH_BYTES: usize = 6144;
+const MAX_SEGMENTS: usize = 200;
+
+/// A validated path to a Firestore document, e.g. `users/alice/saves/slot-1`
+///
+/// Async functions, `WriteBatch` and `Transaction` take it as is. Events take
+/// it in their `new` constructors, e.g. `ReadDocumentEvent::new(&save, 1)`.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy_firebase_firestore::*;
+/// let users = CollectionRef::new("users").unwrap();
+/// let save = users.doc("alice").unwrap().collection("saves").unwrap().doc("slot-1").unwrap();
+///
+/// assert_eq!(save.path(), "users/alice/saves/slot-1");
+/// assert_eq!(save.parent().parent().unwrap().id(), "alice");
+///
+/// assert!(DocumentRef::new("users").is_err());
+/// assert!(DocumentRef::new("users/__alice__").is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DocumentRef(String);
+
+/// A validated path to a Firestore collection, e.g. `users/alice/saves`
+///
+/// Async functions take it as is wherever they take a collection path, as does
+/// `RunQueryEvent::new`. `query` turns it into a `query::Query`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CollectionRef(String);
+
+impl DocumentRef {
+    /// Validates a path relative to the database root
+    pub fn new(path: impl Into<String>) -> Result<Self, InvalidPath> {
+        let path = path.into();
+        validate(&path, 0)?;
+        Ok(DocumentRef(path))
+    }
+
+    /// Parses a full resource name, e.g.
+    /// `projects/{project_id}/databases/(default)/documents/users/alice`
+    pub fn from_name(name: &str) -> Result<Self, InvalidPath> {
+        DocumentRef::new(relative_path(name)?)
+    }
+
+    /// Reads a `ReferenceValue`
+    pub fn from_reference_value(value: &Value) -> Result<Self, InvalidPath> {
+        match &value.value_type {
+            Some(ValueType::ReferenceValue(name)) => DocumentRef::from_name(name),
+            _ => Err(InvalidPath {
+                path: String::new(),
+                reason: "not a reference value",
+            }),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.0.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// The path relative to the database root
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+
+    /// The collection this document is in
+    pub fn parent(&self) -> CollectionRef {
+        let (parent, _) = self.0.rsplit_once('/').unwrap_or_default();
+        CollectionRef(parent.into())
+    }
+
+    /// A subcollection. `path` may have several segments, as in the SDKs
+    pub fn collection(&self, path: &str) -> Result<CollectionRef, InvalidPath> {
+        CollectionRef::new(format!("{}/{path}", self.0))
+    }
+
+    /// The full resource name in `project_id`
+    pub fn name(&self, project_id: &str) -> String {
+        format!(
+            "projects/{project_id}/databases/(default)/documents/{}",
+            self.0
+        )
+    }
+
+    /// A `ReferenceValue` pointing at this document, to store in a field
+    pub fn reference_value(&self, project_id: &str) -> Value {
+        Value {
+            value_type: Some(ValueType::ReferenceValue(self.name(project_id))),
+        }
+    }
+}
+
+impl CollectionRef {
+    /// Validates a path relative to the database root
+    pub fn new(path: impl Into<String>) -> Result<Self, InvalidPath> {
+        let path = path.into();
+        validate(&path, 1)?;
+        Ok(CollectionRef(path))
+    }
+
+    /// Parses a full resource name, e.g.
+    /// `projects/{project_id}/databases/(default)/documents/users`
+    pub fn from_name(name: &str) -> Result<Self, InvalidPath> {
+        CollectionRef::new(relative_path(name)?)
+    }
+
+    pub fn id(&self) -> &str {
+        self.0.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// The path relative to the database root
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+
+    /// Path of the document this collection is under. Empty for a top-level
+    /// collection, as `CreateDocumentEvent::parent_path` expects
+    pub fn parent_path(&self) -> &str {
+        self.0.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+
+    /// The document this collection is under. `None` for a top-level
+    /// collection
+    pub fn parent(&self) -> Option<DocumentRef> {
+        self.0
+            .rsplit_once('/')
+            .map(|(parent, _)| DocumentRef(parent.into()))
+    }
+
+    /// A document. `path` may have several segments, as in the SDKs
+    pub fn doc(&self, path: &str) -> Result<DocumentRef, InvalidPath> {
+        DocumentRef::new(format!("{}/{path}", self.0))
+    }
+
+    /// A document with an `auto_id`, for creating a new one
+    pub fn auto_doc(&self) -> DocumentRef {
+        DocumentRef(format!("{}/{}", self.0, auto_id()))
+    }
+
+    /// The full resource name in `project_id`
+    pub fn name(&self, project_id: &str) -> String {
+        format!(
+            "projects/{project_id}/databases/(default)/documents/{}",
+            self.0
+        )
+    }
+
+    /// A query over every document in this collection
+    pub fn query(&self) -> query::Query {
+        query::Query::collection(self.id()).parent(self.parent_path())
+    }
+}
+
+// Checks each segment, and that there's an even number of them for a
+// document or an odd number for a collection
+fn validate(path: &str, parity: usize) -> Result<(), InvalidPath> {
+    let invalid = |reason| {
+        Err(InvalidPath {
+            path: path.into(),
+            reason,
+        })
+    };
+
+    if path.len() > MAX_PATH_BYTES {
+        return invalid("paths are limited to 6 KiB");
+    }
+
+    let mut segments = 0;
+
+    for segment in path.split('/') {
+        segments += 1;
+
+        if segment.is_empty() {
+            return invalid("empty segment");
+        }
+        if segment == "." || segment == ".." {
+            return invalid("`.` and `..` aren't valid IDs");
+        }
+        if segment.len() >= 4 && segment.starts_with("__") && segment.ends_with("__") {
+            return invalid("IDs matching `__.*__` are reserved");
+        }
+        if segment.len() > MAX_ID_BYTES {
+            return invalid("IDs are limited to 1500 bytes");
+        }
+    }
+
+    if segments > MAX_SEGMENTS {
+        return invalid("subcollections are limited to 100 deep");
+    }
+
+    match (segments % 2, parity) {
+        (0, 0) | (1, 1) => Ok(()),
+        (_, 0) => invalid("document paths need an even number of segments"),
+        _ => invalid("collection paths need an odd number of segments"),
+    }
+}
+
+// Strips `projects/{project_id}/databases/{database}/documents/` from a name
+fn relative_path(name: &str) -> Result<&str, InvalidPath> {
+    let mut parts = name.splitn(6, '/');
+
+    match (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) {
+        (Some("projects"), Some(_), Some("databases"), Some(_), Some("documents"), Some(path)) => {
+            Ok(path)
+        }
+        _ => Err(InvalidPath {
+            path: name.into(),
+            reason: "not a document or collection resource name",
+        }),
+    }
+}
+
+impl fmt::Display for DocumentRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for CollectionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for DocumentRef {
+    type Err = InvalidPath;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        DocumentRef::new(path)
+    }
+}
+
+impl FromStr for CollectionRef {
+    type Err = InvalidPath;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        CollectionRef::new(path)
+    }
+}
+
+impl AsRef<str> for DocumentRef {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for CollectionRef {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&DocumentRef> for DocumentRef {
+    fn from(document: &DocumentRef) -> Self {
+        document.clone()
+    }
+}
+
+impl From<&CollectionRef> for CollectionRef {
+    fn from(collection: &CollectionRef) -> Self {
+        collection.clone()
+    }
+}
+
+impl From<DocumentRef> for String {
+    fn from(document: DocumentRef) -> Self {
+        document.0
+    }
+}
+
+impl From<&DocumentRef> for String {
+    fn from(document: &DocumentRef) -> Self {
+        document.0.clone()
+    }
+}
+
+impl From<CollectionRef> for String {
+    fn from(collection: CollectionRef) -> Self {
+        collection.0
+    }
+}
+
+impl From<&CollectionRef> for String {
+    fn from(collection: &CollectionRef) -> Self {
+        collection.0.clone()
+    }
+}
+
+/// Why a path couldn't be used as a `DocumentRef` or `CollectionRef`
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidPath {
+    pub path: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for InvalidPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid path {:?}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidPath {}
+
+impl From<InvalidPath> for Status {
+    fn from(error: InvalidPath) -> Self {
+        Status::invalid_argument(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{CreateDocumentEvent, QueryDirection, RunQueryEvent, UpdateDocumentEvent};
+
+    fn reason<T: fmt::Debug>(result: Result<T, InvalidPath>) -> &'static str {
+        result.unwrap_err().reason
+    }
+
+    #[test]
+    fn paths_need_the_right_number_of_segments() {
+        assert!(DocumentRef::new("users/alice").is_ok());
+        assert!(DocumentRef::new("users/alice/saves/slot-1").is_ok());
+        assert!(CollectionRef::new("users").is_ok());
+        assert!(CollectionRef::new("users/alice/saves").is_ok());
+
+        assert_eq!(
+            reason(DocumentRef::new("users/alice/saves")),
+            "document paths need an even number of segments"
+        );
+        assert_eq!(
+            reason(CollectionRef::new("users/alice")),
+            "collection paths need an odd number of segments"
+        );
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        let cases = [
+            ("", "empty segment"),
+            ("users//alice", "empty segment"),
+            ("/users/alice", "empty segment"),
+            ("users/alice/", "empty segment"),
+            ("users/..", "`.` and `..` aren't valid IDs"),
+            ("users/.", "`.` and `..` aren't valid IDs"),
+            ("__users__/alice", "IDs matching `__.*__` are reserved"),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(reason(DocumentRef::new(path)), expected, "{path}");
+        }
+
+        // Too short to match `__.*__`
+        assert!(DocumentRef::new("users/___").is_ok());
+        assert!(DocumentRef::new("users/__alice").is_ok());
+    }
+
+    #[test]
+    fn paths_are_limited_in_size() {
+        let long_id = "a".repeat(MAX_ID_BYTES + 1);
+        assert_eq!(
+            reason(DocumentRef::new(format!("users/{long_id}"))),
+            "IDs are limited to 1500 bytes"
+        );
+
+        let long_path = vec!["a".repeat(1000); 8].join("/");
+        assert_eq!(
+            reason(DocumentRef::new(long_path)),
+            "paths are limited to 6 KiB"
+        );
+
+        let deep_path = vec!["a"; MAX_SEGMENTS + 2].join("/");
+        assert_eq!(
+            reason(DocumentRef::new(deep_path)),
+            "subcollections are limited to 100 deep"
+        );
+        assert!(DocumentRef::new(vec!["a"; MAX_SEGMENTS].join("/")).is_ok());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let save = DocumentRef::new("users/alice/saves/slot-1").unwrap();
+        let name = save.name("my-project");
+        assert_eq!(
+            name,
+            "projects/my-project/databases/(default)/documents/users/alice/saves/slot-1"
+        );
+        assert_eq!(DocumentRef::from_name(&name), Ok(save.clone()));
+        assert_eq!(
+            DocumentRef::from_reference_value(&save.reference_value("my-project")),
+            Ok(save.clone())
+        );
+
+        let saves = save.parent();
+        assert_eq!(
+            CollectionRef::from_name(&saves.name("my-project")),
+            Ok(saves)
+        );
+
+        assert_eq!(
+            reason(DocumentRef::from_name("users/alice")),
+            "not a document or collection resource name"
+        );
+        assert_eq!(
+            reason(DocumentRef::from_reference_value(&Value::default())),
+            "not a reference value"
+        );
+    }
+
+    #[test]
+    fn parents_and_children() {
+        let users = CollectionRef::new("users").unwrap();
+        assert_eq!(users.parent(), None);
+        assert_eq!(users.parent_path(), "");
+
+        let alice = users.doc("alice").unwrap();
+        assert_eq!(alice.parent(), users);
+
+        let saves = alice.collection("saves").unwrap();
+        assert_eq!(saves.parent(), Some(alice.clone()));
+        assert_eq!(saves.parent_path(), "users/alice");
+        assert_eq!(saves.id(), "saves");
+
+        assert!(alice.collection("saves/slot-1").is_err());
+        assert!(users.doc("alice/saves").is_err());
+
+        let new_save = saves.auto_doc();
+        assert_eq!(new_save.parent(), saves);
+        assert_eq!(new_save.id().len(), 20);
+    }
+
+    #[test]
+    fn events_take_validated_paths() {
+        let save = DocumentRef::new("users/alice/saves/slot-1").unwrap();
+
+        let create = CreateDocumentEvent::new(&save, HashMap::new(), 1);
+        assert_eq!(create.parent_path, "users/alice");
+        assert_eq!(create.collection_id, "saves");
+        assert_eq!(create.document_id, "slot-1");
+
+        let update = UpdateDocumentEvent::new(save, HashMap::new(), 2);
+        assert_eq!(update.document_path, "users/alice/saves/slot-1");
+        assert_eq!(update.precondition, None);
+
+        let saves = CollectionRef::new("users/alice/saves").unwrap();
+        let query = RunQueryEvent::new(
+            &saves,
+            Some(10),
+            ("score".into(), QueryDirection::Descending),
+            3,
+        );
+        assert_eq!(query.parent, "users/alice");
+        assert_eq!(query.collection_id, "saves");
+    }
+}
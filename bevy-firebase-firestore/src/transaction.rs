@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use futures_lite::future::Boxed;
+use tonic::Code;
+
+use crate::{
+    collect_query,
+    credential::with_refresh,
+    get_document_request, query, run_query_request,
+    transaction_options::{Mode, ReadWrite},
+    BeginTransactionRequest, BevyFirestoreClient, Client, CommitRequest, CommitResponse, Document,
+    FieldPath, FieldTransform, FieldValue, FirestoreCredential, FirestoreTasks, GetDocumentRequest,
+    InvalidFieldPath, QueryResponse, RollbackRequest, Status, TransactionOptions, Value, Write,
+    WriteBatch, WritePrecondition,
+};
+
+/// Bevy `Resource` with the retry settings for transactions sent as events
+///
+/// Transactions are retried when Firestore aborts them because of contention,
+/// waiting `initial_delay` and doubling each time, up to `max_delay`.
+///
+/// A transaction sent as an event that fails as `UNAUTHENTICATED` is run once
+/// more after the credential is refreshed, with `max_attempts` starting over.
+/// The closure can then run up to twice `max_attempts` times.
+#[derive(Resource, Clone, Debug)]
+pub struct TransactionSettings {
+    /// Attempts before giving up, including the first
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for TransactionSettings {
+    fn default() -> Self {
+        TransactionSettings {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A Firestore transaction, passed to the closure given to `run_transaction`
+///
+/// Reads go through the transaction, so Firestore aborts it if a document
+/// read changes before the commit. Writes are buffered and sent with the
+/// commit.
+#[derive(Clone)]
+pub struct Transaction {
+    client: Client,
+    project_id: String,
+    id: Vec<u8>,
+    batch: Arc<Mutex<WriteBatch>>,
+}
+
+impl Transaction {
+    pub fn id(&self) -> &[u8] {
+        &self.id
+    }
+
+    /// Reads a document. `None` if it doesn't exist
+    pub async fn get(&self, document_path: impl AsRef<str>) -> Result<Option<Document>, Status> {
+        let project_id = &self.project_id;
+        let document_path = document_path.as_ref();
+
+        let response = self
+            .client
+            .clone()
+            .get_document(GetDocumentRequest {
+                name: format!(
+                    "projects/{project_id}/databases/(default)/documents/{document_path}"
+                ),
+                consistency_selector: Some(get_document_request::ConsistencySelector::Transaction(
+                    self.id.clone(),
+                )),
+                ..Default::default()
+            })
+            .await;
+
+        match response {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(status) if status.code() == Code::NotFound => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+
+    /// Runs a query
+    pub async fn run_query(&self, query: &query::Query) -> QueryResponse {
+        let mut request = query.request(&self.project_id);
+        request.consistency_selector = Some(run_query_request::ConsistencySelector::Transaction(
+            self.id.clone(),
+        ));
+
+        collect_query(&mut self.client.clone(), request).await
+    }
+
+    /// Replaces a document, creating it if needed
+    pub fn set(&self, document_path: impl AsRef<str>, fields: HashMap<String, Value>) {
+        self.batch.lock().unwrap().set(document_path, fields);
+    }
+
+    /// Merges `fields` into a document, creating it if needed
+    pub fn set_merge(&self, document_path: impl AsRef<str>, fields: HashMap<String, Value>) {
+        self.batch.lock().unwrap().set_merge(document_path, fields);
+    }
+
+    /// Updates the given fields of a document, leaving the rest
+    pub fn update(&self, document_path: impl AsRef<str>, fields: HashMap<String, Value>) {
+        self.batch.lock().unwrap().update(document_path, fields);
+    }
+
+    /// Updates the given fields of a document, if it meets `precondition`
+    pub fn update_if(
+        &self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+        precondition: WritePrecondition,
+    ) {
+        self.batch
+            .lock()
+            .unwrap()
+            .update_if(document_path, fields, precondition);
+    }
+
+    /// Writes or deletes each field path given, leaving the rest of the
+    /// document
+    ///
+    /// Errors, buffering nothing, if a path is given twice or inside another.
+    /// The error converts into a `Status` with `?`.
+    pub fn update_fields(
+        &self,
+        document_path: impl AsRef<str>,
+        fields: Vec<(FieldPath, FieldValue)>,
+    ) -> Result<(), InvalidFieldPath> {
+        self.batch
+            .lock()
+            .unwrap()
+            .update_fields(document_path, fields)?;
+        Ok(())
+    }
+
+    /// Deletes a document
+    pub fn delete(&self, document_path: impl AsRef<str>) {
+        self.batch.lock().unwrap().delete(document_path);
+    }
+
+    /// Deletes a document, if it meets `precondition`
+    pub fn delete_if(&self, document_path: impl AsRef<str>, precondition: WritePrecondition) {
+        self.batch
+            .lock()
+            .unwrap()
+            .delete_if(document_path, precondition);
+    }
+
+    /// Updates the given fields of a document, then applies `transforms`
+    pub fn update_with_transforms(
+        &self,
+        document_path: impl AsRef<str>,
+        fields: HashMap<String, Value>,
+        transforms: Vec<FieldTransform>,
+    ) {
+        self.batch
+            .lock()
+            .unwrap()
+            .update_with_transforms(document_path, fields, transforms);
+    }
+
+    /// Applies server-side transforms to a document's fields, creating it if
+    /// needed
+    pub fn transform(&self, document_path: impl AsRef<str>, transforms: Vec<FieldTransform>) {
+        self.batch
+            .lock()
+            .unwrap()
+            .transform(document_path, transforms);
+    }
+
+    /// Buffers any write, to be sent with the commit. Document names in it
+    /// are paths, as in a `WriteBatch`
+    pub fn write(&self, write: Write) {
+        self.batch.lock().unwrap().write(write);
+    }
+}
+
+/// Runs `transaction` in a Firestore transaction, with the default
+/// `TransactionSettings`
+///
+/// The closure may run several times, so it shouldn't have side effects
+/// beyond the transaction. If it returns an error, the transaction is rolled
+/// back and the error returned. Otherwise its writes are committed, and the
+/// whole transaction retried if Firestore aborts it.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy_firebase_firestore::*;
+/// # use bevy_firebase_firestore::value::ValueType;
+/// async fn add_click(client: &mut Client, project_id: &str) -> Result<i64, Status> {
+///     let (score, _) = run_transaction(client, project_id, |tx| async move {
+///         let document = tx.get("click/player").await?;
+///
+///         let score = match document.and_then(|document| document.fields.get("score").cloned()) {
+///             Some(Value {
+///                 value_type: Some(ValueType::IntegerValue(score)),
+///             }) => score + 1,
+///             _ => 1,
+///         };
+///
+///         tx.update(
+///             "click/player",
+///             HashMap::from([(
+///                 "score".to_string(),
+///                 Value {
+///                     value_type: Some(ValueType::IntegerValue(score)),
+///                 },
+///             )]),
+///         );
+///
+///         Ok(score)
+///     })
+///     .await?;
+///
+///     Ok(score)
+/// }
+/// ```
+pub async fn run_transaction<T, F, Fut>(
+    client: &mut Client,
+    project_id: &str,
+    transaction: F,
+) -> Result<(T, CommitResponse), Status>
+where
+    F: FnMut(Transaction) -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    run_transaction_with_settings(
+        client,
+        project_id,
+        &TransactionSettings::default(),
+        transaction,
+    )
+    .await
+}
+
+/// Runs `transaction` in a Firestore transaction, retrying as set in
+/// `settings`
+pub async fn run_transaction_with_settings<T, F, Fut>(
+    client: &mut Client,
+    project_id: &str,
+    settings: &TransactionSettings,
+    mut transaction: F,
+) -> Result<(T, CommitResponse), Status>
+where
+    F: FnMut(Transaction) -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    let database = format!("projects/{project_id}/databases/(default)");
+
+    let mut retry_transaction = Vec::new();
+    let mut delay = settings.initial_delay;
+    let mut attempt = 1;
+
+    loop {
+        let id = client
+            .begin_transaction(BeginTransactionRequest {
+                database: database.clone(),
+                options: Some(TransactionOptions {
+                    mode: Some(Mode::ReadWrite(ReadWrite { retry_transaction })),
+                }),
+            })
+            .await?
+            .into_inner()
+            .transaction;
+
+        let tx = Transaction {
+            client: client.clone(),
+            project_id: project_id.into(),
+            id: id.clone(),
+            batch: Default::default(),
+        };
+
+        let status = match transaction(tx.clone()).await {
+            Ok(value) => {
+                let writes = tx.batch.lock().unwrap().writes(project_id);
+
+                // A failed commit ends the transaction, so there's nothing to roll back
+                match client
+                    .commit(CommitRequest {
+                        database: database.clone(),
+                        writes,
+                        transaction: id.clone(),
+                    })
+                    .await
+                {
+                    Ok(response) => return Ok((value, response.into_inner())),
+                    Err(status) => status,
+                }
+            }
+            Err(status) => {
+                if let Err(rollback_status) = client
+                    .rollback(RollbackRequest {
+                        database: database.clone(),
+                        transaction: id.clone(),
+                    })
+                    .await
+                {
+                    warn!(
+                        target: "bevy_firebase_firestore::transaction",
+                        "Rollback failed: {}", rollback_status
+                    );
+                }
+                status
+            }
+        };
+
+        if status.code() != Code::Aborted || attempt >= settings.max_attempts {
+            return Err(status);
+        }
+
+        info!(
+            target: "bevy_firebase_firestore::transaction",
+            "Transaction aborted, retrying in {:?} (attempt {}/{})",
+            delay,
+            attempt + 1,
+            settings.max_attempts
+        );
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(settings.max_delay);
+        attempt += 1;
+        retry_transaction = id;
+    }
+}
+
+/// A transaction closure that can be sent in a `RunTransactionEvent`
+pub type TransactionFn = Arc<dyn Fn(Transaction) -> Boxed<Result<(), Status>> + Send + Sync>;
+
+pub type TransactionResponse = Result<CommitResponse, Status>;
+
+/// An event holding a transaction to run
+///
+/// The result arrives as a `TransactionResponseEvent` with the same `id`. If
+/// the ID token expired, the transaction is run again after refreshing it, as
+/// described in `TransactionSettings`.
+///
+/// # Examples
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// fn reset_score(mut ew: EventWriter<RunTransactionEvent>) {
+///     ew.send(RunTransactionEvent::new(1, |tx| async move {
+///         if tx.get("click/player").await?.is_some() {
+///             tx.delete("click/player");
+///         }
+///         Ok(())
+///     }));
+/// }
+/// ```
+#[derive(Clone, Event)]
+pub struct RunTransactionEvent {
+    pub transaction: TransactionFn,
+    pub id: usize,
+}
+
+impl RunTransactionEvent {
+    pub fn new<F, Fut>(id: usize, transaction: F) -> Self
+    where
+        F: Fn(Transaction) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Status>> + Send + 'static,
+    {
+        RunTransactionEvent {
+            transaction: Arc::new(move |tx| Box::pin(transaction(tx))),
+            id,
+        }
+    }
+}
+
+/// Event that contains a `TransactionResponse`
+#[derive(Event)]
+pub struct TransactionResponseEvent {
+    pub transaction_response: TransactionResponse,
+    pub id: usize,
+}
+
+pub(crate) fn run_transaction_event_handler(
+    mut er: EventReader<RunTransactionEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+    settings: Res<TransactionSettings>,
+) {
+    for e in er.iter() {
+        let transaction = e.transaction.clone();
+        let id = e.id;
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+        let settings = settings.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let response = with_refresh!(
+                credential,
+                run_transaction_with_settings(&mut client, &project_id, &settings, |tx| {
+                    transaction(tx)
+                })
+            );
+
+            let transaction_response = response.map(|((), commit)| commit);
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.send_event(TransactionResponseEvent {
+                    transaction_response,
+                    id,
+                });
+            })
+            .await;
+        }));
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_firebase_auth::ProjectId;
+use bevy_tokio_tasks::TokioTasksRuntime;
+
+use crate::{
+    async_commit_batch,
+    credential::with_refresh,
+    document_transform::field_transform::{ServerValue, TransformType},
+    ArrayValue, BevyFirestoreClient, Client, DocumentRef, FieldPath, FieldTransform,
+    FirestoreCredential, FirestoreTasks, Status, Value, WriteBatch, WriteResult,
+};
+
+/// Server-side transforms, applied by Firestore after a write's field updates
+///
+/// Each transform's result is returned in `WriteResult::transform_results`,
+/// in the order the transforms were given. Fields are named by `FieldPath`,
+/// and a plain string is one top-level field.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy_firebase_firestore::*;
+/// # use bevy_firebase_firestore::value::ValueType;
+/// let mut batch = WriteBatch::new();
+/// batch.transform(
+///     "click/player",
+///     vec![
+///         FieldTransform::maximum("best", Value {
+///             value_type: Some(ValueType::IntegerValue(42)),
+///         }),
+///         FieldTransform::server_timestamp("last_played"),
+///         FieldTransform::increment(
+///             FieldPath::new(["stats", "rounds"]),
+///             Value {
+///                 value_type: Some(ValueType::IntegerValue(1)),
+///             },
+///         ),
+///     ],
+/// );
+/// ```
+impl FieldTransform {
+    /// Adds `value` to a numeric field. Missing fields are set to `value`
+    pub fn increment(field_path: impl Into<FieldPath>, value: Value) -> Self {
+        Self::new(field_path, TransformType::Increment(value))
+    }
+
+    /// Sets a numeric field to `value` if it's larger
+    pub fn maximum(field_path: impl Into<FieldPath>, value: Value) -> Self {
+        Self::new(field_path, TransformType::Maximum(value))
+    }
+
+    /// Sets a numeric field to `value` if it's smaller
+    pub fn minimum(field_path: impl Into<FieldPath>, value: Value) -> Self {
+        Self::new(field_path, TransformType::Minimum(value))
+    }
+
+    /// Sets a field to the time the server processed the request
+    pub fn server_timestamp(field_path: impl Into<FieldPath>) -> Self {
+        Self::new(
+            field_path,
+            TransformType::SetToServerValue(ServerValue::RequestTime as i32),
+        )
+    }
+
+    /// Appends the `values` not already in an array field, like a set union
+    pub fn append_missing_elements(field_path: impl Into<FieldPath>, values: Vec<Value>) -> Self {
+        Self::new(
+            field_path,
+            TransformType::AppendMissingElements(ArrayValue { values }),
+        )
+    }
+
+    /// Removes every element equal to one of `values` from an array field
+    pub fn remove_all_from_array(field_path: impl Into<FieldPath>, values: Vec<Value>) -> Self {
+        Self::new(
+            field_path,
+            TransformType::RemoveAllFromArray(ArrayValue { values }),
+        )
+    }
+
+    fn new(field_path: impl Into<FieldPath>, transform_type: TransformType) -> Self {
+        FieldTransform {
+            field_path: field_path.into().to_string(),
+            transform_type: Some(transform_type),
+        }
+    }
+}
+
+pub type WriteResultResponse = Result<WriteResult, Status>;
+
+/// Updates the given fields of a document, then applies `transforms`
+///
+/// The transform results are in the returned `WriteResult`.
+pub async fn async_update_document_with_transforms(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+    fields: HashMap<String, Value>,
+    transforms: Vec<FieldTransform>,
+) -> WriteResultResponse {
+    let mut batch = WriteBatch::new();
+    batch.update_with_transforms(document_path, fields, transforms);
+
+    let response = async_commit_batch(client, project_id, &batch).await?;
+
+    Ok(response
+        .write_results
+        .into_iter()
+        .next()
+        .unwrap_or_default())
+}
+
+/// An event holding field updates and transforms for one document
+///
+/// The result arrives as a `TransformDocumentResponseEvent` with the same
+/// `id`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # use bevy_firebase_firestore::value::ValueType;
+/// fn add_clicks(mut ew: EventWriter<TransformDocumentEvent>) {
+///     ew.send(TransformDocumentEvent {
+///         document_path: "click/player".into(),
+///         document_data: HashMap::new(),
+///         transforms: vec![FieldTransform::increment(
+///             "score",
+///             Value {
+///                 value_type: Some(ValueType::IntegerValue(10)),
+///             },
+///         )],
+///         id: 1,
+///     });
+/// }
+/// ```
+#[derive(Clone, Event)]
+pub struct TransformDocumentEvent {
+    pub document_path: String,
+    pub document_data: HashMap<String, Value>,
+    pub transforms: Vec<FieldTransform>,
+    pub id: usize,
+}
+
+impl TransformDocumentEvent {
+    pub fn new(
+        document: impl Into<DocumentRef>,
+        document_data: HashMap<String, Value>,
+        transforms: Vec<FieldTransform>,
+        id: usize,
+    ) -> Self {
+        TransformDocumentEvent {
+            document_path: document.into().into(),
+            document_data,
+            transforms,
+            id,
+        }
+    }
+}
+
+/// Event that contains a `WriteResultResponse`
+#[derive(Event)]
+pub struct TransformDocumentResponseEvent {
+    pub result: WriteResultResponse,
+    pub id: usize,
+}
+
+pub(crate) fn transform_document_event_handler(
+    mut er: EventReader<TransformDocumentEvent>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Res<BevyFirestoreClient>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+) {
+    for e in er.iter() {
+        let e = e.clone();
+
+        let mut client = client.0.clone();
+        let credential = credential.clone();
+        let project_id = project_id.0.clone();
+
+        tasks.track(runtime.spawn_background_task(move |mut ctx| async move {
+            let result = with_refresh!(
+                credential,
+                async_update_document_with_transforms(
+                    &mut client,
+                    &project_id,
+                    &e.document_path,
+                    e.document_data.clone(),
+                    e.transforms.clone(),
+                )
+            );
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world
+                    .send_event(TransformDocumentResponseEvent { result, id: e.id });
+            })
+            .await;
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueType;
+
+    fn int(value: i64) -> Value {
+        Value {
+            value_type: Some(ValueType::IntegerValue(value)),
+        }
+    }
+
+    #[test]
+    fn field_paths_are_escaped() {
+        let nested = FieldTransform::increment(FieldPath::new(["stats", "best.time"]), int(1));
+        assert_eq!(nested.field_path, "stats.`best.time`");
+
+        let parsed =
+            FieldTransform::maximum("stats.`high score`".parse::<FieldPath>().unwrap(), int(2));
+        assert_eq!(parsed.field_path, "stats.`high score`");
+
+        // A plain string is a single field, even with a dot in it
+        let dotted = FieldTransform::server_timestamp("last.played");
+        assert_eq!(dotted.field_path, "`last.played`");
+
+        let simple = FieldTransform::remove_all_from_array(String::from("tags"), vec![int(3)]);
+        assert_eq!(simple.field_path, "tags");
+        assert_eq!(
+            simple.transform_type,
+            Some(TransformType::RemoveAllFromArray(ArrayValue {
+                values: vec![int(3)]
+            }))
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_firebase_auth::{DeleteAccount, ProjectId, TokenData};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use tonic::{Code, Status};
+
+use crate::{
+    credential::with_refresh, BevyFirestoreClient, Client, DeleteDocumentRequest, Document,
+    FirestoreCredential, FirestoreTasks, GetDocumentRequest, InvalidPath, ListCollectionIdsRequest,
+    ListDocumentsRequest,
+};
+
+/// Bevy `Resource` holding the path templates of documents and collections
+/// owned by a user
+///
+/// `{uid}` is replaced with the user's `TokenData.local_id`, and must be a
+/// whole segment of every template. Everything below each path is included,
+/// so `saves/{uid}` and `saves/{uid}/**` are the same. Set with
+/// `FirestorePlugin::user_data_paths`.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct UserDataPaths(pub Vec<String>);
+
+impl UserDataPaths {
+    /// Checks every template has `{uid}` as one of its segments
+    ///
+    /// Without it a path is shared by all users, and deleting one account
+    /// would delete everyone's data.
+    pub fn validate(&self) -> Result<(), InvalidPath> {
+        for template in self.0.iter() {
+            if !template.split('/').any(|segment| segment == "{uid}") {
+                return Err(InvalidPath {
+                    path: template.clone(),
+                    reason: "user data paths need `{uid}` as a whole segment",
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The paths for one user, relative to the database root
+    ///
+    /// Fails for templates that don't `validate`, an empty uid or one
+    /// containing `/`, and for any path that resolves to fewer segments than
+    /// its template, as those would point at other users' data.
+    pub fn for_user(&self, uid: &str) -> Result<Vec<String>, InvalidPath> {
+        self.validate()?;
+
+        if uid.is_empty() || uid.contains('/') {
+            return Err(InvalidPath {
+                path: uid.into(),
+                reason: "uids can't be empty or contain `/`",
+            });
+        }
+
+        self.0
+            .iter()
+            .map(|template| {
+                let template = template.trim_end_matches("/**").trim_matches('/');
+                let path = template.replace("{uid}", uid);
+
+                let segments = |path: &str| path.split('/').filter(|s| !s.is_empty()).count();
+                if path.is_empty() || segments(&path) < template.split('/').count() {
+                    return Err(InvalidPath {
+                        path,
+                        reason: "resolves to fewer segments than its template",
+                    });
+                }
+
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+fn documents_root(project_id: &str) -> String {
+    format!("projects/{project_id}/databases/(default)/documents")
+}
+
+/// Lists the IDs of a document's subcollections
+pub async fn async_list_collection_ids(
+    client: &mut Client,
+    project_id: &str,
+    document_path: impl AsRef<str>,
+) -> Result<Vec<String>, Status> {
+    let document_path = document_path.as_ref();
+    let mut collection_ids = vec![];
+    let mut page_token = String::new();
+
+    loop {
+        let response = client
+            .list_collection_ids(ListCollectionIdsRequest {
+                parent: format!("{}/{document_path}", documents_root(project_id)),
+                page_token,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        collection_ids.extend(response.collection_ids);
+
+        if response.next_page_token.is_empty() {
+            return Ok(collection_ids);
+        }
+        page_token = response.next_page_token;
+    }
+}
+
+/// Lists every document in a collection
+///
+/// With `show_missing`, documents that don't exist but have subcollections
+/// are included too. They have no `create_time`.
+pub async fn async_list_documents(
+    client: &mut Client,
+    project_id: &str,
+    collection_path: impl AsRef<str>,
+    show_missing: bool,
+) -> Result<Vec<Document>, Status> {
+    let collection_path = collection_path.as_ref();
+    let (parent, collection_id) = match collection_path.rsplit_once('/') {
+        Some((parent, collection_id)) => (
+            format!("{}/{parent}", documents_root(project_id)),
+            collection_id,
+        ),
+        None => (documents_root(project_id), collection_path),
+    };
+
+    let mut documents = vec![];
+    let mut page_token = String::new();
+
+    loop {
+        let response = client
+            .list_documents(ListDocumentsRequest {
+                parent: parent.clone(),
+                collection_id: collection_id.into(),
+                page_token,
+                show_missing,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        documents.extend(response.documents);
+
+        if response.next_page_token.is_empty() {
+            return Ok(documents);
+        }
+        page_token = response.next_page_token;
+    }
+}
+
+/// Collects a document or collection and everything below it
+///
+/// Parents come before their subcollections' documents. Documents that
+/// don't exist but have subcollections are included, with no `create_time`.
+pub async fn async_collect_documents(
+    client: &mut Client,
+    project_id: &str,
+    path: impl AsRef<str>,
+) -> Result<Vec<Document>, Status> {
+    let path = path.as_ref();
+    let root = documents_root(project_id);
+    let mut queue = VecDeque::new();
+
+    // Collections have an odd number of segments, documents an even number
+    if path.split('/').count() % 2 == 1 {
+        queue.extend(async_list_documents(client, project_id, path, true).await?);
+    } else {
+        let name = format!("{root}/{path}");
+        let document = match client
+            .get_document(GetDocumentRequest {
+                name: name.clone(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(status) if status.code() == Code::NotFound => Document {
+                name,
+                ..Default::default()
+            },
+            Err(status) => return Err(status),
+        };
+        queue.push_back(document);
+    }
+
+    let mut documents = vec![];
+
+    while let Some(document) = queue.pop_front() {
+        let document_path = document
+            .name
+            .trim_start_matches(&root)
+            .trim_start_matches('/')
+            .to_string();
+
+        for collection_id in async_list_collection_ids(client, project_id, &document_path).await? {
+            let collection_path = format!("{document_path}/{collection_id}");
+            queue.extend(async_list_documents(client, project_id, &collection_path, true).await?);
+        }
+
+        documents.push(document);
+    }
+
+    Ok(documents)
+}
+
+/// Event sent as user data is deleted by `delete_account_and_data`
+#[derive(Event, Clone, Debug)]
+pub struct UserDataDeleteProgress {
+    pub deleted: usize,
+    pub total: usize,
+}
+
+/// Event sent once all user data is deleted, before the account itself
+#[derive(Event, Clone, Debug)]
+pub struct UserDataDeleted {
+    pub deleted: usize,
+}
+
+/// Event sent when user data couldn't be deleted. The account is kept, so
+/// the deletion can be tried again
+#[derive(Event, Clone, Debug)]
+pub struct UserDataDeleteFailed {
+    /// Path of the document or collection that failed
+    pub path: String,
+    pub status: Status,
+}
+
+/// Function to delete a user's data, then their account
+///
+/// Deletes everything under each of the `UserDataPaths`, deepest documents
+/// first, then sends `DeleteAccount`. Progress is reported with
+/// `UserDataDeleteProgress`, and nothing more is deleted after a
+/// `UserDataDeleteFailed`.
+///
+/// # Examples
+///
+/// Used in place of `delete_account`:
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firebase_firestore::*;
+/// # let mut app = App::new();
+/// #[derive(Default, States, Debug, Clone, Eq, PartialEq, Hash)]
+/// enum AppAuthState {
+///     #[default]
+///     LogIn,
+///     LogOut,
+///     Delete
+/// };
+/// app.add_systems(OnEnter(AppAuthState::Delete), delete_account_and_data);
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub fn delete_account_and_data(
+    runtime: ResMut<TokioTasksRuntime>,
+    mut tasks: ResMut<FirestoreTasks>,
+    client: Option<Res<BevyFirestoreClient>>,
+    credential: Res<FirestoreCredential>,
+    project_id: Res<ProjectId>,
+    token_data: Res<TokenData>,
+    user_data_paths: Res<UserDataPaths>,
+    mut ew: EventWriter<UserDataDeleteFailed>,
+) {
+    let paths = match user_data_paths.for_user(&token_data.local_id) {
+        Ok(paths) => paths,
+        Err(err) => {
+            ew.send(UserDataDeleteFailed {
+                path: err.path.clone(),
+                status: Status::invalid_argument(err.to_string()),
+            });
+            return;
+        }
+    };
+
+    let Some(client) = client else {
+        ew.send(UserDataDeleteFailed {
+            path: paths.first().cloned().unwrap_or_default(),
+            status: Status::unavailable("Firestore client not ready"),
+        });
+        return;
+    };
+
+    let mut client = client.0.clone();
+    let credential = credential.clone();
+    let project_id = project_id.0.clone();
+
+    tasks.track(runtime.spawn_background_task(|mut ctx| async move {
+        let mut documents = vec![];
+
+        for path in paths.iter() {
+            let found = with_refresh!(
+                credential,
+                async_collect_documents(&mut client, &project_id, path)
+            );
+
+            match found {
+                Ok(found) => documents.extend(found),
+                Err(status) => {
+                    let path = path.clone();
+                    ctx.run_on_main_thread(move |ctx| {
+                        ctx.world.send_event(UserDataDeleteFailed { path, status });
+                    })
+                    .await;
+                    return;
+                }
+            }
+        }
+
+        let total = documents.len();
+
+        let root = documents_root(&project_id);
+
+        // Children were collected after their parents
+        for (deleted, document) in documents.into_iter().rev().enumerate() {
+            let result = with_refresh!(
+                credential,
+                client.delete_document(DeleteDocumentRequest {
+                    name: document.name.clone(),
+                    ..Default::default()
+                })
+            );
+
+            if let Err(status) = result {
+                let path = document
+                    .name
+                    .trim_start_matches(&root)
+                    .trim_start_matches('/')
+                    .to_string();
+
+                ctx.run_on_main_thread(move |ctx| {
+                    ctx.world.send_event(UserDataDeleteFailed { path, status });
+                })
+                .await;
+                return;
+            }
+
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.send_event(UserDataDeleteProgress {
+                    deleted: deleted + 1,
+                    total,
+                });
+            })
+            .await;
+        }
+
+        ctx.run_on_main_thread(move |ctx| {
+            ctx.world.send_event(UserDataDeleted { deleted: total });
+            ctx.world.send_event(DeleteAccount);
+        })
+        .await;
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(templates: &[&str]) -> UserDataPaths {
+        UserDataPaths(templates.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn for_user_fills_in_the_uid() {
+        let paths = paths(&["click/{uid}", "saves/{uid}/**", "/users/{uid}/inbox/"]);
+
+        assert_eq!(
+            paths.for_user("alice").unwrap(),
+            ["click/alice", "saves/alice", "users/alice/inbox"]
+        );
+    }
+
+    #[test]
+    fn for_user_rejects_bad_uids() {
+        let paths = paths(&["saves/{uid}"]);
+
+        assert!(paths.for_user("").is_err());
+        assert!(paths.for_user("alice/bob").is_err());
+        assert!(paths.for_user("/").is_err());
+    }
+
+    #[test]
+    fn for_user_rejects_paths_shorter_than_their_template() {
+        // Empty segments would leave the uid's documents for a parent
+        assert!(paths(&["saves//{uid}"]).for_user("alice").is_err());
+    }
+
+    #[test]
+    fn templates_need_a_uid_segment() {
+        assert!(paths(&["saves/{uid}", "users/{uid}/inbox/**"])
+            .validate()
+            .is_ok());
+
+        for template in [
+            "",
+            "/**",
+            "saves",
+            "saves/all/**",
+            "saves/user-{uid}",
+            "{uid}s",
+        ] {
+            let err = paths(&["click/{uid}", template]).validate().unwrap_err();
+            assert_eq!(err.path, template);
+            assert!(paths(&[template]).for_user("alice").is_err());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "user data paths need `{uid}` as a whole segment")]
+    fn plugin_rejects_templates_without_a_uid_segment() {
+        App::new().add_plugins(crate::FirestorePlugin {
+            user_data_paths: vec!["saves/{uid}".into(), "leaderboard".into()],
+            ..Default::default()
+        });
+    }
+}